@@ -46,7 +46,7 @@ pub use tokio::reactor::Handle;
 pub use tokio_codec::Framed;
 pub use tokio::net::TcpStream;
 pub use futures::Future;
-use http::header::HeaderMap;
+use http::header::{HeaderMap, SEC_WEBSOCKET_PROTOCOL};
 
 use result::WebSocketError;
 use codec::ws::MessageCodec;
@@ -74,3 +74,13 @@ pub type ClientNew<S: Send> = Box<
 	Future<Item = (Client<S>, HeaderMap), Error = WebSocketError>
 		+ Send,
 >;
+
+/// The subprotocol the connection speaks, if one was chosen during the handshake.
+///
+/// Pass it the headers a `ClientNew` resolves to (the server's response), or on
+/// the server side the headers the accepted connection was answered with.
+pub fn protocol(headers: &HeaderMap) -> Option<&str> {
+	headers.get(SEC_WEBSOCKET_PROTOCOL)
+	       .and_then(|p| p.to_str().ok())
+	       .and_then(|p| p.split(',').map(str::trim).find(|p| !p.is_empty()))
+}
@@ -1,10 +1,10 @@
 //! Everything you need to create a client connection to a websocket.
 
 use std::borrow::Cow;
-use std::io::BufRead;
+use std::convert::TryFrom;
 use std::str::FromStr;
 
-use bytes::{BufMut, BytesMut};
+use bytes::BytesMut;
 pub use url::{Url, ParseError};
 use http;
 use http::header::{AsHeaderName, HeaderMap, HeaderName, HeaderValue};
@@ -12,10 +12,8 @@ use http::header::{
 	CONNECTION, HOST, ORIGIN, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_EXTENSIONS,
 	SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE
 };
-use httparse;
 
-use codec::http::{MAX_HEADERS, HeaderIndices, HeadersAsBytesIter, ResponseHead};
-use codec::http::record_header_indices;
+use codec::http::ResponseHead;
 use header::{WebSocketExtensions, WebSocketKey, WebSocketVersion};
 use header::connection::{Connection, ConnectionOption};
 use header::sec_websocket_extensions::Extension;
@@ -26,7 +24,6 @@ mod common_imports {
 	pub use std::net::TcpStream;
 	pub use std::net::ToSocketAddrs;
 
-	pub use std::io::BufReader;
 	pub use url::Position;
 	pub use codec::http::MessageHead;
 	pub use http::{Method, StatusCode, Version, Uri};
@@ -39,7 +36,14 @@ mod common_imports {
 use self::common_imports::*;
 
 #[cfg(feature = "sync")]
-use super::sync::Client;
+mod sync_imports {
+	pub use std::io::{self, Read, Write};
+	pub use tokio_codec::{Decoder, Encoder};
+	pub use codec::http::HttpClientCodec;
+	pub use super::super::sync::Client;
+}
+#[cfg(feature = "sync")]
+use self::sync_imports::*;
 
 #[cfg(feature = "sync-ssl")]
 use stream::sync::NetworkStream;
@@ -447,78 +451,46 @@ impl<'u> ClientBuilder<'u> {
 	where
 		S: Stream + Send,
 	{
+		let request = self.handshake_request()?;
+
 		// send request
-		let resource = self.build_request();
-		write!(stream, "GET {} {:?}\r\n", resource, self.version)?;
-		write!(stream, "{:?}\r\n", self.headers)?;
-
-		// wait for a response
-		let mut buf = String::new();
-		let mut reader = BufReader::new(stream);
-
-		loop {
-			reader.read_line(&mut buf).unwrap();
-			if &buf[buf.len() - 4..] == "\r\n\r\n" {
-				break;
+		let mut codec = HttpClientCodec;
+		let mut buf = BytesMut::new();
+		codec.encode(request, &mut buf)?;
+		stream.write_all(&buf)?;
+		stream.flush()?;
+
+		// wait for a response, never buffering more than the codec allows
+		buf.clear();
+		let mut chunk = [0u8; 1024];
+		let response = loop {
+			if let Some(response) = codec.decode(&mut buf)? {
+				break response;
 			}
-		}
-
-		//println!("Response: {}", buf);
-
-		let mut buf_bytes = BytesMut::from(buf);
-
-		let mut headers_indices = [HeaderIndices {
-			name: (0, 0),
-			value: (0, 0),
-		}; MAX_HEADERS];
-
-		let (len, status, version, headers_len) = {
-			let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
-			// println!(
-			// 	"Response.parse([Header; {}], [u8; {}])",
-			// 	headers.len(),
-			// 	buf_bytes.len()
-			// );
-			let mut res = httparse::Response::new(&mut headers);
-			let bytes = buf_bytes.as_ref();
-			match try!(res.parse(bytes)) {
-				httparse::Status::Complete(len) => {
-					//println!("Response.parse Complete({})", len);
-					let status = try!(StatusCode::from_u16(res.code.unwrap()).map_err(|_| {
-						httparse::Error::Status
-					}));
-					let version = if res.version.unwrap() == 1 {
-						Version::HTTP_11
-					} else {
-						Version::HTTP_10
-					};
-					record_header_indices(bytes, &res.headers, &mut headers_indices);
-					let headers_len = res.headers.len();
-					(len, status, version, headers_len)
+			let read = match stream.read(&mut chunk) {
+				Ok(0) => {
+					return Err(WebSocketError::ProtocolError(
+						"Connection closed before handshake could complete.",
+					))
 				}
-				httparse::Status::Partial => return Err(httparse::Error::Status.into()),
-			}
-		};
-
-		let mut headers = HeaderMap::with_capacity(headers_len);
-		let slice = buf_bytes.split_to(len).freeze();
-
-		let new_headers = HeadersAsBytesIter {
-			headers: headers_indices[..headers_len].iter(),
-			slice: slice,
-		};
-		headers.extend(new_headers);
-
-		let response = ResponseHead {
-			version: version,
-			subject: status,
-			headers: headers,
+				Ok(read) => read,
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+				Err(e) => return Err(e.into()),
+			};
+			buf.extend_from_slice(&chunk[..read]);
 		};
 
 		// validate
 		self.validate(&response)?;
 
-		Ok(Client::unchecked(reader, response.headers, true, false))
+		// the server may have sent frames right behind its response
+		Ok(Client::unchecked_with_buffer(
+			stream,
+			&buf,
+			response.headers,
+			true,
+			false,
+		))
 	}
 
 	/// Connect to a websocket server asynchronously.
@@ -794,12 +766,19 @@ impl<'u> ClientBuilder<'u> {
 			version_set: self.version_set,
 			key_set: self.key_set,
 		};
-		let resource = builder.build_request();
+		let resource = match builder.build_request().parse() {
+			Ok(resource) => resource,
+			Err(_) => {
+				return Box::new(future::err(WebSocketError::RequestError(
+					"Request resource is not a valid URI",
+				)))
+			}
+		};
 		let framed = ::codec::http::HttpClientCodec.framed(stream);
 		let request = MessageHead {
 			version: builder.version,
 			headers: builder.headers.clone(),
-			subject: (Method::GET, resource.parse().unwrap()),
+			subject: (Method::GET, resource),
 		};
 
 		let future = framed
@@ -911,8 +890,22 @@ impl<'u> ClientBuilder<'u> {
 		resource
 	}
 
+	/// The head of the upgrade request to send.
+	#[cfg(feature = "sync")]
+	pub(crate) fn handshake_request(&mut self) -> WebSocketResult<MessageHead<(Method, Uri)>> {
+		let resource = match self.build_request().parse() {
+			Ok(resource) => resource,
+			Err(_) => return Err(WebSocketError::RequestError("Request resource is not a valid URI")),
+		};
+		Ok(MessageHead {
+			version: self.version,
+			headers: self.headers.clone(),
+			subject: (Method::GET, resource),
+		})
+	}
+
 	#[cfg(any(feature = "sync", feature = "async"))]
-	fn validate(&self, response: &ResponseHead) -> WebSocketResult<()> {
+	pub(crate) fn validate(&self, response: &ResponseHead) -> WebSocketResult<()> {
 
 		let status = if response.subject != StatusCode::SWITCHING_PROTOCOLS {
 			None
@@ -932,7 +925,7 @@ impl<'u> ClientBuilder<'u> {
 		let key: WebSocketKey =
 			self.headers
 				.get(SEC_WEBSOCKET_KEY)
-				.map(|key| WebSocketKey::from_str(key.to_str().unwrap()).unwrap())
+				.and_then(|key| WebSocketKey::try_from(key).ok())
 				.ok_or(WebSocketError::RequestError("Request Sec-WebSocket-Key was invalid",))?;
 
 		//println!("{:?} : {}", response.headers, WebSocketAccept::new(key));
@@ -972,8 +965,13 @@ impl<'u> ClientBuilder<'u> {
 		Ok(())
 	}
 
+	#[cfg(feature = "evented")]
+	pub(crate) fn scheme(&self) -> &str {
+		self.url.scheme()
+	}
+
 	#[cfg(any(feature = "sync", feature = "async"))]
-	fn extract_host_port(&self, secure: Option<bool>) -> WebSocketResult<(&str, u16)> {
+	pub(crate) fn extract_host_port(&self, secure: Option<bool>) -> WebSocketResult<(&str, u16)> {
 		let port = match (self.url.port(), secure) {
 			(Some(port), _) => port,
 			(None, None) if self.url.scheme() == "wss" => 443,
@@ -1056,4 +1054,52 @@ mod tests {
 		assert!(protos.0.contains(&"electric".to_string()));
 		assert!(!protos.0.contains(&"rust-websocket".to_string()));
 	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connect_on_keeps_bytes_after_handshake() {
+		use super::*;
+		use std::io::Cursor;
+		use stream::ReadWritePair;
+		use message::OwnedMessage;
+
+		let mut input = b"HTTP/1.1 101 Switching Protocols\r\n\
+			Upgrade: websocket\r\n\
+			Connection: Upgrade\r\n\
+			Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+			\r\n"
+			.to_vec();
+		input.extend_from_slice(&[0x81, 0x02, b'h', b'i']);
+		input.extend_from_slice(&[0x81, 0x02, b'y', b'o']);
+		input.extend_from_slice(&[0x82, 0x03, 1]);
+
+		let mut client = ClientBuilder::new("ws://test.ws")
+			.unwrap()
+			.key(b"the sample nonce".clone())
+			.connect_on(ReadWritePair(Cursor::new(input), Cursor::new(Vec::new())))
+			.unwrap();
+
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Text("hi".to_string()));
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Text("yo".to_string()));
+		let (_, rest) = client.into_stream();
+		assert_eq!(rest, Some((vec![0x82, 0x03, 1],)));
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connect_on_fails_on_early_eof() {
+		use super::*;
+		use std::io::Cursor;
+		use stream::ReadWritePair;
+
+		let input = Cursor::new(b"HTTP/1.1 101 Switching Protocols\r\n".to_vec());
+		let result = ClientBuilder::new("ws://test.ws")
+			.unwrap()
+			.connect_on(ReadWritePair(input, Cursor::new(Vec::new())));
+
+		match result {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			_ => panic!("expected the handshake to fail"),
+		}
+	}
 }
@@ -18,6 +18,8 @@ pub use self::builder::{ClientBuilder, Url, ParseError};
 
 #[cfg(feature = "async")]
 pub mod async;
+#[cfg(feature = "async")]
+pub mod reconnect;
 
 #[cfg(feature = "sync")]
 pub mod sync;
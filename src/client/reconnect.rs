@@ -0,0 +1,531 @@
+//! An async client that reconnects by itself when its connection is lost.
+//!
+//! A `ReconnectingClient` is a `Stream` of `Event`s and a `Sink` of
+//! `OwnedMessage`s, like the plain async client, but it outlives any single
+//! connection: when a connection breaks or can't be made it waits a while,
+//! backing off exponentially, and connects again. Every time it connects the
+//! `on_reconnect` hook is asked for messages to send first, which is where
+//! subscriptions are resent.
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # extern crate tokio;
+//! # fn main() {
+//! use websocket::OwnedMessage;
+//! use websocket::ClientBuilder;
+//! use websocket::futures::{Future, Stream};
+//! use websocket::client::reconnect::{Event, QueuePolicy, ReconnectingClient};
+//!
+//! let builder = ClientBuilder::new("ws://127.0.0.1:2794").unwrap();
+//! let client = ReconnectingClient::insecure(builder)
+//!     .queue(QueuePolicy::Queue(100))
+//!     .on_reconnect(|_| vec![OwnedMessage::Text("subscribe ticker".to_string())]);
+//!
+//! tokio::run(client.for_each(|event| {
+//!     match event {
+//!         Event::Message(message) => println!("{:?}", message),
+//!         other => println!("connection: {:?}", other),
+//!     }
+//!     Ok(())
+//! }).map_err(|e| println!("{}", e)));
+//! # }
+//! ```
+//!
+//! Connections are only made and messages only read while the client is
+//! polled as a stream, and the timer it waits with needs a tokio runtime.
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use http::HeaderMap;
+use rand;
+use tokio::timer::Delay;
+
+use client::async::{Client, ClientNew, Handle, TcpStream};
+use client::builder::ClientBuilder;
+use message::OwnedMessage;
+use result::WebSocketError;
+use stream::async::Stream as AsyncStream;
+
+/// What a `ReconnectingClient` has to report.
+#[derive(Debug)]
+pub enum Event {
+	/// A connection was made, with the headers of the server's response.
+	Connected(HeaderMap),
+	/// A message from the server.
+	Message(OwnedMessage),
+	/// The connection was lost, because of the error if there is one,
+	/// otherwise because the server closed it.
+	Disconnected(Option<WebSocketError>),
+	/// An attempt to connect failed.
+	Failed(WebSocketError),
+	/// The client waits `delay` before its `attempt`th attempt to connect.
+	Waiting {
+		/// How many attempts have been made since the last connection, plus one.
+		attempt: u32,
+		/// How long until the attempt.
+		delay: Duration,
+	},
+}
+
+/// How long to wait before connecting again.
+///
+/// The first attempt after a connection was lost waits `initial`, each
+/// failed attempt doubles that up to `max`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+	/// The wait before the first attempt.
+	pub initial: Duration,
+	/// The longest wait.
+	pub max: Duration,
+	/// Wait a random time between half of the delay and all of it, so many
+	/// clients that lost their connections at once don't all come back at once.
+	pub jitter: bool,
+}
+
+impl Default for Backoff {
+	fn default() -> Self {
+		Backoff {
+			initial: Duration::from_millis(100),
+			max: Duration::from_secs(30),
+			jitter: true,
+		}
+	}
+}
+
+impl Backoff {
+	/// The wait before the `attempt`th attempt, counting from one.
+	pub fn delay(&self, attempt: u32) -> Duration {
+		let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::max_value());
+		let delay = self.initial
+		                .checked_mul(factor)
+		                .map_or(self.max, |delay| cmp::min(delay, self.max));
+		if !self.jitter {
+			return delay;
+		}
+		let half = delay / 2;
+		let nanos = half.as_secs() * 1_000_000_000 + u64::from(half.subsec_nanos());
+		half + Duration::from_nanos((nanos as f64 * rand::random::<f64>()) as u64)
+	}
+}
+
+/// What happens to messages sent while there is no connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuePolicy {
+	/// Drop them.
+	Drop,
+	/// Keep up to this many to send once connected again, dropping the oldest
+	/// ones when there are more.
+	Queue(usize),
+}
+
+enum State<S> {
+	Idle,
+	Waiting(Delay),
+	Connecting(ClientNew<S>),
+	Open(Client<S>),
+	Done,
+}
+
+/// An async client that connects again whenever its connection is lost.
+///
+/// See the module documentation.
+pub struct ReconnectingClient<S> {
+	connect: Box<FnMut() -> ClientNew<S> + Send>,
+	on_reconnect: Box<FnMut(&HeaderMap) -> Vec<OwnedMessage> + Send>,
+	backoff: Backoff,
+	policy: QueuePolicy,
+	max_attempts: Option<u32>,
+	attempt: u32,
+	state: State<S>,
+	events: VecDeque<Event>,
+	outbox: VecDeque<OwnedMessage>,
+}
+
+#[cfg(feature = "async-ssl")]
+impl ReconnectingClient<Box<AsyncStream + Send>> {
+	/// Connect with `builder`, over TLS if it is a `wss://` url.
+	///
+	/// See `ClientBuilder::async_connect`.
+	pub fn new(builder: ClientBuilder<'static>) -> Self {
+		ReconnectingClient::with_connector(move || {
+			builder.clone().async_connect(None, &Handle::default())
+		})
+	}
+}
+
+impl ReconnectingClient<TcpStream> {
+	/// Connect with `builder`, without TLS.
+	///
+	/// See `ClientBuilder::async_connect_insecure`.
+	pub fn insecure(builder: ClientBuilder<'static>) -> Self {
+		ReconnectingClient::with_connector(move || {
+			builder.clone().async_connect_insecure(&Handle::default())
+		})
+	}
+}
+
+impl<S> ReconnectingClient<S>
+where
+	S: AsyncStream + Send,
+{
+	/// Connect with whatever future `connect` returns, for example to connect
+	/// over TLS with a custom `TlsConnector`.
+	pub fn with_connector<F>(connect: F) -> Self
+	where
+		F: FnMut() -> ClientNew<S> + Send + 'static,
+	{
+		ReconnectingClient {
+			connect: Box::new(connect),
+			on_reconnect: Box::new(|_| Vec::new()),
+			backoff: Backoff::default(),
+			policy: QueuePolicy::Drop,
+			max_attempts: None,
+			attempt: 0,
+			state: State::Idle,
+			events: VecDeque::new(),
+			outbox: VecDeque::new(),
+		}
+	}
+
+	/// How long to wait between attempts, see `Backoff::default` for the default.
+	pub fn backoff(mut self, backoff: Backoff) -> Self {
+		self.backoff = backoff;
+		self
+	}
+
+	/// What to do with messages sent while disconnected, they are dropped by default.
+	pub fn queue(mut self, policy: QueuePolicy) -> Self {
+		self.policy = policy;
+		self
+	}
+
+	/// Give up after this many attempts in a row failed, by default the client
+	/// never gives up. Its stream ends when it does.
+	pub fn max_attempts(mut self, attempts: u32) -> Self {
+		self.max_attempts = Some(attempts);
+		self
+	}
+
+	/// Called with the server's response headers every time a connection is
+	/// made, the first one included. The messages it returns are sent before
+	/// anything else.
+	pub fn on_reconnect<F>(mut self, hook: F) -> Self
+	where
+		F: FnMut(&HeaderMap) -> Vec<OwnedMessage> + Send + 'static,
+	{
+		self.on_reconnect = Box::new(hook);
+		self
+	}
+
+	/// Whether there is a connection right now.
+	pub fn is_connected(&self) -> bool {
+		match self.state {
+			State::Open(_) => true,
+			_ => false,
+		}
+	}
+
+	/// Make progress on connecting, if there is no connection.
+	fn advance(&mut self) -> Result<(), WebSocketError> {
+		let connected = match self.state {
+			State::Idle => None,
+			State::Waiting(ref mut delay) => match delay.poll() {
+				Ok(Async::Ready(())) => None,
+				Ok(Async::NotReady) => return Ok(()),
+				Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e).into()),
+			},
+			State::Connecting(ref mut future) => match future.poll() {
+				Ok(Async::Ready(client)) => Some(Ok(client)),
+				Ok(Async::NotReady) => return Ok(()),
+				Err(e) => Some(Err(e)),
+			},
+			State::Open(_) | State::Done => return Ok(()),
+		};
+
+		match connected {
+			None => {
+				// poll the new attempt so that it wakes the task up
+				self.state = State::Connecting((self.connect)());
+				return self.advance();
+			}
+			Some(Ok((client, headers))) => {
+				self.attempt = 0;
+				for message in (self.on_reconnect)(&headers).into_iter().rev() {
+					self.outbox.push_front(message);
+				}
+				self.events.push_back(Event::Connected(headers));
+				self.state = State::Open(client);
+			}
+			Some(Err(e)) => {
+				self.events.push_back(Event::Failed(e));
+				self.retry();
+			}
+		}
+		Ok(())
+	}
+
+	/// Wait before the next attempt, or give up.
+	fn retry(&mut self) {
+		self.attempt += 1;
+		if self.max_attempts.map_or(false, |max| self.attempt > max) {
+			self.state = State::Done;
+			return;
+		}
+		let delay = self.backoff.delay(self.attempt);
+		self.events.push_back(Event::Waiting {
+			attempt: self.attempt,
+			delay: delay,
+		});
+		self.state = State::Waiting(Delay::new(Instant::now() + delay));
+	}
+
+	fn lost(&mut self, error: Option<WebSocketError>) {
+		match self.policy {
+			QueuePolicy::Drop => self.outbox.clear(),
+			QueuePolicy::Queue(limit) => {
+				while self.outbox.len() > limit {
+					self.outbox.pop_front();
+				}
+			}
+		}
+		self.events.push_back(Event::Disconnected(error));
+		self.retry();
+	}
+
+	/// Send what is in the outbox over the connection, `Ready` once all of it is.
+	fn write(&mut self) -> Async<()> {
+		let result = match self.state {
+			State::Open(ref mut client) => write(client, &mut self.outbox),
+			_ => return Async::Ready(()),
+		};
+		match result {
+			Ok(written) => written,
+			Err(e) => {
+				self.lost(Some(e));
+				Async::Ready(())
+			}
+		}
+	}
+}
+
+fn write<S>(client: &mut Client<S>, outbox: &mut VecDeque<OwnedMessage>) -> Poll<(), WebSocketError>
+where
+	S: AsyncStream,
+{
+	while let Some(message) = outbox.pop_front() {
+		if let AsyncSink::NotReady(message) = client.start_send(message)? {
+			outbox.push_front(message);
+			break;
+		}
+	}
+	let flushed = client.poll_complete()?;
+	if outbox.is_empty() {
+		Ok(flushed)
+	} else {
+		Ok(Async::NotReady)
+	}
+}
+
+impl<S> Stream for ReconnectingClient<S>
+where
+	S: AsyncStream + Send,
+{
+	type Item = Event;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<Event>, WebSocketError> {
+		loop {
+			if let Some(event) = self.events.pop_front() {
+				return Ok(Async::Ready(Some(event)));
+			}
+			self.advance()?;
+			self.write();
+			if !self.events.is_empty() {
+				continue;
+			}
+
+			let read = match self.state {
+				State::Open(ref mut client) => client.poll(),
+				State::Done => return Ok(Async::Ready(None)),
+				_ => return Ok(Async::NotReady),
+			};
+			match read {
+				Ok(Async::Ready(Some(OwnedMessage::Close(_)))) |
+				Ok(Async::Ready(None)) => self.lost(None),
+				Ok(Async::Ready(Some(message))) => return Ok(Async::Ready(Some(Event::Message(message)))),
+				Ok(Async::NotReady) => return Ok(Async::NotReady),
+				Err(e) => self.lost(Some(e)),
+			}
+		}
+	}
+}
+
+impl<S> Sink for ReconnectingClient<S>
+where
+	S: AsyncStream + Send,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, message: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		// let what is already queued out first
+		if self.is_connected() && self.write().is_not_ready() && self.is_connected() {
+			return Ok(AsyncSink::NotReady(message));
+		}
+		if self.is_connected() {
+			self.outbox.push_back(message);
+			self.write();
+			return Ok(AsyncSink::Ready);
+		}
+
+		// no connection, the message waits for the next one or is dropped
+		if let QueuePolicy::Queue(limit) = self.policy {
+			self.outbox.push_back(message);
+			while self.outbox.len() > limit {
+				self.outbox.pop_front();
+			}
+		}
+		Ok(AsyncSink::Ready)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		self.advance()?;
+		Ok(self.write())
+	}
+
+	/// Send a close frame if connected and stop reconnecting.
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		if self.is_connected() {
+			self.outbox.push_back(OwnedMessage::Close(None));
+			if self.write().is_not_ready() && self.is_connected() {
+				// the close frame is sent first thing next time
+				self.outbox.pop_back();
+				return Ok(Async::NotReady);
+			}
+		}
+		self.outbox.clear();
+		self.state = State::Done;
+		Ok(Async::Ready(()))
+	}
+}
+
+impl<S> fmt::Debug for ReconnectingClient<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ReconnectingClient")
+		 .field("backoff", &self.backoff)
+		 .field("policy", &self.policy)
+		 .field("max_attempts", &self.max_attempts)
+		 .field("attempt", &self.attempt)
+		 .field("queued", &self.outbox.len())
+		 .finish()
+	}
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+	use super::*;
+	use std::net::TcpListener;
+	use std::sync::mpsc::channel;
+	use std::thread;
+	use tokio;
+	use server::sync::Server;
+
+	fn kind(event: &Event) -> &'static str {
+		match *event {
+			Event::Connected(_) => "connected",
+			Event::Message(_) => "message",
+			Event::Disconnected(_) => "disconnected",
+			Event::Failed(_) => "failed",
+			Event::Waiting { .. } => "waiting",
+		}
+	}
+
+	#[test]
+	fn backoff_doubles_up_to_max() {
+		let backoff = Backoff {
+			initial: Duration::from_millis(100),
+			max: Duration::from_secs(1),
+			jitter: false,
+		};
+		assert_eq!(backoff.delay(1), Duration::from_millis(100));
+		assert_eq!(backoff.delay(3), Duration::from_millis(400));
+		assert_eq!(backoff.delay(5), Duration::from_secs(1));
+		assert_eq!(backoff.delay(100), Duration::from_secs(1));
+	}
+
+	#[test]
+	fn resubscribes_after_reconnecting() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		let subscribe = OwnedMessage::Text("subscribe".to_string());
+
+		let expected = subscribe.clone();
+		thread::spawn(move || {
+			let mut first = server.accept().ok().unwrap().accept().unwrap();
+			assert_eq!(first.recv_message().unwrap(), expected);
+			drop(first);
+
+			let mut second = server.accept().ok().unwrap().accept().unwrap();
+			assert_eq!(second.recv_message().unwrap(), expected);
+			second.send_message(&OwnedMessage::Text("hello".to_string())).unwrap();
+			let _ = second.recv_message();
+		});
+
+		let client = ReconnectingClient::insecure(ClientBuilder::new(&url).unwrap())
+			.backoff(Backoff {
+				initial: Duration::from_millis(10),
+				max: Duration::from_millis(10),
+				jitter: false,
+			})
+			.on_reconnect(move |_| vec![subscribe.clone()]);
+
+		let (kinds_tx, kinds) = channel();
+		thread::spawn(move || {
+			tokio::run(client.take(5)
+			                 .map(|event| kind(&event))
+			                 .collect()
+			                 .map(move |seen| kinds_tx.send(seen).unwrap())
+			                 .map_err(|e| panic!("{}", e)))
+		});
+
+		assert_eq!(
+			kinds.recv_timeout(Duration::from_secs(10)).unwrap(),
+			vec!["connected", "disconnected", "waiting", "connected", "message"]
+		);
+	}
+
+	#[test]
+	fn retries_until_the_server_is_up() {
+		// find a free port, nothing listens on it until the client has failed
+		let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+		let client = ReconnectingClient::insecure(ClientBuilder::new(&format!("ws://{}", addr)).unwrap())
+			.backoff(Backoff {
+				initial: Duration::from_millis(50),
+				max: Duration::from_millis(50),
+				jitter: false,
+			});
+
+		let (kinds_tx, kinds) = channel();
+		thread::spawn(move || {
+			tokio::run(client.map(|event| kind(&event))
+			                 .take_while(|kind| Ok(*kind != "connected"))
+			                 .collect()
+			                 .map(move |seen| kinds_tx.send(seen).unwrap())
+			                 .map_err(|e| panic!("{}", e)))
+		});
+		thread::spawn(move || {
+			thread::sleep(Duration::from_millis(20));
+			let mut server = Server::bind(addr).unwrap();
+			let _client = server.accept().ok().unwrap().accept().unwrap();
+			thread::sleep(Duration::from_secs(1));
+		});
+
+		let seen = kinds.recv_timeout(Duration::from_secs(10)).unwrap();
+		assert!(seen.len() >= 2);
+		assert_eq!(&seen[..2], &["failed", "waiting"]);
+	}
+}
@@ -2,12 +2,13 @@
 use std::net::TcpStream;
 use std::net::SocketAddr;
 use std::io::Result as IoResult;
-use std::io::{Read, Write};
-use std::str::{self, FromStr};
+use std::io::{BufReader, Read, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use http::header::HeaderMap;
 use http::header::{SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL};
-use std::io::BufReader;
 
 use ws;
 use ws::sender::Sender as SenderTrait;
@@ -17,14 +18,20 @@ use message::OwnedMessage;
 use result::WebSocketResult;
 use stream::sync::{AsTcpStream, Stream, Splittable, Shutdown};
 use dataframe::DataFrame;
+use intercept::Interceptor;
 use ws::dataframe::DataFrame as DataFrameable;
 use sender::Sender;
 use receiver::Receiver;
-pub use sender::Writer;
+pub use sender::{Writer, SharedWriter};
 pub use receiver::Reader;
 
 use header::sec_websocket_extensions::Extension;
 
+#[cfg(feature = "serde")]
+use codec::json::{Framing, Json};
+#[cfg(feature = "serde")]
+use codec::typed::{self, DeserializeOwned, Format, Serialize, TypedError, TypedMessage};
+
 /// Represents a WebSocket client, which can send and receive messages/data frames.
 ///
 /// The client just wraps around a `Stream` (which is something that can be read from
@@ -35,7 +42,8 @@ use header::sec_websocket_extensions::Extension;
 /// to different threads, often using a send loop and receiver loop concurrently,
 /// as shown in the client example in `examples/client.rs`.
 /// This is only possible for streams that implement the `Splittable` trait, which
-/// currently is only TCP streams. (it is unsafe to duplicate an SSL stream)
+/// are TCP and TLS streams. The halves of a TLS stream share its session through a
+/// `SharedStream`, since the session itself can't be duplicated.
 ///
 ///# Connecting to a Server
 ///
@@ -107,9 +115,27 @@ where
 	}
 
 	/// Changes whether the stream is in nonblocking mode.
+	///
+	/// Use `try_recv_message` to read from a nonblocking client, it keeps
+	/// partially received messages around instead of failing with them.
 	pub fn set_nonblocking(&self, nonblocking: bool) -> IoResult<()> {
 		self.stream.get_ref().as_tcp().set_nonblocking(nonblocking)
 	}
+
+	/// See [`TcpStream::set_read_timeout`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.set_read_timeout).
+	///
+	/// A read that times out fails with a `WouldBlock` or `TimedOut` IO error,
+	/// or makes `try_recv_message` return `Ok(None)`.
+	pub fn set_read_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().as_tcp().set_read_timeout(timeout)
+	}
+
+	/// See [`TcpStream::set_write_timeout`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.set_write_timeout).
+	pub fn set_write_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().as_tcp().set_write_timeout(timeout)
+	}
 }
 
 impl<S> Client<S>
@@ -135,6 +161,20 @@ where
 		}
 	}
 
+	/// Like `unchecked`, for a stream the peer may have already sent frames on,
+	/// `buffered` holds the bytes read past the end of the handshake.
+	pub(crate) fn unchecked_with_buffer(
+		stream: S,
+		buffered: &[u8],
+		headers: HeaderMap,
+		out_mask: bool,
+		in_mask: bool,
+	) -> Self {
+		let mut client = Client::unchecked(BufReader::new(stream), headers, out_mask, in_mask);
+		client.receiver.push_buffered(buffered);
+		client
+	}
+
 	/// Sends a single data frame to the remote endpoint.
 	pub fn send_dataframe<D>(&mut self, dataframe: &D) -> WebSocketResult<()>
 	where
@@ -178,6 +218,91 @@ where
 		self.receiver.recv_message(&mut self.stream)
 	}
 
+	/// Reads a message if a complete one is available, or returns `Ok(None)`
+	/// if the stream would block or its read timeout passed first.
+	///
+	/// Meant for nonblocking clients and clients with a read timeout, a message
+	/// that only partly arrived is kept and completed by later calls.
+	///
+	///```no_run
+	///# extern crate websocket;
+	///# fn main() {
+	///use websocket::ClientBuilder;
+	///
+	///let mut client = ClientBuilder::new("ws://127.0.0.1:1234").unwrap()
+	///                     .connect_insecure().unwrap();
+	///client.set_nonblocking(true).unwrap();
+	///
+	///loop {
+	///    match client.try_recv_message().unwrap() {
+	///        Some(message) => println!("Recv: {:?}", message),
+	///        None => {
+	///            // do other work
+	///        }
+	///    }
+	///}
+	///# }
+	///```
+	pub fn try_recv_message(&mut self) -> WebSocketResult<Option<OwnedMessage>> {
+		self.receiver.try_recv_message(&mut self.stream)
+	}
+
+	/// Run the messages and data frames this client sends and receives
+	/// through `interceptor`, see the `intercept` module.
+	///
+	/// It sees both directions, also after the client is split.
+	pub fn intercept<I>(&mut self, interceptor: I)
+	where
+		I: Interceptor + 'static,
+	{
+		let interceptor = Arc::new(Mutex::new(interceptor));
+		self.sender.interceptors().push(interceptor.clone());
+		self.receiver.interceptors().push(interceptor);
+	}
+
+	/// Sends a value in the given format, see the `codec::typed` module.
+	#[cfg(feature = "serde")]
+	pub fn send_typed<F, T>(&mut self, format: &F, value: &T) -> Result<(), TypedError>
+	where
+		F: Format,
+		T: Serialize + ?Sized,
+	{
+		let message = format.encode(value).map_err(TypedError::Encode)?;
+		Ok(self.send_message(&message)?)
+	}
+
+	/// Reads a message and decodes the value it holds in the given format,
+	/// control messages are returned as they are.
+	///
+	/// A message that doesn't hold a `T` fails with `TypedError::Decode`,
+	/// the next call reads the message after it.
+	#[cfg(feature = "serde")]
+	pub fn recv_typed<F, T>(&mut self, format: &F) -> Result<TypedMessage<T>, TypedError>
+	where
+		F: Format,
+		T: DeserializeOwned,
+	{
+		typed::decode(format, self.recv_message()?)
+	}
+
+	/// Sends a value as JSON, in a text or a binary message.
+	#[cfg(feature = "serde")]
+	pub fn send_json<T>(&mut self, value: &T, framing: Framing) -> Result<(), TypedError>
+	where
+		T: Serialize + ?Sized,
+	{
+		self.send_typed(&Json::new(framing), value)
+	}
+
+	/// Reads a message and decodes the JSON value it holds, see `recv_typed`.
+	#[cfg(feature = "serde")]
+	pub fn recv_json<T>(&mut self) -> Result<TypedMessage<T>, TypedError>
+	where
+		T: DeserializeOwned,
+	{
+		self.recv_typed(&Json::default())
+	}
+
 	/// Access the headers that were sent in the server's handshake response.
 	/// This is a catch all for headers other than protocols and extensions.
 	pub fn headers(&self) -> &HeaderMap {
@@ -202,10 +327,9 @@ where
 	pub fn protocols<'a>(&'a self) -> Vec<&str> {
 		self.headers
 		    .get(SEC_WEBSOCKET_PROTOCOL)
+		    .and_then(|e| e.to_str().ok())
 		    .map(|e| {
-			str::from_utf8(e.as_bytes())
-				.unwrap()
-				.split(',')
+			e.split(',')
 				.filter_map(|x| match x.trim() {
 					"" => None,
 					y => Some(y),
@@ -215,16 +339,22 @@ where
 		    .unwrap_or(vec![])
 	}
 
+	/// The subprotocol the connection speaks, if one was chosen during the
+	/// handshake. For a client this is the one the server accepted, for a
+	/// connection accepted by a server it is the one it answered with.
+	pub fn protocol(&self) -> Option<&str> {
+		self.protocols().into_iter().next()
+	}
+
 	/// If you supplied a protocol, be sure to check if it was accepted by the
 	/// server here. Since no extensions are implemented out of the box yet, using
 	/// one will require its own implementation.
 	pub fn extensions(&self) -> Vec<Extension> {
 		self.headers
 		    .get(SEC_WEBSOCKET_EXTENSIONS)
+		    .and_then(|e| e.to_str().ok())
 		    .map(|e| {
-			str::from_utf8(e.as_bytes())
-				.unwrap()
-				.split(',')
+			e.split(',')
 				.filter_map(|x| match x.trim() {
 					"" => None,
 					y => Some(y),
@@ -314,10 +444,21 @@ where
 	/// bytes might already be read from the stream when this is called,
 	/// these buffered bytes are returned in the form
 	///
-	/// `(byte_buffer: Vec<u8>, buffer_capacity: usize, buffer_position: usize)`
-	pub fn into_stream(self) -> (S, Option<(Vec<u8>,)>) {
+	/// `(unread_bytes: Vec<u8>,)`
+	///
+	/// This includes the frames of a message that was only partly received, as
+	/// well as the start of a frame that was cut off.
+	///
+	/// If nothing is left in the buffer `None` is returned instead.
+	pub fn into_stream(mut self) -> (S, Option<(Vec<u8>,)>) {
+		let mut buffer = self.receiver.take_buffered();
+		buffer.extend_from_slice(self.stream.buffer());
 		let stream = self.stream.into_inner();
-		(stream, None)
+		if buffer.is_empty() {
+			(stream, None)
+		} else {
+			(stream, Some((buffer,)))
+		}
 	}
 
 	/// Returns an iterator over incoming messages.
@@ -391,10 +532,11 @@ where
 	///# }
 	///```
 	pub fn split(
-		self,
+		mut self,
 	) -> IoResult<(Reader<<S as Splittable>::Reader>, Writer<<S as Splittable>::Writer>)> {
-		let stream = self.stream.into_inner();
-		let (read, write) = stream.split()?;
+		// keep whatever was already buffered, it belongs to the reading half
+		self.receiver.push_buffered(self.stream.buffer());
+		let (read, write) = self.stream.into_inner().split()?;
 		Ok((
 			Reader {
 				stream: BufReader::new(read),
@@ -407,3 +549,37 @@ where
 		))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{self, Cursor};
+	use stream::ReadWritePair;
+
+	// hands out what it holds, then blocks
+	struct Drained(Cursor<Vec<u8>>);
+
+	impl Read for Drained {
+		fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+			match self.0.read(buf)? {
+				0 => Err(io::ErrorKind::WouldBlock.into()),
+				n => Ok(n),
+			}
+		}
+	}
+
+	#[test]
+	fn into_stream_keeps_unfinished_messages() {
+		// the first fragment of "hello" and the start of the second
+		let input = vec![0x01, 0x02, b'h', b'e', 0x80, 0x03, b'l'];
+		let stream = ReadWritePair(Drained(Cursor::new(input)), Vec::new());
+		let mut client = Client::unchecked(BufReader::new(stream), HeaderMap::new(), false, false);
+		assert_eq!(client.try_recv_message().unwrap(), None);
+
+		let (_, rest) = client.into_stream();
+		let mut rest = rest.unwrap().0;
+		rest.extend_from_slice(b"lo");
+		let message = Receiver::new(false).recv_message(&mut Cursor::new(rest)).unwrap();
+		assert_eq!(message, OwnedMessage::Text("hello".to_string()));
+	}
+}
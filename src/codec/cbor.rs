@@ -0,0 +1,43 @@
+//! Send and receive values of your own types as CBOR.
+//!
+//! `Cbor` is a `Format` for the `typed` module that sends values in binary
+//! messages, see the `msgpack` module for an example of using a format.
+use serde_cbor;
+
+use codec::typed::{DeserializeOwned, Format, FormatError, Serialize, TypedCodec};
+use message::OwnedMessage;
+
+/// The CBOR format, sending values in binary messages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Cbor;
+
+impl Format for Cbor {
+	fn encode<T>(&self, value: &T) -> Result<OwnedMessage, FormatError>
+	where
+		T: Serialize + ?Sized,
+	{
+		Ok(OwnedMessage::Binary(serde_cbor::to_vec(&value)?))
+	}
+
+	fn decode<T>(&self, payload: &[u8]) -> Result<T, FormatError>
+	where
+		T: DeserializeOwned,
+	{
+		Ok(serde_cbor::from_slice(payload)?)
+	}
+}
+
+/// A codec for values of type `T` sent as CBOR over websocket messages.
+pub type CborCodec<T> = TypedCodec<Cbor, T>;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sends_binary_messages() {
+		// an array of two, an unsigned integer and a text string
+		let message = Cbor.encode(&(1u8, "a")).unwrap();
+		assert_eq!(message, OwnedMessage::Binary(vec![0x82, 0x01, 0x61, b'a']));
+	}
+}
@@ -3,13 +3,13 @@
 //! This module has both an `HttpClientCodec` for an async HTTP client and an
 //! `HttpServerCodec` for an async HTTP server.
 use std::borrow::Cow;
-use std::io::{self, BufReader, Write};
+use std::io::{self, BufReader};
 use std::error::Error;
 use std::fmt::{self, Formatter, Display};
 
-use bytes::{BufMut, BytesMut, Bytes};
+use bytes::{BytesMut, Bytes};
 use http::{self, Method, StatusCode, Uri};
-use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH};
 use httparse::{self, Request};
 use hyper;
 use tokio_codec::{Decoder, Encoder};
@@ -18,6 +18,12 @@ use tokio_codec::{Decoder, Encoder};
 use http::Version;
 
 pub const MAX_HEADERS: usize = 100;
+/// The default maximum length (in bytes) of a request line accepted by
+/// `HttpServerCodec`, including the trailing `\r\n`.
+pub const MAX_REQUEST_LINE: usize = 8 * 1024;
+/// The default maximum size (in bytes) of an entire message head accepted
+/// by `HttpServerCodec` and `HttpClientCodec`, first line and headers included.
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
 pub type ParseRespose<T> = hyper::Result<Option<(MessageHead<T>, usize)>>;
 
 /// An incoming request message.
@@ -84,7 +90,6 @@ impl<'a> Iterator for HeadersAsBytesIter<'a> {
 	}
 }
 
-#[derive(Copy, Clone, Debug)]
 ///A codec to be used with `tokio` codecs that can serialize HTTP requests and
 ///deserialize HTTP responses. One can use this on it's own without websockets to
 ///make a very bare async HTTP server.
@@ -125,8 +130,115 @@ impl<'a> Iterator for HeadersAsBytesIter<'a> {
 ///tokio::run(f.map(|_| ()).map_err(|_| ()));
 ///# }
 ///```
+///
+///A misbehaving server could send an endless response head, so the codec
+///gives up once it has buffered more than `MAX_HEAD_SIZE` bytes, wrap it in
+///`Limited` for other limits.
+#[derive(Copy, Clone, Debug)]
 pub struct HttpClientCodec;
 
+/// Byte limits on the message heads read by an HTTP codec wrapped in `Limited`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HttpLimits {
+	/// The maximum length of a request line, the status line of a response
+	/// is only bounded by `max_head_size`.
+	pub max_request_line: usize,
+	/// The maximum size of the first line plus all headers.
+	pub max_head_size: usize,
+}
+
+impl Default for HttpLimits {
+	fn default() -> Self {
+		HttpLimits {
+			max_request_line: MAX_REQUEST_LINE,
+			max_head_size: MAX_HEAD_SIZE,
+		}
+	}
+}
+
+impl HttpLimits {
+	fn check_request(&self, head: &[u8]) -> Result<(), HttpCodecError> {
+		let line_len = match head.windows(2).position(|i| i == b"\r\n") {
+			Some(p) => p + 2,
+			None => head.len(),
+		};
+		if line_len > self.max_request_line {
+			return Err(HttpCodecError::RequestLineTooLong);
+		}
+		if head.len() > self.max_head_size {
+			return Err(HttpCodecError::HeadersTooLarge);
+		}
+		Ok(())
+	}
+}
+
+/// An `HttpServerCodec` or `HttpClientCodec` reading message heads within
+/// custom `HttpLimits` rather than the default ones.
+///
+///```rust
+///# extern crate websocket;
+///# fn main() {
+///use websocket::codec::http::{HttpLimits, HttpServerCodec, Limited};
+///
+///let codec = Limited::new(HttpServerCodec, HttpLimits {
+///    max_request_line: 1024,
+///    max_head_size: 8 * 1024,
+///});
+///# }
+///```
+#[derive(Copy, Clone, Debug)]
+pub struct Limited<C> {
+	codec: C,
+	limits: HttpLimits,
+}
+
+impl<C> Limited<C> {
+	/// Read with `codec`, failing heads that break `limits`.
+	pub fn new(codec: C, limits: HttpLimits) -> Self {
+		Limited {
+			codec: codec,
+			limits: limits,
+		}
+	}
+
+	/// The limits heads are read with.
+	pub fn limits(&self) -> HttpLimits {
+		self.limits
+	}
+
+	/// Get the wrapped codec back.
+	pub fn into_inner(self) -> C {
+		self.codec
+	}
+}
+
+impl<C: Encoder> Encoder for Limited<C> {
+	type Item = C::Item;
+	type Error = C::Error;
+
+	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		self.codec.encode(item, dst)
+	}
+}
+
+impl Decoder for Limited<HttpClientCodec> {
+	type Item = ResponseHead;
+	type Error = HttpCodecError;
+
+	fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		decode_response(buf, &self.limits)
+	}
+}
+
+impl Decoder for Limited<HttpServerCodec> {
+	type Item = RequestHead;
+	type Error = HttpCodecError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		decode_request(src, &self.limits)
+	}
+}
+
 fn split_off_http(src: &mut BytesMut) -> Option<BytesMut> {
 	match src.windows(4).position(|i| i == b"\r\n\r\n") {
 		Some(p) => Some(src.split_to(p + 4)),
@@ -149,24 +261,15 @@ impl Encoder for HttpClientCodec {
 
 	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
 
-		// TODO: optomize this!
-		let mut request = format!(
+		let request_line = format!(
 			"{} {} {:?}\r\n",
 			item.subject.0, item.subject.1, item.version
-		).to_owned();
-
-		for (key, value) in item.headers.iter() {
-			request += &format!("{}: {}\r\n", key.as_str(), value.to_str().unwrap());
-		}
-
-		request += "\r\n";
-
-		let byte_len = request.as_bytes().len();
-		if byte_len > dst.remaining_mut() {
-			dst.reserve(byte_len);
-		}
-
-		dst.writer().write(request.as_bytes()).map(|_| ())
+		);
+		dst.extend(request_line.as_bytes());
+		// header values are written as raw bytes, they need not be visible ASCII
+		write_headers(&item.headers, dst);
+		dst.extend(b"\r\n");
+		Ok(())
 
 	}
 }
@@ -176,61 +279,72 @@ impl Decoder for HttpClientCodec {
 	type Error = HttpCodecError;
 
 	fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		decode_response(buf, &HttpLimits::default())
+	}
+}
 
-		if buf.len() == 0 {
-			return Ok(None);
-		}
+fn decode_response(buf: &mut BytesMut, limits: &HttpLimits) -> Result<Option<ResponseHead>, HttpCodecError> {
+	if buf.len() == 0 {
+		return Ok(None);
+	}
 
-		let mut headers_indices = [HeaderIndices {
-			name: (0, 0),
-			value: (0, 0),
-		}; MAX_HEADERS];
-
-		let (len, code, reason, version, headers_len) = {
-			let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
-			//trace!("Response.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
-			let mut res = httparse::Response::new(&mut headers);
-			let bytes = buf.as_ref();
-			match res.parse(bytes).unwrap_or(httparse::Status::Partial) {
-				httparse::Status::Complete(len) => {
-					//trace!("Response.parse Complete({})", len);
-					let code = res.code.unwrap();
-					let status = try!(StatusCode::from_u16(code).map_err(
-						|_| httparse::Error::Status,
-					));
-					let reason = match status.canonical_reason() {
-						Some(reason) if reason == res.reason.unwrap() => Cow::Borrowed(reason),
-						_ => Cow::Owned(res.reason.unwrap().to_owned()),
-					};
-					let version = if res.version.unwrap() == 1 {
-						Version::HTTP_11
-					} else {
-						Version::HTTP_10
-					};
-					record_header_indices(bytes, &res.headers, &mut headers_indices);
-					let headers_len = res.headers.len();
-					(len, code, reason, version, headers_len)
-				}
-				httparse::Status::Partial => return Ok(None),
+	let mut headers_indices = [HeaderIndices {
+		name: (0, 0),
+		value: (0, 0),
+	}; MAX_HEADERS];
+
+	let (len, code, reason, version, headers_len) = {
+		let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+		//trace!("Response.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
+		let mut res = httparse::Response::new(&mut headers);
+		let bytes = buf.as_ref();
+		match try!(res.parse(bytes)) {
+			httparse::Status::Complete(len) => {
+				//trace!("Response.parse Complete({})", len);
+				// httparse fills these in for every complete response
+				let code = res.code.unwrap();
+				let status = try!(StatusCode::from_u16(code).map_err(
+					|_| httparse::Error::Status,
+				));
+				let reason = match status.canonical_reason() {
+					Some(reason) if Some(reason) == res.reason => Cow::Borrowed(reason),
+					_ => Cow::Owned(res.reason.unwrap_or("").to_owned()),
+				};
+				let version = if res.version.unwrap() == 1 {
+					Version::HTTP_11
+				} else {
+					Version::HTTP_10
+				};
+				record_header_indices(bytes, &res.headers, &mut headers_indices);
+				let headers_len = res.headers.len();
+				(len, code, reason, version, headers_len)
 			}
-		};
+			httparse::Status::Partial if bytes.len() > limits.max_head_size => {
+				return Err(HttpCodecError::HeadersTooLarge)
+			}
+			httparse::Status::Partial => return Ok(None),
+		}
+	};
 
-		let mut headers = HeaderMap::with_capacity(headers_len);
+	if len > limits.max_head_size {
+		return Err(HttpCodecError::HeadersTooLarge);
+	}
 
-		let slice = buf.split_to(len).freeze();
+	let mut headers = HeaderMap::with_capacity(headers_len);
 
-		let new_headers = HeadersAsBytesIter {
-			headers: headers_indices[..headers_len].iter(),
-			slice: slice,
-		};
-		headers.extend(new_headers);
+	let slice = buf.split_to(len).freeze();
 
-		Ok(Some(MessageHead {
-			version: version,
-			subject: StatusCode::from_u16(code).unwrap(),
-			headers: headers,
-		}))
-	}
+	let new_headers = HeadersAsBytesIter {
+		headers: headers_indices[..headers_len].iter(),
+		slice: slice,
+	};
+	headers.extend(new_headers);
+
+	Ok(Some(MessageHead {
+		version: version,
+		subject: try!(StatusCode::from_u16(code).map_err(|_| HttpCodecError::Status)),
+		headers: headers,
+	}))
 }
 
 ///A codec that can be used with streams implementing `AsyncRead + AsyncWrite`
@@ -282,9 +396,29 @@ impl Decoder for HttpClientCodec {
 ///tokio::run(f.map(|_| ()).map_err(|_| ()));
 ///# }
 ///```
+///
+///Since the request head is read from an untrusted peer the codec gives up
+///on requests larger than `MAX_REQUEST_LINE` and `MAX_HEAD_SIZE`, wrap it in
+///`Limited` for other limits.
 #[derive(Copy, Clone, Debug)]
 pub struct HttpServerCodec;
 
+impl HttpServerCodec {
+	/// Encode a response followed by `body`, setting its `Content-Length`
+	/// header so the peer knows where the body ends.
+	pub fn encode_with_body(
+		&mut self,
+		mut item: ResponseHead,
+		body: &[u8],
+		dst: &mut BytesMut,
+	) -> Result<(), io::Error> {
+		item.headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+		self.encode(item, dst)?;
+		dst.extend_from_slice(body);
+		Ok(())
+	}
+}
+
 impl Encoder for HttpServerCodec {
 	type Item = ResponseHead;
 	type Error = io::Error;
@@ -304,69 +438,79 @@ impl Decoder for HttpServerCodec {
 	type Error = HttpCodecError;
 
 	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-		// check if we get a request from hyper
-		// TODO: this is ineffecient, but hyper does not give us a better way to parse
-		match split_off_http(src) {
-			Some(mut buf) => {
+		decode_request(src, &HttpLimits::default())
+	}
+}
 
-				if buf.len() == 0 {
-					return Ok(None);
-				}
+fn decode_request(src: &mut BytesMut, limits: &HttpLimits) -> Result<Option<RequestHead>, HttpCodecError> {
+	// check if we get a request from hyper
+	// TODO: this is ineffecient, but hyper does not give us a better way to parse
+	match split_off_http(src) {
+		Some(mut buf) => {
+			limits.check_request(&buf)?;
+
+			if buf.len() == 0 {
+				return Ok(None);
+			}
 
-				let mut headers_indices = [HeaderIndices {
-					name: (0, 0),
-					value: (0, 0),
-				}; MAX_HEADERS];
-
-				let (len, method, path, version, headers_len) = {
-					let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
-					//println!("Request.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
-					let mut req = httparse::Request::new(&mut headers);
-					match try!(req.parse(&buf)) {
-						httparse::Status::Complete(len) => {
-							//println!("Request.parse Complete({})", len);
-							let method = Method::from_bytes(req.method.unwrap().as_bytes())?;
-							let path = req.path.unwrap();
-							let bytes_ptr = buf.as_ref().as_ptr() as usize;
-							let path_start = path.as_ptr() as usize - bytes_ptr;
-							let path_end = path_start + path.len();
-							let path = (path_start, path_end);
-							let version = if req.version.unwrap() == 1 {
-								Version::HTTP_11
-							} else {
-								Version::HTTP_10
-							};
-
-							record_header_indices(buf.as_ref(), &req.headers, &mut headers_indices);
-							let headers_len = req.headers.len();
-							(len, method, path, version, headers_len)
-						}
-						httparse::Status::Partial => return Ok(None),
+			let mut headers_indices = [HeaderIndices {
+				name: (0, 0),
+				value: (0, 0),
+			}; MAX_HEADERS];
+
+			let (len, method, path, version, headers_len) = {
+				let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+				//println!("Request.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
+				let mut req = httparse::Request::new(&mut headers);
+				match try!(req.parse(&buf)) {
+					httparse::Status::Complete(len) => {
+						//println!("Request.parse Complete({})", len);
+						let method = Method::from_bytes(req.method.unwrap().as_bytes())?;
+						let path = req.path.unwrap();
+						let bytes_ptr = buf.as_ref().as_ptr() as usize;
+						let path_start = path.as_ptr() as usize - bytes_ptr;
+						let path_end = path_start + path.len();
+						let path = (path_start, path_end);
+						let version = if req.version.unwrap() == 1 {
+							Version::HTTP_11
+						} else {
+							Version::HTTP_10
+						};
+
+						record_header_indices(buf.as_ref(), &req.headers, &mut headers_indices);
+						let headers_len = req.headers.len();
+						(len, method, path, version, headers_len)
 					}
-				};
+					httparse::Status::Partial => return Ok(None),
+				}
+			};
 
-				let mut headers = HeaderMap::with_capacity(headers_len);
-				let slice = buf.split_to(len).freeze();
-				let path = slice.slice(path.0, path.1);
+			let mut headers = HeaderMap::with_capacity(headers_len);
+			let slice = buf.split_to(len).freeze();
+			let path = slice.slice(path.0, path.1);
 
-				// path was found to be utf8 by httparse
-				let path = Uri::from_shared(path)?;
-				let subject = RequestLine(method, path);
+			// path was found to be utf8 by httparse
+			let path = Uri::from_shared(path)?;
+			let subject = RequestLine(method, path);
 
-				headers.extend(HeadersAsBytesIter {
-					headers: headers_indices[..headers_len].iter(),
-					slice: slice,
-				});
+			headers.extend(HeadersAsBytesIter {
+				headers: headers_indices[..headers_len].iter(),
+				slice: slice,
+			});
 
 
-				Ok(Some(RequestHead {
-					version: version,
-					subject: subject,
-					headers: headers,
-				}))
+			Ok(Some(RequestHead {
+				version: version,
+				subject: subject,
+				headers: headers,
+			}))
 
-			}
-			None => Ok(None),
+		}
+		None => {
+			// the head is not complete yet, make sure the peer is not
+			// just trickling bytes at us forever
+			limits.check_request(&src)?;
+			Ok(None)
 		}
 	}
 }
@@ -386,6 +530,10 @@ pub enum HttpCodecError {
 	Header,
 	/// A message head is too large to be reasonable.
 	TooLarge,
+	/// The request line is longer than the codec's limit.
+	RequestLineTooLong,
+	/// The message head is larger than the codec's limit.
+	HeadersTooLarge,
 	/// An invalid `Status`, such as `1337 ELITE`.
 	Status,
 	/// An error that occurs during the writing or reading of HTTP data
@@ -407,6 +555,8 @@ impl Error for HttpCodecError {
 			HttpCodecError::Uri => "invalid URI",
 			HttpCodecError::Header => "invalid Header provided",
 			HttpCodecError::TooLarge => "message head is too large",
+			HttpCodecError::RequestLineTooLong => "request line is too long",
+			HttpCodecError::HeadersTooLarge => "message headers are too large",
 			HttpCodecError::Status => "invalid Status provided",
 			HttpCodecError::Io(ref e) => e.description(),
 		}
@@ -514,4 +664,61 @@ mod tests {
 			});
 		tokio::run(f.map(|_| ()).map_err(|_| ()));
 	}
+
+	#[test]
+	fn test_server_http_codec_limits() {
+		let mut codec = Limited::new(HttpServerCodec, HttpLimits {
+			max_request_line: 16,
+			max_head_size: 64,
+		});
+
+		let mut long_line = BytesMut::from(&b"GET /a/very/long/path/indeed"[..]);
+		match codec.decode(&mut long_line) {
+			Err(HttpCodecError::RequestLineTooLong) => (),
+			_ => panic!("expected the request line to be rejected"),
+		}
+
+		let mut trickle = BytesMut::from(&b"GET / HTTP/1.1\r\n"[..]);
+		assert!(codec.decode(&mut trickle).unwrap().is_none());
+		trickle.extend_from_slice(&[b'a'; 64][..]);
+		match codec.decode(&mut trickle) {
+			Err(HttpCodecError::HeadersTooLarge) => (),
+			_ => panic!("expected the headers to be rejected"),
+		}
+	}
+
+	#[test]
+	fn test_client_http_codec_limits() {
+		let mut codec = Limited::new(HttpClientCodec, HttpLimits {
+			max_request_line: 16,
+			max_head_size: 32,
+		});
+
+		let mut response = BytesMut::from(&b"HTTP/1.1 101 Switching Protocols\r\n"[..]);
+		response.extend_from_slice(&[b'a'; 16][..]);
+		match codec.decode(&mut response) {
+			Err(HttpCodecError::HeadersTooLarge) => (),
+			_ => panic!("expected the response head to be rejected"),
+		}
+
+		let mut response = BytesMut::from(&b"HTTP/1.1 404 Not Found\r\n\r\nextra"[..]);
+		let head = codec.decode(&mut response).unwrap().unwrap();
+		assert_eq!(head.subject, StatusCode::NOT_FOUND);
+		assert_eq!(&response[..], b"extra");
+	}
+
+	#[test]
+	fn test_server_http_codec_body() {
+		let mut headers = HeaderMap::new();
+		headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1000"));
+		let response = MessageHead {
+			version: Version::HTTP_11,
+			subject: StatusCode::UNAUTHORIZED,
+			headers: headers,
+		};
+
+		let mut buf = BytesMut::new();
+		HttpServerCodec.encode_with_body(response, b"{}", &mut buf).unwrap();
+		assert_eq!(&buf[..], &b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 2\r\n\r\n{}"[..]);
+	}
 }
@@ -0,0 +1,167 @@
+//! Send and receive values of your own types as JSON.
+//!
+//! `Json` is the `Format` for the `typed` module that most websocket
+//! services speak: any type that implements `Serialize` is sent as JSON in a
+//! text or binary message and received as any type that implements
+//! `DeserializeOwned`.
+//!
+//!```rust,no_run
+//!# extern crate websocket;
+//!# extern crate tokio;
+//!# use std::collections::HashMap;
+//!# fn main() {
+//!use websocket::ClientBuilder;
+//!use websocket::codec::json::{self, Framing};
+//!use websocket::codec::typed::{TypedError, TypedMessage};
+//!use websocket::futures::{Future, Sink, Stream};
+//!use tokio::reactor::Handle;
+//!
+//!// prices by symbol, any type that implements `Serialize` and
+//!// `Deserialize` will do
+//!type Quotes = HashMap<String, f64>;
+//!
+//!let f = ClientBuilder::new("ws://127.0.0.1:2794").unwrap()
+//!    .async_connect_insecure(&Handle::default())
+//!    .map(|(client, _)| json::typed::<_, Quotes>(client, Framing::Text))
+//!    .map_err(TypedError::from)
+//!    .and_then(|client| {
+//!        let mut quotes = Quotes::new();
+//!        quotes.insert("ACME".to_string(), 12.5);
+//!        client.send(quotes.into())
+//!    })
+//!    .and_then(|client| client.into_future().map_err(|e| e.0))
+//!    .map(|(message, _)| match message {
+//!        Some(TypedMessage::Data(quotes)) => println!("{:?}", quotes),
+//!        other => println!("{:?}", other),
+//!    });
+//!
+//!tokio::run(f.map_err(|e| println!("{}", e)));
+//!# }
+//!```
+//!
+//! The sync `Client` has `send_json` and `recv_json` to do the same.
+use serde_json;
+
+use codec::typed::{DeserializeOwned, Format, FormatError, Serialize, TypedCodec};
+use message::OwnedMessage;
+
+#[cfg(feature = "async")]
+use codec::typed;
+#[cfg(feature = "async")]
+use codec::ws::MessageCodec;
+#[cfg(feature = "async")]
+use stream::async::Stream as AsyncStream;
+#[cfg(feature = "async")]
+use tokio_codec::Framed;
+
+/// Whether values are sent in text or in binary messages.
+///
+/// Either kind is accepted when receiving.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Framing {
+	/// Send values as text messages.
+	Text,
+	/// Send values as binary messages holding UTF-8 JSON.
+	Binary,
+}
+
+impl Default for Framing {
+	fn default() -> Self {
+		Framing::Text
+	}
+}
+
+/// The JSON format, sending values in text messages unless told otherwise.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Json {
+	/// The kind of message values are sent in.
+	pub framing: Framing,
+}
+
+impl Json {
+	/// JSON sent in messages of the kind `framing` asks for.
+	pub fn new(framing: Framing) -> Self {
+		Json { framing: framing }
+	}
+}
+
+impl Format for Json {
+	fn encode<T>(&self, value: &T) -> Result<OwnedMessage, FormatError>
+	where
+		T: Serialize + ?Sized,
+	{
+		Ok(match self.framing {
+			Framing::Text => OwnedMessage::Text(serde_json::to_string(value)?),
+			Framing::Binary => OwnedMessage::Binary(serde_json::to_vec(value)?),
+		})
+	}
+
+	fn decode<T>(&self, payload: &[u8]) -> Result<T, FormatError>
+	where
+		T: DeserializeOwned,
+	{
+		Ok(serde_json::from_slice(payload)?)
+	}
+}
+
+/// A codec for values of type `T` sent as JSON over websocket messages.
+pub type JsonCodec<T> = TypedCodec<Json, T>;
+
+impl<T> TypedCodec<Json, T> {
+	/// Send values in text or in binary messages.
+	pub fn framing(mut self, framing: Framing) -> Self {
+		self.format_mut().framing = framing;
+		self
+	}
+}
+
+/// Turn an async `Client` into one that sends and receives values of type
+/// `T` as JSON, see `typed::typed`.
+#[cfg(feature = "async")]
+pub fn typed<S, T>(client: Framed<S, MessageCodec<OwnedMessage>>, framing: Framing) -> Framed<S, JsonCodec<T>>
+where
+	S: AsyncStream,
+	T: Serialize + DeserializeOwned,
+{
+	typed::typed(client, Json::new(framing))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use bytes::BytesMut;
+	use codec::typed::{decode, encode, TypedError, TypedMessage};
+	use codec::ws::Context;
+	use tokio_codec::{Decoder, Encoder};
+
+	// a symbol and a price
+	type Quote = (String, u32);
+
+	fn quote() -> Quote {
+		("ACME".to_string(), 125)
+	}
+
+	#[test]
+	fn codec_round_trips_values() {
+		let mut client = JsonCodec::<Quote>::new(Context::Client, Json::default()).framing(Framing::Binary);
+		let mut server = JsonCodec::<Quote>::new(Context::Server, Json::default());
+		let mut buf = BytesMut::new();
+
+		client.encode(quote().into(), &mut buf).unwrap();
+		client.encode(TypedMessage::Ping(vec![1]), &mut buf).unwrap();
+		assert_eq!(server.decode(&mut buf).unwrap(), Some(TypedMessage::Data(quote())));
+		assert_eq!(server.decode(&mut buf).unwrap(), Some(TypedMessage::Ping(vec![1])));
+		assert_eq!(server.decode(&mut buf).unwrap(), None);
+	}
+
+	#[test]
+	fn decode_failures_are_not_websocket_errors() {
+		match decode::<_, Quote>(&Json::default(), OwnedMessage::Text("[3, 125]".to_string())) {
+			Err(TypedError::Decode(_)) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+		let text = encode(&Json::default(), &TypedMessage::Data(quote())).unwrap();
+		assert_eq!(text, OwnedMessage::Text("[\"ACME\",125]".to_string()));
+		assert_eq!(decode::<_, Quote>(&Json::default(), text).unwrap(), TypedMessage::Data(quote()));
+	}
+}
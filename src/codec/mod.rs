@@ -9,6 +9,18 @@
 //! encoding and decoding websocket messages (and dataframes if you want to go
 //! more low level) in the `ws` module.
 //! See it's module level documentation for more info.
+//!
+//! With the `serde` feature the `typed` module adds a codec that sends and
+//! receives values of any serializable type in a format such as JSON, the
+//! `msgpack` and `cbor` features add MessagePack and CBOR.
 
 pub mod http;
 pub mod ws;
+#[cfg(feature = "serde")]
+pub mod typed;
+#[cfg(feature = "serde")]
+pub mod json;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+#[cfg(feature = "cbor")]
+pub mod cbor;
@@ -0,0 +1,61 @@
+//! Send and receive values of your own types as MessagePack.
+//!
+//! `MessagePack` is a `Format` for the `typed` module that sends values in
+//! binary messages, structs are encoded as maps with their field names so
+//! peers in other languages can read them.
+//!
+//!```rust,no_run
+//!# extern crate websocket;
+//!# fn main() {
+//!use websocket::ClientBuilder;
+//!use websocket::codec::msgpack::MessagePack;
+//!use websocket::codec::typed::TypedMessage;
+//!
+//!let mut client = ClientBuilder::new("ws://127.0.0.1:2794").unwrap()
+//!    .connect_insecure().unwrap();
+//!
+//!client.send_typed(&MessagePack, &(42u32, "temperature", 21.5f64)).unwrap();
+//!if let TypedMessage::Data(reading) = client.recv_typed::<_, (u32, String, f64)>(&MessagePack).unwrap() {
+//!    println!("{:?}", reading);
+//!}
+//!# }
+//!```
+use rmp_serde;
+
+use codec::typed::{DeserializeOwned, Format, FormatError, Serialize, TypedCodec};
+use message::OwnedMessage;
+
+/// The MessagePack format, sending values in binary messages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MessagePack;
+
+impl Format for MessagePack {
+	fn encode<T>(&self, value: &T) -> Result<OwnedMessage, FormatError>
+	where
+		T: Serialize + ?Sized,
+	{
+		Ok(OwnedMessage::Binary(rmp_serde::to_vec_named(&value)?))
+	}
+
+	fn decode<T>(&self, payload: &[u8]) -> Result<T, FormatError>
+	where
+		T: DeserializeOwned,
+	{
+		Ok(rmp_serde::from_slice(payload)?)
+	}
+}
+
+/// A codec for values of type `T` sent as MessagePack over websocket messages.
+pub type MessagePackCodec<T> = TypedCodec<MessagePack, T>;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sends_binary_messages() {
+		// a fixarray of a fixint and a fixstr
+		let message = MessagePack.encode(&(1u8, "a")).unwrap();
+		assert_eq!(message, OwnedMessage::Binary(vec![0x92, 0x01, 0xa1, b'a']));
+	}
+}
@@ -0,0 +1,305 @@
+//! Send and receive values of your own types in a serialization format.
+//!
+//! A `Format` turns values into websocket messages and the payload of
+//! messages back into values. JSON, MessagePack and CBOR come with the
+//! `serde`, `msgpack` and `cbor` features, other formats only need an
+//! implementation of `Format`:
+//!
+//!```rust
+//!# extern crate websocket;
+//!# extern crate serde_json;
+//!# fn main() {
+//!use websocket::OwnedMessage;
+//!use websocket::codec::typed::{DeserializeOwned, Format, FormatError, Serialize};
+//!
+//!/// JSON that is easy to read in a packet capture.
+//!struct PrettyJson;
+//!
+//!impl Format for PrettyJson {
+//!    fn encode<T>(&self, value: &T) -> Result<OwnedMessage, FormatError>
+//!    where
+//!        T: Serialize + ?Sized,
+//!    {
+//!        Ok(OwnedMessage::Text(serde_json::to_string_pretty(value)?))
+//!    }
+//!
+//!    fn decode<T>(&self, payload: &[u8]) -> Result<T, FormatError>
+//!    where
+//!        T: DeserializeOwned,
+//!    {
+//!        Ok(serde_json::from_slice(payload)?)
+//!    }
+//!}
+//!
+//!if let OwnedMessage::Text(text) = PrettyJson.encode(&vec![1, 2]).unwrap() {
+//!    assert_eq!(PrettyJson.decode::<Vec<u32>>(text.as_bytes()).unwrap(), vec![1, 2]);
+//!}
+//!# }
+//!```
+//!
+//! `TypedCodec` sits on top of the `MessageCodec` and sends and receives
+//! values in a format, `typed` turns an async `Client` into one that uses it.
+//! The sync `Client` has `send_typed` and `recv_typed` to do the same.
+//! Ping, pong and close messages are passed along as they are, so the
+//! connection can still be looked after.
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::marker::PhantomData;
+
+use bytes::BytesMut;
+pub use serde::Serialize;
+pub use serde::de::DeserializeOwned;
+use tokio_codec::{Decoder, Encoder};
+#[cfg(feature = "async")]
+use tokio_codec::{Framed, FramedParts};
+
+use codec::ws::{Context, MessageCodec};
+use message::{CloseData, OwnedMessage};
+use result::WebSocketError;
+#[cfg(feature = "async")]
+use stream::async::Stream as AsyncStream;
+
+/// The error a `Format` fails with.
+pub type FormatError = Box<Error + Send + Sync>;
+
+/// A way of putting values into websocket messages.
+pub trait Format {
+	/// Turn a value into a text or binary message.
+	///
+	/// The value may be unsized, like a `str` or a slice. A serializer that
+	/// wants a sized value can be handed `&value`, a reference is sized
+	/// whatever `T` is.
+	fn encode<T>(&self, value: &T) -> Result<OwnedMessage, FormatError>
+	where
+		T: Serialize + ?Sized;
+
+	/// Read a value from the payload of a text or binary message.
+	fn decode<T>(&self, payload: &[u8]) -> Result<T, FormatError>
+	where
+		T: DeserializeOwned;
+}
+
+/// A message of a connection that carries values of type `T`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TypedMessage<T> {
+	/// A value, sent in a text or binary message.
+	Data(T),
+	/// A ping message.
+	Ping(Vec<u8>),
+	/// A pong message.
+	Pong(Vec<u8>),
+	/// A close message.
+	Close(Option<CloseData>),
+}
+
+impl<T> From<T> for TypedMessage<T> {
+	fn from(value: T) -> Self {
+		TypedMessage::Data(value)
+	}
+}
+
+/// What can go wrong when sending or receiving values.
+#[derive(Debug)]
+pub enum TypedError {
+	/// The connection failed, see the `WebSocketError`.
+	WebSocket(WebSocketError),
+	/// A value could not be encoded.
+	Encode(FormatError),
+	/// A message did not hold a value of the expected type.
+	Decode(FormatError),
+}
+
+impl Display for TypedError {
+	fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+		match *self {
+			TypedError::WebSocket(ref e) => Display::fmt(e, fmt),
+			TypedError::Encode(ref e) => write!(fmt, "could not encode value: {}", e),
+			TypedError::Decode(ref e) => write!(fmt, "could not decode value: {}", e),
+		}
+	}
+}
+
+impl Error for TypedError {
+	fn description(&self) -> &str {
+		match *self {
+			TypedError::WebSocket(ref e) => e.description(),
+			TypedError::Encode(_) => "encoding failure",
+			TypedError::Decode(_) => "decoding failure",
+		}
+	}
+
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			TypedError::WebSocket(ref e) => Some(e),
+			TypedError::Encode(ref e) | TypedError::Decode(ref e) => Some(&**e),
+		}
+	}
+}
+
+impl From<WebSocketError> for TypedError {
+	fn from(err: WebSocketError) -> TypedError {
+		TypedError::WebSocket(err)
+	}
+}
+
+impl From<io::Error> for TypedError {
+	fn from(err: io::Error) -> TypedError {
+		TypedError::WebSocket(err.into())
+	}
+}
+
+/// Turn a typed message into the message that is sent.
+pub fn encode<F, T>(format: &F, message: &TypedMessage<T>) -> Result<OwnedMessage, TypedError>
+where
+	F: Format,
+	T: Serialize,
+{
+	Ok(match *message {
+		TypedMessage::Data(ref value) => format.encode(value).map_err(TypedError::Encode)?,
+		TypedMessage::Ping(ref data) => OwnedMessage::Ping(data.clone()),
+		TypedMessage::Pong(ref data) => OwnedMessage::Pong(data.clone()),
+		TypedMessage::Close(ref close) => OwnedMessage::Close(close.clone()),
+	})
+}
+
+/// Turn a received message into a typed one.
+pub fn decode<F, T>(format: &F, message: OwnedMessage) -> Result<TypedMessage<T>, TypedError>
+where
+	F: Format,
+	T: DeserializeOwned,
+{
+	Ok(match message {
+		OwnedMessage::Text(text) => TypedMessage::Data(format.decode(text.as_bytes()).map_err(TypedError::Decode)?),
+		OwnedMessage::Binary(data) => TypedMessage::Data(format.decode(&data).map_err(TypedError::Decode)?),
+		OwnedMessage::Ping(data) => TypedMessage::Ping(data),
+		OwnedMessage::Pong(data) => TypedMessage::Pong(data),
+		OwnedMessage::Close(close) => TypedMessage::Close(close),
+	})
+}
+
+/// A codec for values of type `T` sent in the format `F`.
+///
+/// A message that doesn't decode fails with `TypedError::Decode`, the codec
+/// can go on with the next message after that.
+pub struct TypedCodec<F, T> {
+	messages: MessageCodec<OwnedMessage>,
+	format: F,
+	value_type: PhantomData<fn(T) -> T>,
+}
+
+impl<F, T> TypedCodec<F, T>
+where
+	F: Format,
+{
+	/// Create a codec for the `context` side of a connection.
+	pub fn new(context: Context, format: F) -> Self {
+		TypedCodec::with_codec(MessageCodec::default(context), format)
+	}
+
+	/// Create a codec on top of an existing message codec, keeping what it
+	/// already read of a message.
+	pub fn with_codec(messages: MessageCodec<OwnedMessage>, format: F) -> Self {
+		TypedCodec {
+			messages: messages,
+			format: format,
+			value_type: PhantomData,
+		}
+	}
+
+	/// The format values are sent in.
+	pub fn format(&self) -> &F {
+		&self.format
+	}
+
+	/// Change the format values are sent in.
+	pub fn format_mut(&mut self) -> &mut F {
+		&mut self.format
+	}
+}
+
+impl<F, T> Decoder for TypedCodec<F, T>
+where
+	F: Format,
+	T: DeserializeOwned,
+{
+	type Item = TypedMessage<T>;
+	type Error = TypedError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		match self.messages.decode(src)? {
+			Some(message) => decode(&self.format, message).map(Some),
+			None => Ok(None),
+		}
+	}
+}
+
+impl<F, T> Encoder for TypedCodec<F, T>
+where
+	F: Format,
+	T: Serialize,
+{
+	type Item = TypedMessage<T>;
+	type Error = TypedError;
+
+	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		let message = encode(&self.format, &item)?;
+		Ok(self.messages.encode(message, dst)?)
+	}
+}
+
+/// Turn a connection using the `MessageCodec`, like an async `Client`, into
+/// one that sends and receives values of type `T` in `format`.
+///
+/// Whatever was already read or buffered for writing is kept.
+#[cfg(feature = "async")]
+pub fn typed<S, F, T>(client: Framed<S, MessageCodec<OwnedMessage>>, format: F) -> Framed<S, TypedCodec<F, T>>
+where
+	S: AsyncStream,
+	F: Format,
+	T: Serialize + DeserializeOwned,
+{
+	let FramedParts { io, codec, read_buf, write_buf, .. } = client.into_parts();
+	let mut parts = FramedParts::new(io, TypedCodec::with_codec(codec, format));
+	parts.read_buf = read_buf;
+	parts.write_buf = write_buf;
+	Framed::from_parts(parts)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[cfg(feature = "cbor")]
+	use codec::cbor::Cbor;
+	use codec::json::{Framing, Json};
+	#[cfg(feature = "msgpack")]
+	use codec::msgpack::MessagePack;
+
+	fn round_trips<F: Format>(format: F) {
+		let reading = (42u32, "temperature".to_string(), 21.5f64);
+		let message = encode(&format, &TypedMessage::Data(reading.clone())).unwrap();
+		assert_eq!(decode(&format, message).unwrap(), TypedMessage::Data(reading));
+
+		let slice = format.encode(&[1u32, 2][..]).unwrap();
+		assert_eq!(decode(&format, slice).unwrap(), TypedMessage::Data(vec![1u32, 2]));
+
+		let ping = encode(&format, &TypedMessage::<u32>::Ping(vec![1])).unwrap();
+		assert_eq!(ping, OwnedMessage::Ping(vec![1]));
+		assert_eq!(decode::<_, u32>(&format, ping).unwrap(), TypedMessage::Ping(vec![1]));
+
+		match decode::<_, u32>(&format, OwnedMessage::Binary(vec![0xff])) {
+			Err(TypedError::Decode(_)) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn formats_round_trip_values() {
+		round_trips(Json::new(Framing::Text));
+		round_trips(Json::new(Framing::Binary));
+		#[cfg(feature = "msgpack")]
+		round_trips(MessagePack);
+		#[cfg(feature = "cbor")]
+		round_trips(Cbor);
+	}
+}
@@ -98,6 +98,99 @@ impl DataFrame {
 
 		DataFrame::read_dataframe_body(header, data, should_be_masked)
 	}
+
+	/// Reads a DataFrame from a Reader that may run out of data partway through,
+	/// like a non-blocking stream or one with a read timeout.
+	///
+	/// The bytes of an unfinished frame are kept in `partial` and `Ok(None)` is
+	/// returned when the reader would block or times out, call this again with
+	/// the same buffer to pick up where it left off. `partial` may also hold
+	/// more than one frame, whatever follows the returned frame stays in it.
+	pub fn read_dataframe_partial<R>(
+		reader: &mut R,
+		should_be_masked: bool,
+		partial: &mut Vec<u8>,
+	) -> WebSocketResult<Option<Self>>
+	where
+		R: Read,
+	{
+		// the header is at most 14 bytes, gather it in `partial` first
+		let header_len = loop {
+			let (needed, complete) = header_len(partial);
+			if complete && partial.len() >= needed {
+				break needed;
+			}
+
+			let start = partial.len();
+			partial.resize(needed, 0);
+			let read = reader.read(&mut partial[start..]);
+			match read {
+				Ok(0) => {
+					partial.truncate(start);
+					return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete frame").into());
+				}
+				Ok(n) => partial.truncate(start + n),
+				Err(e) => {
+					partial.truncate(start);
+					match e.kind() {
+						io::ErrorKind::Interrupted => (),
+						io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => return Ok(None),
+						_ => return Err(e.into()),
+					}
+				}
+			}
+		};
+
+		let header = match dfh::read_header(&mut &partial[..header_len]) {
+			Ok(header) => header,
+			Err(e) => {
+				partial.drain(..header_len);
+				return Err(e);
+			}
+		};
+
+		// the payload is read straight into the frame's buffer, and only goes
+		// back into `partial` if the reader runs dry before it is complete
+		let mut data = partial.split_off(header_len);
+		if data.len() as u64 >= header.len {
+			*partial = data.split_off(header.len as usize);
+			return DataFrame::read_dataframe_body(header, data, should_be_masked).map(Some);
+		}
+
+		let missing = header.len - data.len() as u64;
+		match reader.take(missing).read_to_end(&mut data) {
+			Ok(read) if (read as u64) < missing => {
+				partial.append(&mut data);
+				Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete payload").into())
+			}
+			Ok(_) => {
+				partial.clear();
+				DataFrame::read_dataframe_body(header, data, should_be_masked).map(Some)
+			}
+			Err(e) => {
+				partial.append(&mut data);
+				match e.kind() {
+					io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Ok(None),
+					_ => Err(e.into()),
+				}
+			}
+		}
+	}
+}
+
+/// How long the header starting at `bytes` is, as far as can be told from what
+/// is there, and whether that is the final length.
+fn header_len(bytes: &[u8]) -> (usize, bool) {
+	if bytes.len() < 2 {
+		return (2, false);
+	}
+	let mask = if bytes[1] & 0x80 == 0x80 { 4 } else { 0 };
+	let len = match bytes[1] & 0x7F {
+		126 => 4,
+		127 => 10,
+		_ => 2,
+	};
+	(len + mask, true)
 }
 
 impl DataFrameable for DataFrame {
@@ -238,6 +331,36 @@ mod tests {
 		assert_eq!(more_payload.unwrap().data, payload);
 	}
 
+	#[test]
+	fn read_dataframe_partial_resumes() {
+		// hands out a byte at a time, blocking in between
+		struct Trickle<'a>(&'a [u8], bool);
+		impl<'a> Read for Trickle<'a> {
+			fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+				self.1 = !self.1;
+				if self.1 {
+					return Err(io::ErrorKind::WouldBlock.into());
+				}
+				(&mut self.0).take(1).read(buf)
+			}
+		}
+
+		let mut data = vec![0x81, 0x7E, 0x00, 0x80];
+		data.extend(vec![b'a'; 128]);
+		let mut reader = Trickle(&data, false);
+		let mut partial = Vec::new();
+		let mut blocked = 0;
+		let frame = loop {
+			match DataFrame::read_dataframe_partial(&mut reader, false, &mut partial).unwrap() {
+				Some(frame) => break frame,
+				None => blocked += 1,
+			}
+		};
+		assert_eq!(blocked, data.len());
+		assert_eq!(frame.data, vec![b'a'; 128]);
+		assert!(partial.is_empty());
+	}
+
 	#[bench]
 	fn bench_read_dataframe(b: &mut Bencher) {
 		let data = b"The quick brown fox jumps over the lazy dog";
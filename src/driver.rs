@@ -0,0 +1,139 @@
+//! What the drivers of `jsonrpc` and `mux` share: the links that keep a
+//! connection open and the queue of messages waiting to be written.
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Async, AsyncSink, Poll, Sink};
+use futures::sync::mpsc::UnboundedSender;
+
+use client::async::Client;
+use message::OwnedMessage;
+use result::WebSocketError;
+use stream::async::Stream as AsyncStream;
+
+/// The commands the handles send their driver.
+pub trait Command {
+	/// The error of a handle whose driver is gone.
+	type Error;
+
+	/// Tells the driver the last link is gone.
+	fn release() -> Self;
+
+	/// The error to send with once the driver is gone.
+	fn closed() -> Self::Error;
+}
+
+/// Keeps the connection open, every handle has one. The last one to go
+/// tells the driver.
+pub struct Link<C: Command> {
+	commands: UnboundedSender<C>,
+	users: Arc<AtomicUsize>,
+}
+
+impl<C: Command> Link<C> {
+	pub fn new(commands: &UnboundedSender<C>, users: &Arc<AtomicUsize>) -> Link<C> {
+		users.fetch_add(1, Ordering::SeqCst);
+		Link {
+			commands: commands.clone(),
+			users: users.clone(),
+		}
+	}
+
+	pub fn send(&self, command: C) -> Result<(), C::Error> {
+		self.commands.unbounded_send(command).map_err(|_| C::closed())
+	}
+}
+
+impl<C: Command> Clone for Link<C> {
+	fn clone(&self) -> Link<C> {
+		Link::new(&self.commands, &self.users)
+	}
+}
+
+impl<C: Command> Drop for Link<C> {
+	fn drop(&mut self) {
+		if self.users.fetch_sub(1, Ordering::SeqCst) == 1 {
+			let _ = self.commands.unbounded_send(C::release());
+		}
+	}
+}
+
+/// The messages a driver has yet to write, nothing is queued after the
+/// close.
+pub struct Outbox {
+	queue: VecDeque<OwnedMessage>,
+	close_sent: bool,
+}
+
+impl Outbox {
+	pub fn new() -> Outbox {
+		Outbox {
+			queue: VecDeque::new(),
+			close_sent: false,
+		}
+	}
+
+	pub fn push(&mut self, message: OwnedMessage) {
+		if !self.close_sent {
+			self.queue.push_back(message);
+		}
+	}
+
+	pub fn close(&mut self) {
+		if !self.close_sent {
+			self.queue.push_back(OwnedMessage::Close(None));
+			self.close_sent = true;
+		}
+	}
+
+	pub fn close_sent(&self) -> bool {
+		self.close_sent
+	}
+
+	/// Write what the client takes, ready once everything queued was
+	/// flushed.
+	pub fn write<S>(&mut self, client: &mut Client<S>) -> Poll<(), WebSocketError>
+	where
+		S: AsyncStream,
+	{
+		while let Some(message) = self.queue.pop_front() {
+			if let AsyncSink::NotReady(message) = client.start_send(message)? {
+				self.queue.push_front(message);
+				break;
+			}
+		}
+		match client.poll_complete()? {
+			Async::Ready(()) if self.queue.is_empty() => Ok(Async::Ready(())),
+			_ => Ok(Async::NotReady),
+		}
+	}
+}
+
+#[cfg(test)]
+pub use self::tests::pair;
+
+#[cfg(test)]
+mod tests {
+	use futures::{Future, Stream};
+	use tokio::reactor::Handle;
+	use client::async::Client;
+	use client::builder::ClientBuilder;
+	use result::WebSocketError;
+	use server::async::Server;
+	use stream::async::TcpStream;
+
+	pub type Pair = Box<Future<Item = (Client<TcpStream>, Client<TcpStream>), Error = WebSocketError> + Send>;
+
+	/// A client and the server's side of its connection.
+	pub fn pair() -> Pair {
+		let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		let accept = server.incoming()
+		                   .into_future()
+		                   .map_err(|(e, _)| e.error.into())
+		                   .and_then(|(upgrade, _)| upgrade.unwrap().0.accept());
+		let connect = ClientBuilder::new(&url).unwrap().async_connect_insecure(&Handle::default());
+		Box::new(connect.join(accept).map(|((client, _), (server, _))| (client, server)))
+	}
+}
@@ -0,0 +1,758 @@
+//! Serve and connect many websockets from a single thread with mio.
+//!
+//! The sync `Client` needs a thread per connection and the async one needs
+//! all of tokio. A `Manager` sits in between: it owns any number of server and
+//! client sockets, drives their handshakes and frames with one `mio::Poll`, and
+//! hands out what happened as a list of `Event`s whenever it is polled.
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # fn main() {
+//! use websocket::OwnedMessage;
+//! use websocket::evented::{Event, Manager};
+//!
+//! let mut manager = Manager::new().unwrap();
+//! manager.listen(&"127.0.0.1:2794".parse().unwrap()).unwrap();
+//!
+//! let mut events = Vec::new();
+//! loop {
+//!     manager.poll(&mut events, None).unwrap();
+//!     for event in events.drain(..) {
+//!         match event {
+//!             Event::Message(id, message) => {
+//!                 let _ = manager.send(id, &message);
+//!             }
+//!             Event::Closed(id, _) => println!("{:?} is gone", id),
+//!             _ => (),
+//!         }
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Pings are answered and close frames are echoed by the manager. Handshakes
+//! are given up after the timeout of the `HandshakeConfig`, closing handshakes
+//! after `CLOSE_TIMEOUT_SECS`. Only plain TCP is supported, there is no TLS.
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use std::convert::TryFrom;
+
+use bytes::BytesMut;
+use http::StatusCode;
+use http::header::{HeaderMap, HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
+                   UPGRADE};
+use mio::{Events as MioEvents, Poll, PollOpt, Ready, Token};
+use mio::net::{TcpListener, TcpStream};
+use tokio_codec::{Decoder, Encoder};
+
+use client::builder::ClientBuilder;
+use codec::http::{HttpClientCodec, HttpServerCodec, Limited, RequestHead, ResponseHead};
+use header::{WebSocketAccept, WebSocketKey};
+use message::{CloseData, OwnedMessage};
+use receiver::Receiver;
+use result::{WebSocketError, WebSocketResult, WSUrlErrorKind};
+use sender::Sender;
+use server::upgrade::{self, HandshakeConfig, HyperIntoWsError};
+use ws;
+
+const LISTENER: Token = Token(0);
+const READ_CHUNK: usize = 4096;
+
+/// How long a connection that is closing waits for the peer's close frame
+/// and for what is left to be written, in seconds.
+pub const CLOSE_TIMEOUT_SECS: u64 = 5;
+
+/// Identifies one of the connections of a `Manager`.
+///
+/// Ids are reused once a connection is closed and its `Closed` event was handed out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ConnectionId(usize);
+
+/// Something that happened to a connection of a `Manager`.
+#[derive(Debug)]
+pub enum Event {
+	/// A client finished its handshake with the listener, along with its request.
+	Accepted(ConnectionId, RequestHead),
+	/// A connection made with `connect` finished its handshake, along with the
+	/// headers of the server's response.
+	Connected(ConnectionId, HeaderMap),
+	/// A text, binary or pong message arrived.
+	Message(ConnectionId, OwnedMessage),
+	/// Something went wrong with the connection, `Closed` follows.
+	Error(ConnectionId, WebSocketError),
+	/// The connection is over, with the close frame the peer sent if it sent one.
+	/// This is the last event of a connection.
+	Closed(ConnectionId, Option<CloseData>),
+}
+
+enum State {
+	ServerHandshake(Limited<HttpServerCodec>),
+	ClientHandshake(HttpClientCodec, ClientBuilder<'static>),
+	Open,
+}
+
+struct Connection {
+	stream: TcpStream,
+	state: State,
+	input: BytesMut,
+	output: Vec<u8>,
+	receiver: Receiver,
+	sender: Sender,
+	/// whether we sent a close frame
+	closing: bool,
+	/// whether the connection is to be dropped once the output is written
+	finished: bool,
+	/// when the handshake or the closing is given up
+	deadline: Option<Instant>,
+}
+
+/// Drives many websocket connections from a single `mio::Poll`.
+pub struct Manager {
+	poll: Poll,
+	events: MioEvents,
+	listener: Option<TcpListener>,
+	connections: Vec<Option<Connection>>,
+	config: HandshakeConfig,
+}
+
+impl Manager {
+	/// Create a manager without any connections.
+	pub fn new() -> io::Result<Self> {
+		Ok(Manager {
+			poll: Poll::new()?,
+			events: MioEvents::with_capacity(1024),
+			listener: None,
+			connections: Vec::new(),
+			config: HandshakeConfig::default(),
+		})
+	}
+
+	/// Set the size limits incoming handshakes are read with and how long
+	/// handshakes, those of `connect` as well, may take. A connection whose
+	/// handshake takes longer gets an `Error` and is closed.
+	pub fn handshake_config(&mut self, config: HandshakeConfig) {
+		self.config = config;
+	}
+
+	/// Accept websocket clients on `addr`. A manager has at most one listener,
+	/// a second call replaces the first.
+	pub fn listen(&mut self, addr: &SocketAddr) -> io::Result<SocketAddr> {
+		let listener = TcpListener::bind(addr)?;
+		self.poll.register(&listener, LISTENER, Ready::readable(), PollOpt::edge())?;
+		let local = listener.local_addr()?;
+		self.listener = Some(listener);
+		Ok(local)
+	}
+
+	/// Start connecting to the server `builder` points to. The connection can
+	/// be used once its `Connected` event arrives.
+	///
+	/// Resolving the host name blocks, and only `ws://` URLs are supported,
+	/// others fail with `WSUrlErrorKind::InvalidScheme`.
+	pub fn connect(&mut self, mut builder: ClientBuilder<'static>) -> WebSocketResult<ConnectionId> {
+		if builder.scheme() != "ws" {
+			return Err(WebSocketError::WebSocketUrlError(WSUrlErrorKind::InvalidScheme));
+		}
+		let request = builder.handshake_request()?;
+		let addr = {
+			let host_port = builder.extract_host_port(Some(false))?;
+			host_port.to_socket_addrs()?.next().ok_or_else(|| {
+				io::Error::new(io::ErrorKind::NotFound, "the host has no addresses")
+			})?
+		};
+
+		let mut codec = HttpClientCodec;
+		let mut output = BytesMut::new();
+		codec.encode(request, &mut output)?;
+
+		let stream = TcpStream::connect(&addr)?;
+		let id = self.add(stream, State::ClientHandshake(codec, builder), true)?;
+		if let Some(connection) = self.connection(id) {
+			// written once the socket says it is connected
+			connection.output.extend_from_slice(&output);
+		}
+		Ok(id)
+	}
+
+	/// Send a message to a connection whose handshake is done.
+	pub fn send<M>(&mut self, id: ConnectionId, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		let connection = match self.connection(id) {
+			Some(connection) => connection,
+			None => return Err(gone()),
+		};
+		match connection.state {
+			State::Open if !connection.closing && !connection.finished => (),
+			_ => return Err(gone()),
+		}
+		let masked = ws::Sender::is_masked(&connection.sender);
+		message.serialize(&mut connection.output, masked)?;
+		Ok(connection.flush()?)
+	}
+
+	/// Start closing a connection, its `Closed` event follows once the peer
+	/// answered.
+	pub fn close(&mut self, id: ConnectionId, close: Option<CloseData>) -> WebSocketResult<()> {
+		self.send(id, &OwnedMessage::Close(close))?;
+		if let Some(connection) = self.connection(id) {
+			connection.closing = true;
+			connection.deadline = Some(Instant::now() + Duration::from_secs(CLOSE_TIMEOUT_SECS));
+		}
+		Ok(())
+	}
+
+	/// Drop a connection right away, without a closing handshake or a
+	/// `Closed` event.
+	pub fn disconnect(&mut self, id: ConnectionId) {
+		self.remove(id);
+	}
+
+	/// The address of a connection's peer.
+	pub fn peer_addr(&mut self, id: ConnectionId) -> io::Result<SocketAddr> {
+		match self.connection(id) {
+			Some(connection) => connection.stream.peer_addr(),
+			None => Err(io::Error::new(io::ErrorKind::NotFound, "no such connection")),
+		}
+	}
+
+	/// Wait for something to happen, up to `timeout`, and add what did to `events`.
+	pub fn poll(&mut self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+		// wake up in time to give up on a handshake
+		let now = Instant::now();
+		let timeout = match self.connections.iter().filter_map(|c| c.as_ref().and_then(|c| c.deadline)).min() {
+			Some(deadline) => {
+				let left = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+				Some(timeout.map_or(left, |timeout| cmp::min(timeout, left)))
+			}
+			None => timeout,
+		};
+		self.poll.poll(&mut self.events, timeout)?;
+
+		let ready = self.events
+		                .iter()
+		                .map(|event| (event.token(), event.readiness()))
+		                .collect::<Vec<_>>();
+		for (token, readiness) in ready {
+			if token == LISTENER {
+				self.accept(events)?;
+				continue;
+			}
+
+			let id = ConnectionId(token.0 - 1);
+			let config = self.config;
+			let done = match self.connection(id) {
+				Some(connection) => {
+					if readiness.is_writable() {
+						if let Err(e) = connection.flush() {
+							connection.fail(id, e.into(), events);
+						}
+					}
+					if readiness.is_readable() && !connection.finished {
+						connection.read(id, &config, events);
+					}
+					connection.finished && connection.output.is_empty()
+				}
+				None => false,
+			};
+			if done {
+				self.remove(id);
+			}
+		}
+		self.expire(events);
+		Ok(())
+	}
+
+	/// Give up on the connections whose deadline passed.
+	fn expire(&mut self, events: &mut Vec<Event>) {
+		let now = Instant::now();
+		for index in 0..self.connections.len() {
+			let id = ConnectionId(index);
+			let done = match self.connection(id) {
+				Some(connection) => {
+					if connection.deadline.map_or(false, |deadline| deadline <= now) {
+						connection.expire(id, events)
+					} else {
+						false
+					}
+				}
+				None => false,
+			};
+			if done {
+				self.remove(id);
+			}
+		}
+	}
+
+	fn accept(&mut self, events: &mut Vec<Event>) -> io::Result<()> {
+		loop {
+			let accepted = match self.listener {
+				Some(ref listener) => listener.accept(),
+				None => return Ok(()),
+			};
+			let stream = match accepted {
+				Ok((stream, _)) => stream,
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+				Err(e) => return Err(e),
+			};
+			let codec = self.config.codec();
+			let id = self.add(stream, State::ServerHandshake(codec), false)?;
+
+			// the request may already be there
+			let config = self.config;
+			if let Some(connection) = self.connection(id) {
+				connection.read(id, &config, events);
+			}
+		}
+	}
+
+	fn add(&mut self, stream: TcpStream, state: State, client: bool) -> io::Result<ConnectionId> {
+		let index = match self.connections.iter().position(Option::is_none) {
+			Some(index) => index,
+			None => {
+				self.connections.push(None);
+				self.connections.len() - 1
+			}
+		};
+		self.poll.register(
+			&stream,
+			Token(index + 1),
+			Ready::readable() | Ready::writable(),
+			PollOpt::edge(),
+		)?;
+		let deadline = self.config.timeout.map(|timeout| Instant::now() + timeout);
+		self.connections[index] = Some(Connection {
+			stream: stream,
+			state: state,
+			input: BytesMut::new(),
+			output: Vec::new(),
+			receiver: Receiver::new(!client),
+			sender: Sender::new(client),
+			closing: false,
+			finished: false,
+			deadline: deadline,
+		});
+		Ok(ConnectionId(index))
+	}
+
+	fn connection(&mut self, id: ConnectionId) -> Option<&mut Connection> {
+		self.connections.get_mut(id.0).and_then(Option::as_mut)
+	}
+
+	fn remove(&mut self, id: ConnectionId) {
+		if let Some(connection) = self.connections.get_mut(id.0).and_then(Option::take) {
+			let _ = self.poll.deregister(&connection.stream);
+			let _ = connection.stream.shutdown(Shutdown::Both);
+		}
+	}
+}
+
+impl Connection {
+	fn read(&mut self, id: ConnectionId, config: &HandshakeConfig, events: &mut Vec<Event>) {
+		match self.try_read(id, config, events) {
+			Ok(()) => (),
+			// the peer hung up, which is how many clients end a connection
+			Err(WebSocketError::NoDataAvailable) => self.finish(id, None, events),
+			Err(e) => self.fail(id, e, events),
+		}
+	}
+
+	fn try_read(
+		&mut self,
+		id: ConnectionId,
+		config: &HandshakeConfig,
+		events: &mut Vec<Event>,
+	) -> WebSocketResult<()> {
+		loop {
+			let open = match self.state {
+				State::Open => true,
+				_ => false,
+			};
+			if open {
+				return self.read_messages(id, events);
+			}
+
+			// never buffer more of a handshake than the codecs would allow
+			let eof = self.fill(config.max_head_size + 1)?;
+			if !self.handshake(id, events)? {
+				if eof {
+					self.finish(id, None, events);
+				}
+				return Ok(());
+			}
+		}
+	}
+
+	/// Read from the socket into `input` until it would block or `limit` bytes
+	/// are buffered. Returns whether the peer closed the connection.
+	fn fill(&mut self, limit: usize) -> io::Result<bool> {
+		let mut chunk = [0; READ_CHUNK];
+		while self.input.len() < limit {
+			match self.stream.read(&mut chunk) {
+				Ok(0) => return Ok(true),
+				Ok(n) => self.input.extend_from_slice(&chunk[..n]),
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(false)
+	}
+
+	/// Try to finish the handshake with what is in `input`, returns whether it is done.
+	fn handshake(&mut self, id: ConnectionId, events: &mut Vec<Event>) -> WebSocketResult<bool> {
+		let state = ::std::mem::replace(&mut self.state, State::Open);
+		match state {
+			State::ServerHandshake(mut codec) => {
+				let request = match codec.decode(&mut self.input) {
+					Ok(Some(request)) => request,
+					Ok(None) => {
+						self.state = State::ServerHandshake(codec);
+						return Ok(false);
+					}
+					Err(e) => {
+						self.reject(id, HyperIntoWsError::from(e), events);
+						return Ok(false);
+					}
+				};
+				if let Err(e) = upgrade::validate(&request.subject.0, &request.version, &request.headers) {
+					self.reject(id, e, events);
+					return Ok(false);
+				}
+				let response = ResponseHead {
+					version: request.version,
+					subject: StatusCode::SWITCHING_PROTOCOLS,
+					headers: accept_headers(&request.headers),
+				};
+				let mut buf = BytesMut::new();
+				HttpServerCodec.encode(response, &mut buf)?;
+				self.output.extend_from_slice(&buf);
+				self.flush()?;
+				self.deadline = None;
+				events.push(Event::Accepted(id, request));
+				Ok(true)
+			}
+			State::ClientHandshake(mut codec, builder) => {
+				let response = match codec.decode(&mut self.input)? {
+					Some(response) => response,
+					None => {
+						self.state = State::ClientHandshake(codec, builder);
+						return Ok(false);
+					}
+				};
+				builder.validate(&response)?;
+				self.deadline = None;
+				events.push(Event::Connected(id, response.headers));
+				Ok(true)
+			}
+			State::Open => Ok(true),
+		}
+	}
+
+	/// Answer a bad handshake and drop the connection once that is written.
+	fn reject(&mut self, id: ConnectionId, error: HyperIntoWsError, events: &mut Vec<Event>) {
+		let response = error.response().unwrap_or_else(|| {
+			let mut headers = HeaderMap::new();
+			headers.insert(CONNECTION, HeaderValue::from_static("close"));
+			ResponseHead {
+				version: ::http::Version::HTTP_11,
+				subject: StatusCode::BAD_REQUEST,
+				headers: headers,
+			}
+		});
+		let mut buf = BytesMut::new();
+		if HttpServerCodec.encode_with_body(response, &[], &mut buf).is_ok() {
+			self.output.extend_from_slice(&buf);
+			let _ = self.flush();
+		}
+		events.push(Event::Error(id, error.into()));
+		self.finish(id, None, events);
+	}
+
+	fn read_messages(&mut self, id: ConnectionId, events: &mut Vec<Event>) -> WebSocketResult<()> {
+		loop {
+			let message = {
+				let mut source = Source {
+					buffered: &mut self.input,
+					stream: &mut self.stream,
+				};
+				match self.receiver.try_recv_message(&mut source) {
+					Ok(Some(message)) => message,
+					Ok(None) => return Ok(()),
+					Err(e) => return Err(e),
+				}
+			};
+			match message {
+				OwnedMessage::Ping(data) => {
+					let masked = ws::Sender::is_masked(&self.sender);
+					ws::Message::serialize(&OwnedMessage::Pong(data), &mut self.output, masked)?;
+					self.flush()?;
+				}
+				OwnedMessage::Close(close) => {
+					if !self.closing {
+						let masked = ws::Sender::is_masked(&self.sender);
+						ws::Message::serialize(&OwnedMessage::Close(None), &mut self.output, masked)?;
+						self.closing = true;
+						self.flush()?;
+					}
+					self.finish(id, close, events);
+					return Ok(());
+				}
+				message => events.push(Event::Message(id, message)),
+			}
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		while !self.output.is_empty() {
+			match self.stream.write(&self.output) {
+				Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+				Ok(n) => {
+					self.output.drain(..n);
+				}
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+				// a client socket may not be connected yet
+				Err(ref e) if e.kind() == io::ErrorKind::NotConnected => return Ok(()),
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(())
+	}
+
+	fn fail(&mut self, id: ConnectionId, error: WebSocketError, events: &mut Vec<Event>) {
+		if self.finished {
+			return;
+		}
+		events.push(Event::Error(id, error));
+		// the connection is broken, nothing more is going to get through
+		self.output.clear();
+		self.finish(id, None, events);
+	}
+
+	fn finish(&mut self, id: ConnectionId, close: Option<CloseData>, events: &mut Vec<Event>) {
+		if self.finished {
+			return;
+		}
+		self.finished = true;
+		// the peer may never read what is left
+		self.deadline = Some(Instant::now() + Duration::from_secs(CLOSE_TIMEOUT_SECS));
+		events.push(Event::Closed(id, close));
+	}
+
+	/// Give up on the handshake or the closing, returns whether the
+	/// connection can be dropped.
+	fn expire(&mut self, id: ConnectionId, events: &mut Vec<Event>) -> bool {
+		if self.finished {
+			return true;
+		}
+		match self.state {
+			State::ServerHandshake(_) => self.reject(id, HyperIntoWsError::Timeout, events),
+			State::ClientHandshake(..) => {
+				let error = io::Error::new(io::ErrorKind::TimedOut, "the handshake timed out");
+				self.fail(id, error.into(), events);
+			}
+			// the peer didn't answer the close
+			State::Open => {
+				self.output.clear();
+				self.finish(id, None, events);
+			}
+		}
+		self.output.is_empty()
+	}
+}
+
+/// Reads what is left over from the handshake before reading from the socket.
+struct Source<'a> {
+	buffered: &'a mut BytesMut,
+	stream: &'a mut TcpStream,
+}
+
+impl<'a> Read for Source<'a> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.buffered.is_empty() {
+			return self.stream.read(buf);
+		}
+		let n = cmp::min(buf.len(), self.buffered.len());
+		buf[..n].copy_from_slice(&self.buffered.split_to(n));
+		Ok(n)
+	}
+}
+
+fn accept_headers(request: &HeaderMap) -> HeaderMap {
+	let mut headers = HeaderMap::new();
+	// validated before this is called
+	let key = request.get(SEC_WEBSOCKET_KEY).and_then(|k| WebSocketKey::try_from(k).ok());
+	if let Some(key) = key {
+		headers.insert(SEC_WEBSOCKET_ACCEPT, WebSocketAccept::new(key).into());
+	}
+	headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+	headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+	headers
+}
+
+fn gone() -> WebSocketError {
+	io::Error::new(io::ErrorKind::NotConnected, "the connection is not open").into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn wait_for<F>(manager: &mut Manager, events: &mut Vec<Event>, mut found: F)
+	where
+		F: FnMut(&Event) -> bool,
+	{
+		for _ in 0..100 {
+			manager.poll(events, Some(Duration::from_millis(50))).unwrap();
+			if events.iter().any(&mut found) {
+				return;
+			}
+		}
+		panic!("no such event in {:?}", events);
+	}
+
+	#[test]
+	fn server_and_client_in_one_loop() {
+		let mut manager = Manager::new().unwrap();
+		let addr = manager.listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+		let builder = ClientBuilder::new(&format!("ws://{}/chat", addr)).unwrap();
+		let client = manager.connect(builder).unwrap();
+
+		let mut events = Vec::new();
+		wait_for(&mut manager, &mut events, |e| match *e {
+			Event::Connected(id, _) => id == client,
+			_ => false,
+		});
+		let server = events.iter()
+		                   .filter_map(|e| match *e {
+			Event::Accepted(id, ref request) => {
+				assert_eq!(request.subject.1.path(), "/chat");
+				Some(id)
+			}
+			_ => None,
+		})
+		                   .next()
+		                   .unwrap();
+		events.clear();
+
+		manager.send(client, &OwnedMessage::Text("hi".to_string())).unwrap();
+		wait_for(&mut manager, &mut events, |e| match *e {
+			Event::Message(id, OwnedMessage::Text(ref text)) => id == server && text == "hi",
+			_ => false,
+		});
+		events.clear();
+
+		manager.close(server, None).unwrap();
+		wait_for(&mut manager, &mut events, |e| match *e {
+			Event::Closed(id, _) => id == server,
+			_ => false,
+		});
+		assert!(events.iter().any(|e| match *e {
+			Event::Closed(id, None) => id == client,
+			_ => false,
+		}));
+	}
+
+	#[test]
+	fn hanging_up_is_a_plain_close() {
+		let mut manager = Manager::new().unwrap();
+		let addr = manager.listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+		let builder = ClientBuilder::new(&format!("ws://{}", addr)).unwrap();
+		let client = manager.connect(builder).unwrap();
+
+		let mut events = Vec::new();
+		wait_for(&mut manager, &mut events, |e| match *e {
+			Event::Connected(id, _) => id == client,
+			_ => false,
+		});
+		let server = events.iter()
+		                   .filter_map(|e| match *e {
+			Event::Accepted(id, _) => Some(id),
+			_ => None,
+		})
+		                   .next()
+		                   .unwrap();
+		events.clear();
+
+		manager.disconnect(server);
+		wait_for(&mut manager, &mut events, |e| match *e {
+			Event::Closed(id, _) => id == client,
+			_ => false,
+		});
+		assert!(!events.iter().any(|e| match *e {
+			Event::Error(..) => true,
+			_ => false,
+		}), "{:?}", events);
+	}
+
+	#[test]
+	fn gives_up_on_slow_handshakes_and_closes() {
+		use std::net::TcpStream as StdTcpStream;
+
+		let mut manager = Manager::new().unwrap();
+		let addr = manager.listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+		manager.handshake_config(HandshakeConfig {
+			timeout: Some(Duration::from_millis(200)),
+			..HandshakeConfig::default()
+		});
+
+		// never sends its request
+		let mut silent = StdTcpStream::connect(addr).unwrap();
+		let mut events = Vec::new();
+		wait_for(&mut manager, &mut events, |e| match *e {
+			Event::Closed(..) => true,
+			_ => false,
+		});
+		assert!(events.iter().any(|e| match *e {
+			Event::Error(..) => true,
+			_ => false,
+		}));
+		let mut response = String::new();
+		silent.read_to_string(&mut response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+		events.clear();
+
+		// shakes hands but never answers the close
+		let mut deaf = StdTcpStream::connect(addr).unwrap();
+		deaf.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+		                 Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		                 Sec-WebSocket-Version: 13\r\n\r\n")
+		    .unwrap();
+		wait_for(&mut manager, &mut events, |e| match *e {
+			Event::Accepted(..) => true,
+			_ => false,
+		});
+		let id = events.iter()
+		               .filter_map(|e| match *e {
+			Event::Accepted(id, _) => Some(id),
+			_ => None,
+		})
+		               .next()
+		               .unwrap();
+		events.clear();
+		manager.close(id, None).unwrap();
+		let started = Instant::now();
+		while !events.iter().any(|e| match *e {
+			Event::Closed(closed, None) => closed == id,
+			_ => false,
+		}) {
+			assert!(started.elapsed() < Duration::from_secs(CLOSE_TIMEOUT_SECS + 5));
+			manager.poll(&mut events, None).unwrap();
+		}
+		assert!(started.elapsed() >= Duration::from_secs(CLOSE_TIMEOUT_SECS));
+	}
+
+	#[test]
+	fn refuses_secure_urls() {
+		let mut manager = Manager::new().unwrap();
+		let builder = ClientBuilder::new("wss://127.0.0.1:1").unwrap();
+		match manager.connect(builder) {
+			Err(WebSocketError::WebSocketUrlError(WSUrlErrorKind::InvalidScheme)) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+}
@@ -4,6 +4,8 @@ use std::str::FromStr;
 use http::header::HeaderValue;
 use unicase::Ascii;
 
+use header::{HeaderError, is_token};
+
 pub use self::ConnectionOption::{KeepAlive, Close, ConnectionHeader};
 
 static KEEP_ALIVE: &'static str = "keep-alive";
@@ -16,8 +18,11 @@ pub enum ConnectionOption {
 }
 
 impl FromStr for ConnectionOption {
-	type Err = ();
-	fn from_str(s: &str) -> Result<ConnectionOption, ()> {
+	type Err = HeaderError;
+	fn from_str(s: &str) -> Result<ConnectionOption, HeaderError> {
+		if !is_token(s) {
+			return Err(HeaderError::Invalid(Connection::HEADER_NAME));
+		}
 		let s = Ascii::new(s.to_owned());
 		if s == KEEP_ALIVE {
 			Ok(KeepAlive)
@@ -42,20 +47,28 @@ impl Display for ConnectionOption {
 pub struct Connection(pub Vec<ConnectionOption>);
 
 impl FromStr for Connection {
-	type Err = ();
-	fn from_str(s: &str) -> Result<Connection, ()> {
-		let options = s.split(',')
-		               .filter_map(|x| match x.trim() {
+	type Err = HeaderError;
+	fn from_str(s: &str) -> Result<Connection, HeaderError> {
+		let options = try!(s.split(',')
+		                    .filter_map(|x| match x.trim() {
 			"" => None,
 			y => Some(y),
 		})
-		               .filter_map(|x| x.trim().parse().ok())
-		               .collect();
+		                    .map(|x| x.parse())
+		                    .collect::<Result<Vec<ConnectionOption>, HeaderError>>());
+		if options.is_empty() {
+			return Err(HeaderError::Invalid(Connection::HEADER_NAME));
+		}
 		Ok(Connection(options))
 	}
 }
 
+try_from_header_value!(Connection, Connection::HEADER_NAME);
+
 impl Connection {
+	/// The name of this header.
+	pub const HEADER_NAME: &'static str = "Connection";
+
 	#[inline]
 	pub fn close() -> Connection {
 		Connection(vec![ConnectionOption::Close])
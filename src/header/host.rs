@@ -2,13 +2,20 @@ use std::borrow::Cow;
 use std::fmt;
 use std::str::FromStr;
 
+use header::HeaderError;
+
 #[derive(PartialEq, Clone, Debug)]
 pub struct Host {
 	hostname: Cow<'static, str>,
 	port: Option<u16>,
 }
 
+try_from_header_value!(Host, Host::HEADER_NAME);
+
 impl Host {
+	/// The name of this header.
+	pub const HEADER_NAME: &'static str = "Host";
+
 	pub fn new<H, P>(hostname: H, port: P) -> Host
 	where
 		H: Into<Cow<'static, str>>,
@@ -39,14 +46,21 @@ impl fmt::Display for Host {
 }
 
 impl FromStr for Host {
-	type Err = ();
+	type Err = HeaderError;
 	fn from_str(s: &str) -> Result<Host, Self::Err> {
+		let s = s.trim();
 		let idx = s.rfind(':');
 		let port = idx.and_then(|idx| s[idx + 1..].parse().ok());
-		let hostname = match port {
-			None => s,
-			Some(_) => &s[..idx.unwrap()],
+		let hostname = match (idx, port) {
+			(Some(idx), Some(_)) => &s[..idx],
+			_ => s,
 		};
+		// no paths, userinfo or whitespace inside of a host
+		if hostname.is_empty() ||
+			hostname.bytes().any(|b| b <= b' ' || b == b'/' || b == b'@' || b >= 0x7f)
+		{
+			return Err(HeaderError::Invalid(Host::HEADER_NAME));
+		}
 
 		Ok(Host {
 			hostname: hostname.to_owned().into(),
@@ -2,6 +2,12 @@
 //!
 //! These headers are commonly used in WebSocket requests and responses.
 //! The `Header` trait from the `hyper` crate is used.
+//!
+//! Every header here can be parsed from a string with `FromStr` or straight from
+//! an `http::header::HeaderValue` with `TryFrom`. Header values usually come from
+//! the peer, so malformed input is reported with a `HeaderError` and never panics.
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 
 pub use self::host::Host;
 pub use self::origin::Origin;
@@ -12,6 +18,23 @@ pub use self::sec_websocket_version::WebSocketVersion;
 pub use self::sec_websocket_extensions::WebSocketExtensions;
 pub use self::upgrade::Upgrade;
 
+/// Implements `TryFrom<&HeaderValue>` for a header that implements
+/// `FromStr<Err = HeaderError>`.
+macro_rules! try_from_header_value {
+	($header:ty, $name:expr) => {
+		impl<'a> ::std::convert::TryFrom<&'a ::http::header::HeaderValue> for $header {
+			type Error = ::header::HeaderError;
+
+			fn try_from(value: &'a ::http::header::HeaderValue) -> Result<Self, Self::Error> {
+				match value.to_str() {
+					Ok(s) => s.parse(),
+					Err(_) => Err(::header::HeaderError::NotVisibleAscii($name)),
+				}
+			}
+		}
+	};
+}
+
 pub mod connection;
 mod host;
 mod origin;
@@ -21,3 +44,49 @@ mod sec_websocket_protocol;
 mod sec_websocket_version;
 pub mod sec_websocket_extensions;
 pub mod upgrade;
+
+/// The error given when a header value is malformed.
+///
+/// Each variant carries the name of the header that failed to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+	/// The value contains bytes that are not visible ASCII.
+	NotVisibleAscii(&'static str),
+	/// The value is not a valid value for this header.
+	Invalid(&'static str),
+}
+
+impl HeaderError {
+	/// The name of the header that failed to parse.
+	pub fn header_name(&self) -> &'static str {
+		match *self {
+			HeaderError::NotVisibleAscii(name) |
+			HeaderError::Invalid(name) => name,
+		}
+	}
+}
+
+impl Display for HeaderError {
+	fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+		write!(fmt, "{}: {}", self.description(), self.header_name())
+	}
+}
+
+impl Error for HeaderError {
+	fn description(&self) -> &str {
+		match *self {
+			HeaderError::NotVisibleAscii(_) => "header value is not visible ASCII",
+			HeaderError::Invalid(_) => "invalid header value",
+		}
+	}
+}
+
+/// Checks that a string is a `token` as defined by RFC 7230, section 3.2.6.
+pub(crate) fn is_token(s: &str) -> bool {
+	!s.is_empty() &&
+		s.bytes().all(|b| match b {
+			b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' |
+			b'_' | b'`' | b'|' | b'~' => true,
+			b => b.is_ascii_alphanumeric(),
+		})
+}
@@ -2,7 +2,7 @@ use std::borrow::Cow;
 use std::fmt;
 use std::str::FromStr;
 
-use header::Host;
+use header::{HeaderError, Host};
 
 /// Represents an Origin header
 #[derive(PartialEq, Clone, Debug)]
@@ -17,7 +17,12 @@ enum OriginOrNull {
 	Null,
 }
 
+try_from_header_value!(Origin, Origin::HEADER_NAME);
+
 impl Origin {
+	/// The name of this header.
+	pub const HEADER_NAME: &'static str = "Origin";
+
 	pub fn new<S: Into<Cow<'static, str>>, H: Into<Cow<'static, str>>>(
 		scheme: S,
 		hostname: H,
@@ -59,16 +64,25 @@ static HTTP: &'static str = "http";
 static HTTPS: &'static str = "https";
 
 impl FromStr for Origin {
-	type Err = ();
+	type Err = HeaderError;
 
 	fn from_str(s: &str) -> Result<Origin, Self::Err> {
+		let s = s.trim();
+		if s == "null" {
+			return Ok(Origin::null());
+		}
+
+		let invalid = HeaderError::Invalid(Origin::HEADER_NAME);
 		let idx = match s.find("://") {
 			Some(idx) => idx,
-			None => return Err(()),
+			None => return Err(invalid),
 		};
 
 		let (scheme, etc) = (&s[..idx], &s[idx + 3..]);
-		let host = try!(Host::from_str(etc));
+		if scheme.is_empty() || !scheme.bytes().all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b)) {
+			return Err(invalid);
+		}
+		let host = try!(Host::from_str(etc).map_err(|_| invalid));
 		let scheme = match scheme {
 			"http" => Cow::Borrowed(HTTP),
 			"https" => Cow::Borrowed(HTTPS),
@@ -3,8 +3,7 @@ use base64;
 use http::header::HeaderValue;
 use std::fmt::{self, Debug};
 use std::str::FromStr;
-use header::WebSocketKey;
-use result::{WebSocketResult, WebSocketError};
+use header::{HeaderError, WebSocketKey};
 use sha1::Sha1;
 
 static MAGIC_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
@@ -20,15 +19,14 @@ impl Debug for WebSocketAccept {
 }
 
 impl FromStr for WebSocketAccept {
-	type Err = WebSocketError;
+	type Err = HeaderError;
 
-	fn from_str(accept: &str) -> WebSocketResult<WebSocketAccept> {
-		match base64::decode(accept) {
+	fn from_str(accept: &str) -> Result<WebSocketAccept, HeaderError> {
+		match base64::decode(accept.trim()) {
 			Ok(vec) => {
+				// the accept hash must be 20 bytes
 				if vec.len() != 20 {
-					return Err(WebSocketError::ProtocolError(
-						"Sec-WebSocket-Accept must be 20 bytes",
-					));
+					return Err(HeaderError::Invalid(WebSocketAccept::HEADER_NAME));
 				}
 				let mut array = [0u8; 20];
 				let mut iter = vec.into_iter();
@@ -37,14 +35,17 @@ impl FromStr for WebSocketAccept {
 				}
 				Ok(WebSocketAccept(array))
 			}
-			Err(_) => Err(WebSocketError::ProtocolError(
-				"Invalid Sec-WebSocket-Accept",
-			)),
+			Err(_) => Err(HeaderError::Invalid(WebSocketAccept::HEADER_NAME)),
 		}
 	}
 }
 
+try_from_header_value!(WebSocketAccept, WebSocketAccept::HEADER_NAME);
+
 impl WebSocketAccept {
+	/// The name of this header.
+	pub const HEADER_NAME: &'static str = "Sec-WebSocket-Accept";
+
 	/// Create a new WebSocketAccept from the given WebSocketKey
 	pub fn new<K: Into<WebSocketKey>>(key: K) -> WebSocketAccept {
 		let key: WebSocketKey = key.into();
@@ -7,11 +7,7 @@ use std::ops::Deref;
 
 use http::header::HeaderValue;
 
-use result::{WebSocketResult, WebSocketError};
-
-const INVALID_EXTENSION: &'static str = "Invalid Sec-WebSocket-Extensions extension name";
-
-// TODO: check if extension name is valid according to spec
+use header::{HeaderError, is_token};
 
 /// Represents a Sec-WebSocket-Extensions header
 #[derive(PartialEq, Clone, Debug)]
@@ -25,17 +21,25 @@ impl Deref for WebSocketExtensions {
 	}
 }
 
+impl WebSocketExtensions {
+	/// The name of this header.
+	pub const HEADER_NAME: &'static str = "Sec-WebSocket-Extensions";
+}
+
 impl FromStr for WebSocketExtensions {
-	type Err = ();
+	type Err = HeaderError;
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		Ok(WebSocketExtensions(
-			s.split(',')
-			 .map(|s| s.trim().parse::<Extension>().unwrap())
-			 .collect(),
-		))
+		let extensions = s.split(',')
+		                  .map(|s| s.trim())
+		                  .filter(|s| !s.is_empty())
+		                  .map(|s| s.parse::<Extension>())
+		                  .collect::<Result<Vec<Extension>, HeaderError>>();
+		Ok(WebSocketExtensions(try!(extensions)))
 	}
 }
 
+try_from_header_value!(WebSocketExtensions, WebSocketExtensions::HEADER_NAME);
+
 impl From<WebSocketExtensions> for HeaderValue {
 	fn from(extensions: WebSocketExtensions) -> Self {
 		HeaderValue::from_str(&extensions.0
@@ -67,24 +71,32 @@ impl Extension {
 }
 
 impl FromStr for Extension {
-	type Err = WebSocketError;
+	type Err = HeaderError;
 
-	fn from_str(s: &str) -> WebSocketResult<Extension> {
+	fn from_str(s: &str) -> Result<Extension, HeaderError> {
+		let invalid = HeaderError::Invalid(WebSocketExtensions::HEADER_NAME);
 		let mut ext = s.split(';').map(|x| x.trim());
+		let name = match ext.next() {
+			Some(x) if is_token(x) => x.to_string(),
+			_ => return Err(invalid),
+		};
+		let mut params = Vec::new();
+		for param in ext {
+			let mut pair = param.splitn(2, '=').map(|x| x.trim());
+			let name = match pair.next() {
+				Some(x) if is_token(x) => x.to_string(),
+				_ => return Err(invalid),
+			};
+			// values may be tokens or quoted strings (RFC6455 9.1)
+			let value = pair.next().map(|x| x.trim_matches('"').to_string());
+			params.push(Parameter {
+				name: name,
+				value: value,
+			});
+		}
 		Ok(Extension {
-			name: match ext.next() {
-				Some(x) => x.to_string(),
-				None => return Err(WebSocketError::ProtocolError(INVALID_EXTENSION)),
-			},
-			params: ext.map(|x| {
-				let mut pair = x.splitn(1, '=').map(|x| x.trim().to_string());
-
-				Parameter {
-					name: pair.next().unwrap(),
-					value: pair.next(),
-				}
-			})
-			           .collect(),
+			name: name,
+			params: params,
 		})
 	}
 }
@@ -1,13 +1,12 @@
 use std::mem;
 use std::str::FromStr;
-use std::convert::TryFrom;
 
 use base64;
 use http::header::HeaderValue;
 //use hyper::header::parsing::from_one_raw_str;
 use std::fmt::{self, Debug};
 use rand;
-use result::{WebSocketResult, WebSocketError};
+use header::HeaderError;
 
 /// Represents a Sec-WebSocket-Key header.
 #[derive(PartialEq, Clone, Copy, Default)]
@@ -20,15 +19,14 @@ impl Debug for WebSocketKey {
 }
 
 impl FromStr for WebSocketKey {
-	type Err = WebSocketError;
+	type Err = HeaderError;
 
-	fn from_str(key: &str) -> WebSocketResult<WebSocketKey> {
-		match base64::decode(key) {
+	fn from_str(key: &str) -> Result<WebSocketKey, HeaderError> {
+		match base64::decode(key.trim()) {
 			Ok(vec) => {
+				// the key must be 16 bytes
 				if vec.len() != 16 {
-					return Err(WebSocketError::ProtocolError(
-						"Sec-WebSocket-Key must be 16 bytes",
-					));
+					return Err(HeaderError::Invalid(WebSocketKey::HEADER_NAME));
 				}
 				let mut array = [0u8; 16];
 				let mut iter = vec.into_iter();
@@ -38,14 +36,13 @@ impl FromStr for WebSocketKey {
 
 				Ok(WebSocketKey(array))
 			}
-			Err(_) => Err(WebSocketError::ProtocolError(
-				"Invalid Sec-WebSocket-Accept",
-			)),
+			Err(_) => Err(HeaderError::Invalid(WebSocketKey::HEADER_NAME)),
 		}
 	}
 }
 
 impl WebSocketKey {
+	/// The name of this header.
 	pub const HEADER_NAME: &'static str = "Sec-WebSocket-Key";
 	/// Generate a new, random WebSocketKey
 	pub fn new() -> WebSocketKey {
@@ -63,12 +60,7 @@ impl From<WebSocketKey> for HeaderValue {
 	}
 }
 
-impl TryFrom<HeaderValue> for WebSocketKey {
-	type Error = ();
-	fn try_from(value: HeaderValue) -> Result<WebSocketKey, ()> {
-		Ok(WebSocketKey([0u8; 16]))
-	}
-}
+try_from_header_value!(WebSocketKey, WebSocketKey::HEADER_NAME);
 
 impl fmt::Display for WebSocketKey {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -5,7 +5,7 @@ use std::str::FromStr;
 
 use http::header::HeaderValue;
 
-// TODO: only allow valid protocol names to be added
+use header::{HeaderError, is_token};
 
 /// Represents a Sec-WebSocket-Protocol header
 #[derive(PartialEq, Clone, Debug)]
@@ -18,15 +18,29 @@ impl Deref for WebSocketProtocol {
 	}
 }
 
+impl WebSocketProtocol {
+	/// The name of this header.
+	pub const HEADER_NAME: &'static str = "Sec-WebSocket-Protocol";
+}
+
 impl FromStr for WebSocketProtocol {
-	type Err = ();
+	type Err = HeaderError;
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		Ok(WebSocketProtocol(
-			s.split(',').map(|s| s.trim().to_owned()).collect(),
-		))
+		let protocols = s.split(',')
+		                 .map(|s| s.trim())
+		                 .filter(|s| !s.is_empty())
+		                 .map(|s| if is_token(s) {
+			Ok(s.to_owned())
+		} else {
+			Err(HeaderError::Invalid(WebSocketProtocol::HEADER_NAME))
+		})
+		                 .collect::<Result<Vec<String>, HeaderError>>();
+		Ok(WebSocketProtocol(try!(protocols)))
 	}
 }
 
+try_from_header_value!(WebSocketProtocol, WebSocketProtocol::HEADER_NAME);
+
 impl From<WebSocketProtocol> for HeaderValue {
 	fn from(protocol: WebSocketProtocol) -> Self {
 		HeaderValue::from_str(&protocol.0.join(", ")).unwrap()
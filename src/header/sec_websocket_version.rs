@@ -1,8 +1,10 @@
 use std::fmt;
-use std::str::{self, FromStr};
+use std::str::FromStr;
 
 use http::header::HeaderValue;
 
+use header::HeaderError;
+
 /// Represents a Sec-WebSocket-Version header
 #[derive(PartialEq, Clone)]
 pub enum WebSocketVersion {
@@ -22,17 +24,28 @@ impl fmt::Debug for WebSocketVersion {
 }
 
 impl FromStr for WebSocketVersion {
-	type Err = ();
+	type Err = HeaderError;
 	fn from_str(value: &str) -> Result<Self, Self::Err> {
-		let value = try!(str::from_utf8(value.as_bytes()).map_err(|_| ())).trim();
+		let value = value.trim();
 
 		match &value[..] {
 			"13" => Ok(WebSocketVersion::WebSocket13),
-			_ => Ok(WebSocketVersion::Unknown(value.to_owned())),
+			// versions are a plain number (RFC6455 4.1)
+			v if !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()) => {
+				Ok(WebSocketVersion::Unknown(value.to_owned()))
+			}
+			_ => Err(HeaderError::Invalid(WebSocketVersion::HEADER_NAME)),
 		}
 	}
 }
 
+impl WebSocketVersion {
+	/// The name of this header.
+	pub const HEADER_NAME: &'static str = "Sec-WebSocket-Version";
+}
+
+try_from_header_value!(WebSocketVersion, WebSocketVersion::HEADER_NAME);
+
 impl From<WebSocketVersion> for HeaderValue {
 	fn from(version: WebSocketVersion) -> HeaderValue {
 		match &version {
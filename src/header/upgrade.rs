@@ -4,8 +4,15 @@ use std::str::FromStr;
 use http::header::HeaderValue;
 use unicase;
 
+use header::{HeaderError, is_token};
+
 pub struct Upgrade(pub Vec<Protocol>);
 
+impl Upgrade {
+	/// The name of this header.
+	pub const HEADER_NAME: &'static str = "Upgrade";
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ProtocolName {
 	HTTP,
@@ -61,12 +68,17 @@ impl Protocol {
 }
 
 impl FromStr for Protocol {
-	type Err = ();
-	fn from_str(s: &str) -> Result<Protocol, ()> {
+	type Err = HeaderError;
+	fn from_str(s: &str) -> Result<Protocol, HeaderError> {
 		let mut parts = s.splitn(2, '/');
+		let name = parts.next().unwrap_or("");
+		let version = parts.next();
+		if !is_token(name) || version.map_or(false, |v| !is_token(v)) {
+			return Err(HeaderError::Invalid(Upgrade::HEADER_NAME));
+		}
 		Ok(Protocol::new(
-			try!(parts.next().unwrap().parse()),
-			parts.next().map(|x| x.to_owned()),
+			try!(name.parse().map_err(|_| HeaderError::Invalid(Upgrade::HEADER_NAME))),
+			version.map(|x| x.to_owned()),
 		))
 	}
 }
@@ -82,19 +94,24 @@ impl Display for Protocol {
 }
 
 impl FromStr for Upgrade {
-	type Err = ();
+	type Err = HeaderError;
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		let protocols = s.split(',')
-		                 .filter_map(|x| match x.trim() {
+		let protocols = try!(s.split(',')
+		                      .filter_map(|x| match x.trim() {
 			"" => None,
 			y => Some(y),
 		})
-		                 .filter_map(|x| x.trim().parse().ok())
-		                 .collect();
+		                      .map(|x| x.parse())
+		                      .collect::<Result<Vec<Protocol>, HeaderError>>());
+		if protocols.is_empty() {
+			return Err(HeaderError::Invalid(Upgrade::HEADER_NAME));
+		}
 		Ok(Upgrade(protocols))
 	}
 }
 
+try_from_header_value!(Upgrade, Upgrade::HEADER_NAME);
+
 impl From<Upgrade> for HeaderValue {
 	fn from(upgrade: Upgrade) -> Self {
 		HeaderValue::from_str(&upgrade.0
@@ -0,0 +1,524 @@
+//! Look at, change, drop or refuse messages on their way in and out.
+//!
+//! An `Interceptor` is called with every message that is sent or received,
+//! and can pass it on as it is, pass on another message instead, drop it or
+//! fail the send or receive with an error, after which the connection should
+//! be given up. A `Chain` runs many interceptors one after another.
+//!
+//! They are installed with `intercept` on the sync `Client`, `Reader` and
+//! `Writer`, or by wrapping a codec in an `InterceptCodec`, see `intercept`
+//! to do that for an async `Client`.
+//!
+//!```rust,no_run
+//!use websocket::{ClientBuilder, OwnedMessage};
+//!use websocket::intercept::{self, Action, Counter};
+//!
+//!let mut client = ClientBuilder::new("ws://127.0.0.1:2794").unwrap()
+//!    .connect_insecure().unwrap();
+//!
+//!let counter = Counter::new();
+//!client.intercept(counter.clone());
+//!// keep passwords out of the logs of the server
+//!client.intercept(intercept::outgoing(|message| match message {
+//!    OwnedMessage::Text(ref text) if text.starts_with("password ") => {
+//!        Action::Pass(OwnedMessage::Text("password ***".to_string()))
+//!    }
+//!    message => Action::Pass(message),
+//!}));
+//!
+//!client.send_message(&OwnedMessage::Text("password hunter2".to_string())).unwrap();
+//!println!("{:?}", counter.counts());
+//!```
+//!
+//! Data frames can be intercepted as well. On sync connections every frame
+//! that is read, and every frame that is written, passes through them, a
+//! message is put together from the frames that are passed on. The async
+//! codec only passes messages.
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use dataframe::{DataFrame, Opcode};
+use message::OwnedMessage;
+use result::{WebSocketError, WebSocketResult};
+use ws;
+use ws::dataframe::DataFrame as DataFrameable;
+
+#[cfg(feature = "async")]
+use bytes::BytesMut;
+#[cfg(feature = "async")]
+use codec::ws::MessageCodec;
+#[cfg(feature = "async")]
+use stream::async::Stream as AsyncStream;
+#[cfg(feature = "async")]
+use tokio_codec::{Decoder, Encoder, Framed, FramedParts};
+
+/// What to do with a message or a data frame.
+#[derive(Debug)]
+pub enum Action<T> {
+	/// Pass this on, the one that was given or another one.
+	Pass(T),
+	/// Drop it, as if it was never sent or received.
+	Drop,
+	/// Fail the send or receive with this error.
+	Fail(WebSocketError),
+}
+
+impl<T> Action<T> {
+	fn and_then<F>(self, f: F) -> Action<T>
+	where
+		F: FnOnce(T) -> Action<T>,
+	{
+		match self {
+			Action::Pass(item) => f(item),
+			other => other,
+		}
+	}
+
+	/// The item to go on with, `None` if it was dropped.
+	fn into_result(self) -> WebSocketResult<Option<T>> {
+		match self {
+			Action::Pass(item) => Ok(Some(item)),
+			Action::Drop => Ok(None),
+			Action::Fail(e) => Err(e),
+		}
+	}
+}
+
+/// Sees messages and data frames on their way in and out.
+///
+/// Every method passes what it is given on by default. Interceptors have to be
+/// `Sync` as well as `Send`, so that the clients, readers and writers holding
+/// them still are.
+pub trait Interceptor: Send + Sync {
+	/// A message that is about to be sent.
+	fn outgoing(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		Action::Pass(message)
+	}
+
+	/// A message that was received.
+	fn incoming(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		Action::Pass(message)
+	}
+
+	/// A data frame that is about to be written.
+	fn outgoing_dataframe(&mut self, frame: DataFrame) -> Action<DataFrame> {
+		Action::Pass(frame)
+	}
+
+	/// A data frame that was read.
+	fn incoming_dataframe(&mut self, frame: DataFrame) -> Action<DataFrame> {
+		Action::Pass(frame)
+	}
+}
+
+/// Interceptors that run one after another.
+///
+/// What goes out passes them in the order they were added, what comes in in
+/// the opposite order, so the first one is the closest to the application.
+/// Once one of them drops or fails something the rest don't see it.
+#[derive(Default)]
+pub struct Chain {
+	interceptors: Vec<Box<Interceptor>>,
+}
+
+impl Chain {
+	/// A chain without interceptors, which passes everything.
+	pub fn new() -> Self {
+		Chain::default()
+	}
+
+	/// Add an interceptor to the end of the chain.
+	pub fn with<I>(mut self, interceptor: I) -> Self
+	where
+		I: Interceptor + 'static,
+	{
+		self.push(interceptor);
+		self
+	}
+
+	/// Add an interceptor to the end of the chain.
+	pub fn push<I>(&mut self, interceptor: I)
+	where
+		I: Interceptor + 'static,
+	{
+		self.interceptors.push(Box::new(interceptor));
+	}
+
+	/// Whether there are no interceptors.
+	pub fn is_empty(&self) -> bool {
+		self.interceptors.is_empty()
+	}
+
+	/// Run a message that is about to be sent through the chain and turn it
+	/// into the data frame to write, `None` if it was dropped.
+	pub(crate) fn send_message<M>(&mut self, message: &M) -> WebSocketResult<Option<DataFrame>>
+	where
+		M: ws::Message,
+	{
+		let message = match self.outgoing(message.to_owned_message()?).into_result()? {
+			Some(message) => message,
+			None => return Ok(None),
+		};
+		self.send_dataframe(&message)
+	}
+
+	/// Run a data frame that is about to be written through the chain.
+	pub(crate) fn send_dataframe<D>(&mut self, frame: &D) -> WebSocketResult<Option<DataFrame>>
+	where
+		D: DataFrameable,
+	{
+		self.outgoing_dataframe(to_dataframe(frame)?).into_result()
+	}
+
+	/// Run a message that was received through the chain.
+	pub(crate) fn receive_message(&mut self, message: OwnedMessage) -> WebSocketResult<Option<OwnedMessage>> {
+		self.incoming(message).into_result()
+	}
+
+	/// Run a data frame that was read through the chain.
+	pub(crate) fn receive_dataframe(&mut self, frame: DataFrame) -> WebSocketResult<Option<DataFrame>> {
+		self.incoming_dataframe(frame).into_result()
+	}
+}
+
+impl Interceptor for Chain {
+	fn outgoing(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		self.interceptors.iter_mut().fold(Action::Pass(message), |action, interceptor| {
+			action.and_then(|message| interceptor.outgoing(message))
+		})
+	}
+
+	fn incoming(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		self.interceptors.iter_mut().rev().fold(Action::Pass(message), |action, interceptor| {
+			action.and_then(|message| interceptor.incoming(message))
+		})
+	}
+
+	fn outgoing_dataframe(&mut self, frame: DataFrame) -> Action<DataFrame> {
+		self.interceptors.iter_mut().fold(Action::Pass(frame), |action, interceptor| {
+			action.and_then(|frame| interceptor.outgoing_dataframe(frame))
+		})
+	}
+
+	fn incoming_dataframe(&mut self, frame: DataFrame) -> Action<DataFrame> {
+		self.interceptors.iter_mut().rev().fold(Action::Pass(frame), |action, interceptor| {
+			action.and_then(|frame| interceptor.incoming_dataframe(frame))
+		})
+	}
+}
+
+/// One interceptor shared by many connections, or by both halves of a split
+/// client.
+impl<I> Interceptor for Arc<Mutex<I>>
+where
+	I: Interceptor,
+{
+	fn outgoing(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		self.lock().unwrap_or_else(|e| e.into_inner()).outgoing(message)
+	}
+
+	fn incoming(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		self.lock().unwrap_or_else(|e| e.into_inner()).incoming(message)
+	}
+
+	fn outgoing_dataframe(&mut self, frame: DataFrame) -> Action<DataFrame> {
+		self.lock().unwrap_or_else(|e| e.into_inner()).outgoing_dataframe(frame)
+	}
+
+	fn incoming_dataframe(&mut self, frame: DataFrame) -> Action<DataFrame> {
+		self.lock().unwrap_or_else(|e| e.into_inner()).incoming_dataframe(frame)
+	}
+}
+
+/// An interceptor of outgoing messages, see `outgoing`.
+pub struct Outgoing<F>(F);
+
+/// Intercept outgoing messages with a closure.
+pub fn outgoing<F>(f: F) -> Outgoing<F>
+where
+	F: FnMut(OwnedMessage) -> Action<OwnedMessage> + Send + Sync,
+{
+	Outgoing(f)
+}
+
+impl<F> Interceptor for Outgoing<F>
+where
+	F: FnMut(OwnedMessage) -> Action<OwnedMessage> + Send + Sync,
+{
+	fn outgoing(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		(self.0)(message)
+	}
+}
+
+/// An interceptor of incoming messages, see `incoming`.
+pub struct Incoming<F>(F);
+
+/// Intercept incoming messages with a closure.
+pub fn incoming<F>(f: F) -> Incoming<F>
+where
+	F: FnMut(OwnedMessage) -> Action<OwnedMessage> + Send + Sync,
+{
+	Incoming(f)
+}
+
+impl<F> Interceptor for Incoming<F>
+where
+	F: FnMut(OwnedMessage) -> Action<OwnedMessage> + Send + Sync,
+{
+	fn incoming(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		(self.0)(message)
+	}
+}
+
+/// How many messages, and payload bytes, a `Counter` saw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Counts {
+	/// Messages received.
+	pub incoming: usize,
+	/// Payload bytes received.
+	pub incoming_bytes: usize,
+	/// Messages sent.
+	pub outgoing: usize,
+	/// Payload bytes sent.
+	pub outgoing_bytes: usize,
+}
+
+/// Counts the messages that pass, its clones count together.
+#[derive(Debug, Clone, Default)]
+pub struct Counter {
+	counts: Arc<[AtomicUsize; 4]>,
+}
+
+impl Counter {
+	/// A counter at zero.
+	pub fn new() -> Self {
+		Counter::default()
+	}
+
+	/// What was counted so far.
+	pub fn counts(&self) -> Counts {
+		Counts {
+			incoming: self.counts[0].load(Ordering::SeqCst),
+			incoming_bytes: self.counts[1].load(Ordering::SeqCst),
+			outgoing: self.counts[2].load(Ordering::SeqCst),
+			outgoing_bytes: self.counts[3].load(Ordering::SeqCst),
+		}
+	}
+
+	fn count(&self, first: usize, message: &OwnedMessage) {
+		self.counts[first].fetch_add(1, Ordering::SeqCst);
+		self.counts[first + 1].fetch_add(message.size(), Ordering::SeqCst);
+	}
+}
+
+impl Interceptor for Counter {
+	fn outgoing(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		self.count(2, &message);
+		Action::Pass(message)
+	}
+
+	fn incoming(&mut self, message: OwnedMessage) -> Action<OwnedMessage> {
+		self.count(0, &message);
+		Action::Pass(message)
+	}
+}
+
+/// Any data frame as a `DataFrame`.
+fn to_dataframe<D>(frame: &D) -> WebSocketResult<DataFrame>
+where
+	D: DataFrameable,
+{
+	let opcode = match Opcode::new(frame.opcode()) {
+		Some(opcode) => opcode,
+		None => return Err(WebSocketError::ProtocolError("Invalid data frame opcode")),
+	};
+	let mut data = Vec::with_capacity(frame.size());
+	frame.write_payload(&mut data)?;
+	let mut dataframe = DataFrame::new(frame.is_last(), opcode, data);
+	dataframe.reserved = *frame.reserved();
+	Ok(dataframe)
+}
+
+/// A codec that runs the messages of another codec, like the `MessageCodec`,
+/// through interceptors. Data frames are not intercepted.
+#[cfg(feature = "async")]
+pub struct InterceptCodec<C> {
+	inner: C,
+	interceptors: Chain,
+}
+
+#[cfg(feature = "async")]
+impl<C> InterceptCodec<C> {
+	/// Intercept the messages of `inner`.
+	pub fn new(inner: C, interceptors: Chain) -> Self {
+		InterceptCodec {
+			inner: inner,
+			interceptors: interceptors,
+		}
+	}
+
+	/// The interceptors, to add more.
+	pub fn interceptors(&mut self) -> &mut Chain {
+		&mut self.interceptors
+	}
+
+	/// The codec that is intercepted.
+	pub fn into_inner(self) -> C {
+		self.inner
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Decoder for InterceptCodec<C>
+where
+	C: Decoder<Item = OwnedMessage, Error = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<OwnedMessage>, WebSocketError> {
+		while let Some(message) = self.inner.decode(src)? {
+			if let Some(message) = self.interceptors.incoming(message).into_result()? {
+				return Ok(Some(message));
+			}
+		}
+		Ok(None)
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Encoder for InterceptCodec<C>
+where
+	C: Encoder<Item = OwnedMessage, Error = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn encode(&mut self, message: OwnedMessage, dst: &mut BytesMut) -> Result<(), WebSocketError> {
+		match self.interceptors.outgoing(message).into_result()? {
+			Some(message) => self.inner.encode(message, dst),
+			None => Ok(()),
+		}
+	}
+}
+
+/// Run the messages of a connection using the `MessageCodec`, like an async
+/// `Client`, through `interceptors`.
+///
+/// Whatever was already read or buffered for writing is kept.
+#[cfg(feature = "async")]
+pub fn intercept<S>(
+	client: Framed<S, MessageCodec<OwnedMessage>>,
+	interceptors: Chain,
+) -> Framed<S, InterceptCodec<MessageCodec<OwnedMessage>>>
+where
+	S: AsyncStream,
+{
+	let FramedParts { io, codec, read_buf, write_buf, .. } = client.into_parts();
+	let mut parts = FramedParts::new(io, InterceptCodec::new(codec, interceptors));
+	parts.read_buf = read_buf;
+	parts.write_buf = write_buf;
+	Framed::from_parts(parts)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn text(text: &str) -> OwnedMessage {
+		OwnedMessage::Text(text.to_string())
+	}
+
+	#[test]
+	fn chains_run_in_order_and_stop_early() {
+		let counter = Counter::new();
+		let mut chain = Chain::new()
+			.with(outgoing(|message| match message {
+				OwnedMessage::Ping(_) => Action::Drop,
+				OwnedMessage::Text(text) => Action::Pass(OwnedMessage::Text(text.to_uppercase())),
+				message => Action::Pass(message),
+			}))
+			.with(counter.clone())
+			.with(incoming(|message| match message {
+				OwnedMessage::Text(ref text) if text == "boom" => {
+					Action::Fail(WebSocketError::ProtocolError("refused"))
+				}
+				message => Action::Pass(message),
+			}));
+
+		assert_eq!(chain.outgoing(text("hi")).into_result().unwrap(), Some(text("HI")));
+		assert_eq!(chain.outgoing(OwnedMessage::Ping(vec![])).into_result().unwrap(), None);
+		assert!(chain.incoming(text("boom")).into_result().is_err());
+		assert_eq!(chain.incoming(text("ok")).into_result().unwrap(), Some(text("ok")));
+		// the dropped ping and the refused message never got to the counter
+		assert_eq!(counter.counts(), Counts {
+			incoming: 1,
+			incoming_bytes: 2,
+			outgoing: 1,
+			outgoing_bytes: 2,
+		});
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_clients_stay_sync() {
+		use std::net::TcpStream;
+		use client::sync::{Client, Reader, Writer};
+
+		fn assert_sync<T: Sync>() {}
+		assert_sync::<Client<TcpStream>>();
+		assert_sync::<Reader<TcpStream>>();
+		assert_sync::<Writer<TcpStream>>();
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn writers_and_readers_intercept() {
+		use message::Message;
+		use receiver::{Reader, Receiver};
+		use sender::{Sender, Writer};
+		use std::io::{BufReader, Cursor};
+
+		let mut writer = Writer {
+			stream: Vec::new(),
+			sender: Sender::new(false),
+		};
+		writer.intercept(outgoing(|message| match message {
+			OwnedMessage::Text(_) => Action::Pass(text("***")),
+			message => Action::Pass(message),
+		}));
+		writer.send_message(&Message::text("secret")).unwrap();
+		writer.send_message(&Message::binary(vec![1, 2])).unwrap();
+		writer.send_message(&Message::text("again")).unwrap();
+
+		let mut reader = Reader {
+			stream: BufReader::new(Cursor::new(writer.stream)),
+			receiver: Receiver::new(false),
+		};
+		reader.intercept(incoming(|message| match message {
+			OwnedMessage::Binary(_) => Action::Drop,
+			message => Action::Pass(message),
+		}));
+		assert_eq!(reader.recv_message::<::std::vec::IntoIter<DataFrame>>().unwrap(), text("***"));
+		assert_eq!(reader.recv_message::<::std::vec::IntoIter<DataFrame>>().unwrap(), text("***"));
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn codecs_intercept() {
+		use codec::ws::Context;
+
+		let chain = Chain::new().with(incoming(|message| match message {
+			OwnedMessage::Pong(_) => Action::Drop,
+			message => Action::Pass(message),
+		}));
+		let mut client = MessageCodec::default(Context::Client);
+		let mut server = InterceptCodec::new(MessageCodec::default(Context::Server), chain);
+		let mut buf = BytesMut::new();
+
+		client.encode(OwnedMessage::Pong(vec![]), &mut buf).unwrap();
+		client.encode(text("after"), &mut buf).unwrap();
+		assert_eq!(server.decode(&mut buf).unwrap(), Some(text("after")));
+		assert_eq!(server.decode(&mut buf).unwrap(), None);
+	}
+}
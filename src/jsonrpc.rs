@@ -0,0 +1,749 @@
+//! JSON-RPC 2.0 over an async websocket connection.
+//!
+//! `connect` takes over an async `Client` and splits it into an `Rpc` handle
+//! to call the peer's methods and send it notifications, a stream of the
+//! `Request`s the peer sends, and a future that drives the connection and
+//! has to be spawned. Both sides of a connection use it the same way, so a
+//! server can call methods of its clients as well.
+//!
+//!```rust,no_run
+//!# extern crate websocket;
+//!# extern crate tokio;
+//!# extern crate serde_json;
+//!# fn main() {
+//!use serde_json::Value;
+//!use websocket::ClientBuilder;
+//!use websocket::futures::{Future, Stream};
+//!use websocket::jsonrpc;
+//!use tokio::reactor::Handle;
+//!
+//!let f = ClientBuilder::new("ws://127.0.0.1:2794").unwrap()
+//!    .async_connect_insecure(&Handle::default())
+//!    .map_err(|e| println!("{}", e))
+//!    .and_then(|(client, _)| {
+//!        let (rpc, requests, driver) = jsonrpc::connect(client);
+//!        tokio::spawn(driver);
+//!        // notifications of the server, it has no other requests
+//!        tokio::spawn(requests.for_each(|request| {
+//!            println!("{}: {:?}", request.method(), request.params());
+//!            Ok(())
+//!        }));
+//!
+//!        rpc.notify("subscribe", Some(Value::from("ticker"))).unwrap();
+//!        rpc.call("add", Some(Value::from(vec![1, 2])))
+//!            .map(|sum| println!("1 + 2 = {}", sum))
+//!            .map_err(|e| println!("{}", e))
+//!    });
+//!
+//!tokio::run(f);
+//!# }
+//!```
+//!
+//! Calls that are still waiting for their response when the connection is
+//! closed fail with `CallError::Closed`.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{future, Async, Future, Poll, Stream};
+use futures::future::Either;
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::sync::oneshot;
+use serde_json::{self, Map, Value};
+use tokio::timer::Delay;
+
+use client::async::Client;
+use driver::{self, Outbox};
+use message::OwnedMessage;
+use result::WebSocketError;
+use stream::async::Stream as AsyncStream;
+
+/// Invalid JSON was received.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist or is not available.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters.
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal JSON-RPC error.
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// The error object of a JSON-RPC response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+	/// The kind of error, see the constants of this module for the ones the
+	/// specification defines.
+	pub code: i64,
+	/// A short description of the error.
+	pub message: String,
+	/// Anything else about the error.
+	pub data: Option<Value>,
+}
+
+impl RpcError {
+	/// An error with a code and a message.
+	pub fn new<M: Into<String>>(code: i64, message: M) -> Self {
+		RpcError {
+			code: code,
+			message: message.into(),
+			data: None,
+		}
+	}
+
+	/// The method is not known.
+	pub fn method_not_found(method: &str) -> Self {
+		RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))
+	}
+
+	/// The parameters are not the ones the method takes.
+	pub fn invalid_params<M: Into<String>>(message: M) -> Self {
+		RpcError::new(INVALID_PARAMS, message)
+	}
+
+	/// Something went wrong handling the request.
+	pub fn internal_error<M: Into<String>>(message: M) -> Self {
+		RpcError::new(INTERNAL_ERROR, message)
+	}
+
+	fn to_value(&self) -> Value {
+		let mut error = Map::new();
+		error.insert("code".to_string(), Value::from(self.code));
+		error.insert("message".to_string(), Value::from(self.message.clone()));
+		if let Some(ref data) = self.data {
+			error.insert("data".to_string(), data.clone());
+		}
+		Value::Object(error)
+	}
+
+	fn from_value(mut error: Value) -> Self {
+		RpcError {
+			code: error.get("code").and_then(Value::as_i64).unwrap_or(INTERNAL_ERROR),
+			message: error.get("message").and_then(Value::as_str).unwrap_or("").to_string(),
+			data: error.get_mut("data").map(Value::take),
+		}
+	}
+}
+
+impl fmt::Display for RpcError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		write!(fmt, "JSON-RPC error {}: {}", self.code, self.message)
+	}
+}
+
+impl Error for RpcError {
+	fn description(&self) -> &str {
+		&self.message
+	}
+}
+
+/// Why a call did not get a result.
+#[derive(Debug)]
+pub enum CallError {
+	/// The peer answered with an error.
+	Rpc(RpcError),
+	/// The peer did not answer in time.
+	Timeout,
+	/// The connection was closed before the peer answered.
+	Closed,
+}
+
+impl fmt::Display for CallError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			CallError::Rpc(ref e) => fmt::Display::fmt(e, fmt),
+			_ => fmt.write_str(self.description()),
+		}
+	}
+}
+
+impl Error for CallError {
+	fn description(&self) -> &str {
+		match *self {
+			CallError::Rpc(ref e) => &e.message,
+			CallError::Timeout => "JSON-RPC call timed out",
+			CallError::Closed => "JSON-RPC connection closed",
+		}
+	}
+
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			CallError::Rpc(ref e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+/// The result of a call, once the peer answers.
+pub type CallFuture = Box<Future<Item = Value, Error = CallError> + Send>;
+
+type Pending = oneshot::Sender<Result<Value, CallError>>;
+
+enum Command {
+	Send(Value),
+	Call(Vec<(u64, Pending)>, Value),
+	Cancel(u64),
+	Release,
+}
+
+impl driver::Command for Command {
+	type Error = CallError;
+
+	fn release() -> Command {
+		Command::Release
+	}
+
+	fn closed() -> CallError {
+		CallError::Closed
+	}
+}
+
+/// Keeps the connection open, every `Rpc`, `Requests`, unanswered call and
+/// unanswered `Request` has one. The last one to go tells the driver.
+type Link = driver::Link<Command>;
+
+fn request(id: Option<u64>, method: &str, params: Option<Value>) -> Value {
+	let mut request = Map::new();
+	request.insert("jsonrpc".to_string(), Value::from("2.0"));
+	request.insert("method".to_string(), Value::from(method));
+	if let Some(params) = params {
+		request.insert("params".to_string(), params);
+	}
+	if let Some(id) = id {
+		request.insert("id".to_string(), Value::from(id));
+	}
+	Value::Object(request)
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+	let mut response = Map::new();
+	response.insert("jsonrpc".to_string(), Value::from("2.0"));
+	match result {
+		Ok(value) => response.insert("result".to_string(), value),
+		Err(error) => response.insert("error".to_string(), error.to_value()),
+	};
+	response.insert("id".to_string(), id);
+	Value::Object(response)
+}
+
+/// Calls methods of the peer and sends it notifications.
+///
+/// It can be cloned and used from any task. The connection is closed once
+/// every `Rpc` and the `Requests` of it are dropped and all calls, both ways,
+/// are answered.
+#[derive(Clone)]
+pub struct Rpc {
+	link: Link,
+	next_id: Arc<AtomicUsize>,
+	timeout: Option<Duration>,
+}
+
+impl Rpc {
+	/// A handle whose calls fail with `CallError::Timeout` if the peer takes
+	/// longer than `timeout` to answer. There is no timeout by default.
+	pub fn with_timeout(&self, timeout: Duration) -> Rpc {
+		Rpc {
+			timeout: Some(timeout),
+			..self.clone()
+		}
+	}
+
+	/// Call a method of the peer.
+	pub fn call(&self, method: &str, params: Option<Value>) -> CallFuture {
+		let id = self.next_id();
+		let (tx, rx) = oneshot::channel();
+		let call = Command::Call(vec![(id, tx)], request(Some(id), method, params));
+		if let Err(e) = self.link.send(call) {
+			return Box::new(future::err(e));
+		}
+		self.answer(id, rx)
+	}
+
+	/// Send a notification, a request the peer does not answer.
+	pub fn notify(&self, method: &str, params: Option<Value>) -> Result<(), CallError> {
+		self.link.send(Command::Send(request(None, method, params)))
+	}
+
+	/// Start a batch of calls and notifications that are sent together.
+	pub fn batch(&self) -> Batch {
+		Batch {
+			rpc: self.clone(),
+			requests: Vec::new(),
+			calls: Vec::new(),
+		}
+	}
+
+	fn next_id(&self) -> u64 {
+		self.next_id.fetch_add(1, Ordering::SeqCst) as u64
+	}
+
+	fn answer(&self, id: u64, rx: oneshot::Receiver<Result<Value, CallError>>) -> CallFuture {
+		let link = self.link.clone();
+		let answer = rx.then(|answer| match answer {
+			Ok(result) => result,
+			Err(oneshot::Canceled) => Err(CallError::Closed),
+		});
+		let timeout = match self.timeout {
+			Some(timeout) => timeout,
+			None => return Box::new(answer.then(move |answer| {
+				drop(link);
+				answer
+			})),
+		};
+
+		let delay = Delay::new(Instant::now() + timeout);
+		Box::new(answer.select2(delay).then(move |answered| match answered {
+			Ok(Either::A((value, _))) => Ok(value),
+			Err(Either::A((e, _))) => Err(e),
+			Ok(Either::B(_)) | Err(Either::B(_)) => {
+				// forget about the call, a late answer is dropped
+				let _ = link.send(Command::Cancel(id));
+				Err(CallError::Timeout)
+			}
+		}))
+	}
+}
+
+impl fmt::Debug for Rpc {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Rpc").field("timeout", &self.timeout).finish()
+	}
+}
+
+/// Calls and notifications that are sent in one message.
+pub struct Batch {
+	rpc: Rpc,
+	requests: Vec<Value>,
+	calls: Vec<(u64, Pending)>,
+}
+
+impl Batch {
+	/// Add a call to the batch. Its result is only sent for once the batch is.
+	pub fn call(&mut self, method: &str, params: Option<Value>) -> CallFuture {
+		let id = self.rpc.next_id();
+		let (tx, rx) = oneshot::channel();
+		self.requests.push(request(Some(id), method, params));
+		self.calls.push((id, tx));
+		self.rpc.answer(id, rx)
+	}
+
+	/// Add a notification to the batch.
+	pub fn notify(&mut self, method: &str, params: Option<Value>) {
+		self.requests.push(request(None, method, params));
+	}
+
+	/// Send the batch. Nothing is sent for an empty batch.
+	pub fn send(self) -> Result<(), CallError> {
+		if self.requests.is_empty() {
+			return Ok(());
+		}
+		self.rpc.link.send(Command::Call(self.calls, Value::Array(self.requests)))
+	}
+}
+
+/// The responses to a batch the peer sent, which go out together.
+struct BatchReply {
+	responses: Mutex<(Vec<Value>, usize)>,
+	link: Link,
+}
+
+impl BatchReply {
+	/// Add the response to one of the calls of the batch.
+	fn add(&self, response: Value) {
+		let mut responses = self.responses.lock().unwrap_or_else(|e| e.into_inner());
+		responses.0.push(response);
+		self.answered(&mut responses);
+	}
+
+	fn answered(&self, responses: &mut (Vec<Value>, usize)) {
+		responses.1 -= 1;
+		if responses.1 == 0 && !responses.0.is_empty() {
+			let batch = mem::replace(&mut responses.0, Vec::new());
+			let _ = self.link.send(Command::Send(Value::Array(batch)));
+		}
+	}
+}
+
+enum Reply {
+	Single(Value, Link),
+	Batch(Value, Arc<BatchReply>),
+}
+
+/// A call or notification from the peer.
+///
+/// A call that is dropped without a response is answered with a
+/// `METHOD_NOT_FOUND` error.
+pub struct Request {
+	method: String,
+	params: Option<Value>,
+	reply: Option<Reply>,
+}
+
+impl Request {
+	/// The method the peer wants called.
+	pub fn method(&self) -> &str {
+		&self.method
+	}
+
+	/// The parameters of the call.
+	pub fn params(&self) -> Option<&Value> {
+		self.params.as_ref()
+	}
+
+	/// Take the parameters out of the request.
+	pub fn take_params(&mut self) -> Option<Value> {
+		self.params.take()
+	}
+
+	/// Whether this is a notification, which is not answered.
+	pub fn is_notification(&self) -> bool {
+		self.reply.is_none()
+	}
+
+	/// Answer the call, does nothing for a notification.
+	pub fn respond(mut self, result: Result<Value, RpcError>) {
+		self.send(result);
+	}
+
+	fn send(&mut self, result: Result<Value, RpcError>) {
+		match self.reply.take() {
+			Some(Reply::Single(id, link)) => {
+				let _ = link.send(Command::Send(response(id, result)));
+			}
+			Some(Reply::Batch(id, batch)) => batch.add(response(id, result)),
+			None => (),
+		}
+	}
+}
+
+impl Drop for Request {
+	fn drop(&mut self) {
+		if self.reply.is_some() {
+			let error = RpcError::method_not_found(&self.method);
+			self.send(Err(error));
+		}
+	}
+}
+
+impl fmt::Debug for Request {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Request")
+		 .field("method", &self.method)
+		 .field("params", &self.params)
+		 .field("notification", &self.is_notification())
+		 .finish()
+	}
+}
+
+/// The requests the peer sends, the stream ends with the connection.
+pub struct Requests {
+	requests: UnboundedReceiver<Request>,
+	_link: Link,
+}
+
+impl Stream for Requests {
+	type Item = Request;
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<Option<Request>, ()> {
+		self.requests.poll()
+	}
+}
+
+impl fmt::Debug for Requests {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Requests").finish()
+	}
+}
+
+/// Speak JSON-RPC over `client`.
+///
+/// Returns the handle to call the peer with, the requests of the peer and
+/// the future that drives the connection, which must be spawned. The future
+/// ends once the connection is closed.
+pub fn connect<S>(client: Client<S>) -> (Rpc, Requests, Box<Future<Item = (), Error = ()> + Send>)
+where
+	S: AsyncStream + Send + 'static,
+{
+	let (commands_tx, commands_rx) = mpsc::unbounded();
+	let (requests_tx, requests_rx) = mpsc::unbounded();
+	let users = Arc::new(AtomicUsize::new(0));
+	let rpc = Rpc {
+		link: Link::new(&commands_tx, &users),
+		next_id: Arc::new(AtomicUsize::new(1)),
+		timeout: None,
+	};
+	let requests = Requests {
+		requests: requests_rx,
+		_link: Link::new(&commands_tx, &users),
+	};
+	let driver = Driver {
+		client: client,
+		commands: commands_rx,
+		commands_tx: commands_tx,
+		users: users,
+		requests: requests_tx,
+		pending: HashMap::new(),
+		outbox: Outbox::new(),
+		close_received: false,
+	};
+	(rpc, requests, Box::new(driver))
+}
+
+struct Driver<S> {
+	client: Client<S>,
+	commands: UnboundedReceiver<Command>,
+	// for the links of the peer's requests
+	commands_tx: UnboundedSender<Command>,
+	users: Arc<AtomicUsize>,
+	requests: UnboundedSender<Request>,
+	pending: HashMap<u64, Pending>,
+	outbox: Outbox,
+	close_received: bool,
+}
+
+impl<S> Driver<S>
+where
+	S: AsyncStream + Send + 'static,
+{
+	fn link(&self) -> Link {
+		Link::new(&self.commands_tx, &self.users)
+	}
+
+	fn poll_commands(&mut self) {
+		loop {
+			match self.commands.poll() {
+				Ok(Async::Ready(Some(Command::Send(message)))) => self.send(message),
+				Ok(Async::Ready(Some(Command::Call(calls, message)))) => {
+					self.pending.extend(calls);
+					self.send(message);
+				}
+				Ok(Async::Ready(Some(Command::Cancel(id)))) => {
+					self.pending.remove(&id);
+				}
+				// nobody is left to talk to the peer
+				Ok(Async::Ready(Some(Command::Release))) => {
+					if self.users.load(Ordering::SeqCst) == 0 {
+						self.outbox.close();
+					}
+				}
+				// the driver keeps a sender, the commands can't end
+				Ok(Async::Ready(None)) | Err(()) |
+				Ok(Async::NotReady) => return,
+			}
+		}
+	}
+
+	fn send(&mut self, message: Value) {
+		self.outbox.push(OwnedMessage::Text(message.to_string()));
+	}
+
+	fn receive(&mut self, message: OwnedMessage) {
+		let parsed = match message {
+			OwnedMessage::Text(text) => serde_json::from_str(&text),
+			OwnedMessage::Binary(data) => serde_json::from_slice(&data),
+			OwnedMessage::Ping(data) => return self.outbox.push(OwnedMessage::Pong(data)),
+			OwnedMessage::Pong(_) => return,
+			OwnedMessage::Close(_) => {
+				self.close_received = true;
+				return self.outbox.close();
+			}
+		};
+
+		match parsed {
+			Ok(Value::Array(items)) => self.receive_batch(items),
+			Ok(item) => {
+				if let Some(answer) = self.receive_one(item, None) {
+					self.send(answer);
+				}
+			}
+			Err(e) => {
+				let error = RpcError::new(PARSE_ERROR, e.to_string());
+				self.send(response(Value::Null, Err(error)));
+			}
+		}
+	}
+
+	fn receive_batch(&mut self, items: Vec<Value>) {
+		if items.is_empty() {
+			let error = RpcError::new(INVALID_REQUEST, "Empty batch");
+			return self.send(response(Value::Null, Err(error)));
+		}
+
+		let calls = items.iter().filter(|item| is_call(item)).count();
+		let batch = Arc::new(BatchReply {
+			// one more than there are calls, so the batch is only sent
+			// once all of it was looked at
+			responses: Mutex::new((Vec::new(), calls + 1)),
+			link: self.link(),
+		});
+		for item in items {
+			if let Some(answer) = self.receive_one(item, Some(&batch)) {
+				batch.responses.lock().unwrap_or_else(|e| e.into_inner()).0.push(answer);
+			}
+		}
+		let mut responses = batch.responses.lock().unwrap_or_else(|e| e.into_inner());
+		batch.answered(&mut responses);
+	}
+
+	/// Handle a request or response, returns the answer to send right away
+	/// if there is one.
+	fn receive_one(&mut self, mut item: Value, batch: Option<&Arc<BatchReply>>) -> Option<Value> {
+		let method = match item.get("method") {
+			Some(&Value::String(ref method)) => Some(method.clone()),
+			Some(_) => None,
+			None => return self.receive_response(item),
+		};
+		let id = item.get_mut("id").map(Value::take);
+		let method = match method {
+			Some(method) => method,
+			None => {
+				let error = RpcError::new(INVALID_REQUEST, "Invalid Request");
+				return Some(response(id.unwrap_or(Value::Null), Err(error)));
+			}
+		};
+
+		let reply = id.map(|id| match batch {
+			Some(batch) => Reply::Batch(id, batch.clone()),
+			None => Reply::Single(id, self.link()),
+		});
+		let request = Request {
+			method: method,
+			params: item.get_mut("params").map(Value::take),
+			reply: reply,
+		};
+		// a request nobody listens for is dropped and answered as unknown
+		let _ = self.requests.unbounded_send(request);
+		None
+	}
+
+	fn receive_response(&mut self, mut item: Value) -> Option<Value> {
+		let id = item.get("id").and_then(Value::as_u64);
+		let result = match (item.get_mut("result").map(Value::take), item.get_mut("error").map(Value::take)) {
+			(Some(result), None) => Ok(result),
+			(None, Some(error)) => Err(CallError::Rpc(RpcError::from_value(error))),
+			_ => {
+				let error = RpcError::new(INVALID_REQUEST, "Invalid Request");
+				return Some(response(Value::Null, Err(error)));
+			}
+		};
+		if let Some(call) = id.and_then(|id| self.pending.remove(&id)) {
+			let _ = call.send(result);
+		}
+		None
+	}
+
+	fn poll_connection(&mut self) -> Poll<(), WebSocketError> {
+		loop {
+			self.poll_commands();
+			let flushed = self.outbox.write(&mut self.client)?;
+			// only done once the close went out
+			if self.outbox.close_sent() && self.close_received && flushed.is_ready() {
+				return Ok(Async::Ready(()));
+			}
+			match self.client.poll()? {
+				Async::Ready(Some(message)) => self.receive(message),
+				Async::Ready(None) => return Ok(Async::Ready(())),
+				Async::NotReady => return Ok(Async::NotReady),
+			}
+		}
+	}
+}
+
+/// Whether the item is a request that gets a `Reply`.
+fn is_call(item: &Value) -> bool {
+	item.get("method").map_or(false, Value::is_string) && item.get("id").is_some()
+}
+
+impl<S> Future for Driver<S>
+where
+	S: AsyncStream + Send + 'static,
+{
+	type Item = ();
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<(), ()> {
+		match self.poll_connection() {
+			Ok(Async::NotReady) => Ok(Async::NotReady),
+			Ok(Async::Ready(())) | Err(_) => {
+				for (_, call) in self.pending.drain() {
+					let _ = call.send(Err(CallError::Closed));
+				}
+				Ok(Async::Ready(()))
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio;
+	use driver::pair;
+
+	#[test]
+	fn calls_batches_and_notifications() {
+		let test = pair().map_err(|e| panic!("{}", e)).and_then(|(client, server)| {
+			let (server_rpc, requests, server_driver) = connect(server);
+			tokio::spawn(server_driver);
+			tokio::spawn(requests.for_each(|request| {
+				let result = match request.method() {
+					"add" => {
+						let terms: Vec<i64> = serde_json::from_value(request.params().unwrap().clone()).unwrap();
+						Ok(Value::from(terms.iter().sum::<i64>()))
+					}
+					method => Err(RpcError::method_not_found(method)),
+				};
+				request.respond(result);
+				Ok(())
+			}));
+			server_rpc.notify("tick", None).unwrap();
+
+			let (rpc, notifications, driver) = connect(client);
+			tokio::spawn(driver);
+			let mut batch = rpc.batch();
+			let three = batch.call("add", Some(Value::from(vec![1, 2])));
+			let seven = batch.call("add", Some(Value::from(vec![3, 4])));
+			batch.notify("ignored", None);
+			batch.send().unwrap();
+			let unknown = rpc.call("nope", None).then(|result| match result {
+				Err(CallError::Rpc(ref e)) if e.code == METHOD_NOT_FOUND => Ok(()),
+				other => panic!("unexpected {:?}", other),
+			});
+
+			let calls = three.join3(seven, unknown).map(|(three, seven, ())| {
+				assert_eq!(three, Value::from(3));
+				assert_eq!(seven, Value::from(7));
+			});
+			let tick = notifications.into_future().map(|(tick, _)| {
+				let tick = tick.unwrap();
+				assert_eq!(tick.method(), "tick");
+				assert!(tick.is_notification());
+			});
+			calls.map_err(|e| panic!("{}", e))
+			     .join(tick.map_err(|_| panic!("no notification")))
+			     .map(|_| ())
+		});
+		tokio::run(test);
+	}
+
+	#[test]
+	fn pending_calls_fail_when_the_connection_closes() {
+		let test = pair().map_err(|e| panic!("{}", e)).and_then(|(client, server)| {
+			let (rpc, _requests, driver) = connect(client);
+			tokio::spawn(driver);
+			let call = rpc.call("slow", None).then(|result| match result {
+				Err(CallError::Closed) => Ok(()),
+				other => panic!("unexpected {:?}", other),
+			});
+			// read the call and hang up without answering it
+			let hang_up = server.into_future().map(|_| ()).map_err(|(e, _)| panic!("{}", e));
+			hang_up.join(call).map(|_| ())
+		});
+		tokio::run(test);
+	}
+}
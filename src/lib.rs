@@ -18,6 +18,9 @@
 //! convenience module called `websocket::sync` and `websocket::async` has been added that
 //! groups all the sync and async stuff, respectively.
 //!
+//! Both handshakes are built on the HTTP codec in `codec::http`, so unlike before
+//! the `sync` feature also pulls in `tokio-codec` and `bytes`.
+//!
 //! # Clients
 //! To make a client use the `ClientBuilder` struct, this builder has methods
 //! for creating both synchronous and asynchronous clients.
@@ -45,14 +48,26 @@ extern crate native_tls;
 extern crate tokio;
 #[cfg(feature = "async")]
 extern crate tokio_io;
-#[cfg(feature = "async")]
+#[cfg(any(feature = "sync", feature = "async"))]
 extern crate tokio_codec;
-#[cfg(feature = "async")]
+#[cfg(any(feature = "sync", feature = "async"))]
 extern crate bytes;
 #[cfg(feature = "async")]
 pub extern crate futures;
 #[cfg(feature = "async-ssl")]
 extern crate tokio_tls;
+#[cfg(feature = "evented")]
+extern crate mio;
+#[cfg(feature = "std-future")]
+extern crate futures03;
+#[cfg(feature = "serde")]
+extern crate serde_crate as serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
+#[cfg(feature = "cbor")]
+extern crate serde_cbor;
 
 #[macro_use]
 extern crate bitflags;
@@ -65,8 +80,9 @@ pub mod dataframe;
 pub mod message;
 pub mod result;
 pub mod header;
+pub mod intercept;
 
-#[cfg(feature = "async")]
+#[cfg(any(feature = "sync", feature = "async"))]
 pub mod codec;
 
 #[cfg(feature = "sync")]
@@ -78,11 +94,26 @@ pub mod client;
 pub mod server;
 pub mod stream;
 
+#[cfg(feature = "evented")]
+pub mod evented;
+#[cfg(feature = "std-future")]
+pub mod std_future;
+#[cfg(all(feature = "serde", feature = "async"))]
+pub mod jsonrpc;
+#[cfg(feature = "async")]
+mod driver;
+#[cfg(feature = "sync")]
+pub mod stomp;
+#[cfg(any(feature = "sync", feature = "async"))]
+pub mod mux;
+#[cfg(any(feature = "sync", feature = "async"))]
+pub mod reliable;
+
 /// A collection of handy synchronous-only parts of the crate.
 #[cfg(feature = "sync")]
 pub mod sync {
 	pub use sender;
-	pub use sender::Writer;
+	pub use sender::{Writer, SharedWriter};
 
 	pub use receiver;
 	pub use receiver::Reader;
@@ -96,6 +127,9 @@ pub mod sync {
 		pub use server::upgrade::sync::Upgrade;
 		pub use server::upgrade::sync::IntoWs;
 		pub use server::upgrade::sync as upgrade;
+		pub use server::router::{Router, Params};
+		pub use server::pool::HandshakePool;
+		pub use server::runtime::{Runtime, Handler, Outbound};
 	}
 	pub use server::sync::Server;
 
@@ -125,6 +159,8 @@ pub mod async {
 		pub use server::upgrade::async::Upgrade;
 		pub use server::upgrade::async::IntoWs;
 		pub use server::upgrade::async as upgrade;
+		pub use server::router::{Router, Params};
+		pub use server::service::{serve, Handler, Outbound};
 	}
 	pub use server::async::Server;
 
@@ -132,6 +168,7 @@ pub mod async {
 	pub mod client {
 		pub use client::async::*;
 		pub use client::builder::ClientBuilder;
+		pub use client::reconnect::ReconnectingClient;
 	}
 	pub use client::async::Client;
 
@@ -241,6 +241,10 @@ impl<'a> ws::Message for Message<'a> {
 		};
 		Ok(msg)
 	}
+
+	fn to_owned_message(&self) -> WebSocketResult<OwnedMessage> {
+		Ok(self.clone().into())
+	}
 }
 
 /// Represents an owned WebSocket message.
@@ -361,6 +365,10 @@ impl ws::Message for OwnedMessage {
 	{
 		Ok(Message::from_dataframes(frames)?.into())
 	}
+
+	fn to_owned_message(&self) -> WebSocketResult<OwnedMessage> {
+		Ok(self.clone())
+	}
 }
 
 impl ws::dataframe::DataFrame for OwnedMessage {
@@ -0,0 +1,559 @@
+//! Channels over an async `Client`.
+//!
+//! `connect` takes over the client and returns a `Mux` handle to open
+//! channels with, the stream of channels the peer opens and a future that
+//! drives the connection, which has to be spawned. Every `Channel` is a
+//! `Stream` of the payloads that arrive on it and a `Sink` of payloads to
+//! send, which is not ready while the channel's window is used up.
+//!
+//!```rust,no_run
+//!# extern crate websocket;
+//!# extern crate tokio;
+//!# fn main() {
+//!use websocket::ClientBuilder;
+//!use websocket::codec::ws::Context;
+//!use websocket::futures::{Future, Sink, Stream};
+//!use websocket::mux;
+//!use tokio::reactor::Handle;
+//!
+//!let f = ClientBuilder::new("ws://127.0.0.1:2794").unwrap()
+//!    .async_connect_insecure(&Handle::default())
+//!    .map_err(|e| println!("{}", e))
+//!    .and_then(|(client, _)| {
+//!        let (mux, _incoming, driver) = mux::async::connect(client, Context::Client);
+//!        tokio::spawn(driver);
+//!
+//!        let logs = mux.open_named("logs").unwrap();
+//!        logs.send(b"tail".to_vec())
+//!            .and_then(|logs| logs.for_each(|line| {
+//!                println!("{}", String::from_utf8_lossy(&line));
+//!                Ok(())
+//!            }))
+//!            .map_err(|e| println!("{}", e))
+//!    });
+//!
+//!tokio::run(f);
+//!# }
+//!```
+//!
+//! The connection is closed once the `Mux`, the stream of incoming channels
+//! and every channel are dropped.
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::task::{self, Task};
+
+use client::async::Client;
+use driver::{self, Outbox};
+use codec::ws::Context;
+use message::OwnedMessage;
+use stream::async::Stream as AsyncStream;
+use super::{Consumed, Credit, Frame, MuxError, Numbers, INITIAL_WINDOW, MAX_PAYLOAD};
+
+enum Command {
+	Open(u32, String, Entry, u32),
+	Data(u32, Vec<u8>),
+	Close(u32),
+	Grant(u32, u32),
+	Release,
+}
+
+impl driver::Command for Command {
+	type Error = MuxError;
+
+	fn release() -> Command {
+		Command::Release
+	}
+
+	fn closed() -> MuxError {
+		MuxError::Closed
+	}
+}
+
+/// Keeps the connection open, the `Mux`, `Incoming` and every `Channel`
+/// have one. The last one to go tells the driver.
+type Link = driver::Link<Command>;
+
+/// The sending side of a channel, shared with the driver, which hands out
+/// the window the peer grants.
+struct Window {
+	credit: Credit,
+	// whether the driver is gone
+	closed: bool,
+	task: Option<Task>,
+}
+
+type SharedWindow = Arc<Mutex<Window>>;
+
+/// What the driver keeps of a channel.
+struct Entry {
+	// dropped once the peer closed the channel
+	payloads: Option<UnboundedSender<Vec<u8>>>,
+	window: SharedWindow,
+	receive: Credit,
+	closed: bool,
+	peer_closed: bool,
+}
+
+/// A logical channel, a `Stream` and a `Sink` of payloads.
+///
+/// Closing the sink closes the channel for sending, the stream ends once
+/// the peer closed it. Dropping the channel closes it as well, payloads
+/// that arrive after that are dropped.
+pub struct Channel {
+	id: u32,
+	name: String,
+	payloads: UnboundedReceiver<Vec<u8>>,
+	window: SharedWindow,
+	consumed: Consumed,
+	closed: bool,
+	link: Link,
+}
+
+impl Channel {
+	fn new(id: u32, name: String, window: u32, link: Link) -> (Channel, Entry) {
+		let (tx, rx) = mpsc::unbounded();
+		let shared = Arc::new(Mutex::new(Window {
+			credit: Credit::new(),
+			closed: false,
+			task: None,
+		}));
+		let channel = Channel {
+			id: id,
+			name: name,
+			payloads: rx,
+			window: shared.clone(),
+			consumed: Consumed::new(window),
+			closed: false,
+			link: link,
+		};
+		let entry = Entry {
+			payloads: Some(tx),
+			window: shared,
+			receive: Credit::new(),
+			closed: false,
+			peer_closed: false,
+		};
+		(channel, entry)
+	}
+
+	/// The number of the channel.
+	pub fn id(&self) -> u32 {
+		self.id
+	}
+
+	/// The name of the channel, empty if it has none.
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+}
+
+impl Stream for Channel {
+	type Item = Vec<u8>;
+	type Error = MuxError;
+
+	fn poll(&mut self) -> Poll<Option<Vec<u8>>, MuxError> {
+		match self.payloads.poll() {
+			Ok(Async::Ready(Some(payload))) => {
+				if let Some(bytes) = self.consumed.add(payload.len()) {
+					let _ = self.link.send(Command::Grant(self.id, bytes));
+				}
+				Ok(Async::Ready(Some(payload)))
+			}
+			Ok(Async::Ready(None)) | Err(()) => Ok(Async::Ready(None)),
+			Ok(Async::NotReady) => Ok(Async::NotReady),
+		}
+	}
+}
+
+impl Sink for Channel {
+	type SinkItem = Vec<u8>;
+	type SinkError = MuxError;
+
+	fn start_send(&mut self, payload: Vec<u8>) -> StartSend<Vec<u8>, MuxError> {
+		if self.closed {
+			return Err(MuxError::ChannelClosed(self.id));
+		}
+		if payload.len() > MAX_PAYLOAD {
+			return Err(MuxError::PayloadTooLarge(payload.len()));
+		}
+		let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+		if window.closed {
+			return Err(MuxError::Closed);
+		}
+		if !window.credit.fits(payload.len()) {
+			window.task = Some(task::current());
+			return Ok(AsyncSink::NotReady(payload));
+		}
+		window.credit.take(payload.len());
+		self.link.send(Command::Data(self.id, payload))?;
+		Ok(AsyncSink::Ready)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), MuxError> {
+		// the driver sends what it gets as soon as it can
+		Ok(Async::Ready(()))
+	}
+
+	fn close(&mut self) -> Poll<(), MuxError> {
+		if !self.closed {
+			self.closed = true;
+			self.link.send(Command::Close(self.id))?;
+		}
+		Ok(Async::Ready(()))
+	}
+}
+
+impl Drop for Channel {
+	fn drop(&mut self) {
+		if !self.closed {
+			let _ = self.link.send(Command::Close(self.id));
+		}
+	}
+}
+
+impl fmt::Debug for Channel {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Channel").field("id", &self.id).field("name", &self.name).finish()
+	}
+}
+
+/// Opens channels, it can be cloned and used from any task.
+#[derive(Clone)]
+pub struct Mux {
+	link: Link,
+	numbers: Arc<Mutex<Numbers>>,
+	window: u32,
+}
+
+impl Mux {
+	/// Open a channel without a name.
+	pub fn open(&self) -> Result<Channel, MuxError> {
+		self.open_named("")
+	}
+
+	/// Open a channel with a name.
+	pub fn open_named(&self, name: &str) -> Result<Channel, MuxError> {
+		// the numbers go out in order, the peer refuses one that was passed
+		let mut numbers = self.numbers.lock().unwrap_or_else(|e| e.into_inner());
+		let id = numbers.next()?;
+		let (channel, entry) = Channel::new(id, name.to_string(), self.window, self.link.clone());
+		self.link.send(Command::Open(id, name.to_string(), entry, self.window))?;
+		Ok(channel)
+	}
+
+	/// A handle whose channels let up to `bytes` be on their way to them,
+	/// no less than `INITIAL_WINDOW`, which is the default.
+	pub fn with_window(&self, bytes: u32) -> Mux {
+		Mux {
+			window: cmp::max(bytes, INITIAL_WINDOW),
+			..self.clone()
+		}
+	}
+}
+
+impl fmt::Debug for Mux {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Mux").field("window", &self.window).finish()
+	}
+}
+
+/// The channels the peer opens, the stream ends with the connection.
+///
+/// Their window is `INITIAL_WINDOW`.
+pub struct Incoming {
+	channels: UnboundedReceiver<Channel>,
+	_link: Link,
+}
+
+impl Stream for Incoming {
+	type Item = Channel;
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<Option<Channel>, ()> {
+		self.channels.poll()
+	}
+}
+
+impl fmt::Debug for Incoming {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Incoming").finish()
+	}
+}
+
+/// Carry channels over `client`, `context` is the side of the connection
+/// this is.
+///
+/// Returns the handle to open channels with, the channels the peer opens and
+/// the future that drives the connection, which must be spawned. The future
+/// ends once the connection is closed.
+pub fn connect<S>(client: Client<S>, context: Context) -> (Mux, Incoming, Box<Future<Item = (), Error = ()> + Send>)
+where
+	S: AsyncStream + Send + 'static,
+{
+	let (commands_tx, commands_rx) = mpsc::unbounded();
+	let (incoming_tx, incoming_rx) = mpsc::unbounded();
+	let users = Arc::new(AtomicUsize::new(0));
+	let numbers = Numbers::new(context == Context::Client);
+	let mux = Mux {
+		link: Link::new(&commands_tx, &users),
+		numbers: Arc::new(Mutex::new(Numbers::new(context == Context::Client))),
+		window: INITIAL_WINDOW,
+	};
+	let incoming = Incoming {
+		channels: incoming_rx,
+		_link: Link::new(&commands_tx, &users),
+	};
+	let driver = Driver {
+		client: client,
+		commands: commands_rx,
+		commands_tx: commands_tx,
+		users: users,
+		incoming: incoming_tx,
+		numbers: numbers,
+		channels: HashMap::new(),
+		outbox: Outbox::new(),
+		close_received: false,
+	};
+	(mux, incoming, Box::new(driver))
+}
+
+struct Driver<S> {
+	client: Client<S>,
+	commands: UnboundedReceiver<Command>,
+	// for the links of the peer's channels
+	commands_tx: UnboundedSender<Command>,
+	users: Arc<AtomicUsize>,
+	incoming: UnboundedSender<Channel>,
+	// to tell the peer's channel numbers from ours
+	numbers: Numbers,
+	channels: HashMap<u32, Entry>,
+	outbox: Outbox,
+	close_received: bool,
+}
+
+impl<S> Driver<S>
+where
+	S: AsyncStream + Send + 'static,
+{
+	fn poll_commands(&mut self) {
+		loop {
+			match self.commands.poll() {
+				Ok(Async::Ready(Some(Command::Open(id, name, entry, window)))) => {
+					self.send(Frame::Open(id, name));
+					self.add(id, entry, window);
+				}
+				Ok(Async::Ready(Some(Command::Data(id, payload)))) => self.send(Frame::Data(id, payload)),
+				Ok(Async::Ready(Some(Command::Close(id)))) => {
+					let gone = match self.channels.get_mut(&id) {
+						Some(entry) => {
+							entry.closed = true;
+							entry.peer_closed
+						}
+						None => false,
+					};
+					if gone {
+						self.channels.remove(&id);
+					}
+					self.send(Frame::Close(id));
+				}
+				Ok(Async::Ready(Some(Command::Grant(id, bytes)))) => self.grant(id, bytes),
+				// nobody is left to use the connection
+				Ok(Async::Ready(Some(Command::Release))) => {
+					if self.users.load(Ordering::SeqCst) == 0 {
+						self.outbox.close();
+					}
+				}
+				// the driver keeps a sender, the commands can't end
+				Ok(Async::Ready(None)) | Err(()) |
+				Ok(Async::NotReady) => return,
+			}
+		}
+	}
+
+	/// Start keeping count of a channel, `window` is the window of its
+	/// receiving side.
+	fn add(&mut self, id: u32, entry: Entry, window: u32) {
+		self.channels.insert(id, entry);
+		if let Some(bytes) = Consumed::new(window).initial_grant() {
+			self.grant(id, bytes);
+		}
+	}
+
+	fn grant(&mut self, id: u32, bytes: u32) {
+		if let Some(entry) = self.channels.get_mut(&id) {
+			entry.receive.grant(bytes);
+			self.outbox.push(Frame::Window(id, bytes).to_message());
+		}
+	}
+
+	fn send(&mut self, frame: Frame) {
+		self.outbox.push(frame.to_message());
+	}
+
+	fn receive(&mut self, message: OwnedMessage) -> Result<(), MuxError> {
+		let frame = match message {
+			OwnedMessage::Ping(data) => {
+				self.outbox.push(OwnedMessage::Pong(data));
+				return Ok(());
+			}
+			OwnedMessage::Close(_) => {
+				self.close_received = true;
+				self.outbox.close();
+				return Ok(());
+			}
+			message => match Frame::from_message(&message)? {
+				Some(frame) => frame,
+				None => return Ok(()),
+			},
+		};
+
+		match frame {
+			Frame::Open(id, name) => {
+				if !self.numbers.peer_opens(id) {
+					return Err(MuxError::Protocol("channel can't be opened"));
+				}
+				// a channel the peer opens at our side is not the peer's
+				let link = Link::new(&self.commands_tx, &self.users);
+				let (channel, entry) = Channel::new(id, name, INITIAL_WINDOW, link);
+				self.add(id, entry, INITIAL_WINDOW);
+				// nobody takes the channel, it is dropped and closed
+				let _ = self.incoming.unbounded_send(channel);
+			}
+			Frame::Data(id, payload) => {
+				let (len, dropped) = match self.channels.get_mut(&id) {
+					Some(ref entry) if entry.peer_closed => return Err(MuxError::Protocol("data on a closed channel")),
+					Some(entry) => {
+						if !entry.receive.fits(payload.len()) {
+							return Err(MuxError::Protocol("window exceeded"));
+						}
+						entry.receive.take(payload.len());
+						let len = payload.len() as u32;
+						let sent = entry.payloads.as_ref().map(|payloads| payloads.unbounded_send(payload));
+						(len, sent.map_or(true, |sent| sent.is_err()))
+					}
+					None => return Err(MuxError::Protocol("data on a closed channel")),
+				};
+				if dropped {
+					// nobody reads the channel any more, don't keep the peer waiting
+					self.grant(id, len);
+				}
+			}
+			Frame::Close(id) => {
+				let gone = match self.channels.get_mut(&id) {
+					Some(ref entry) if entry.peer_closed => return Err(MuxError::Protocol("close of a closed channel")),
+					Some(entry) => {
+						entry.peer_closed = true;
+						entry.payloads = None;
+						entry.closed
+					}
+					None => return Err(MuxError::Protocol("close of a closed channel")),
+				};
+				if gone {
+					self.channels.remove(&id);
+				}
+			}
+			Frame::Window(id, bytes) => {
+				// the channel may be gone already
+				if let Some(entry) = self.channels.get(&id) {
+					let mut window = entry.window.lock().unwrap_or_else(|e| e.into_inner());
+					window.credit.grant(bytes);
+					if let Some(task) = window.task.take() {
+						task.notify();
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn poll_connection(&mut self) -> Poll<(), MuxError> {
+		loop {
+			self.poll_commands();
+			let flushed = self.outbox.write(&mut self.client)?;
+			// only done once the close went out
+			if self.outbox.close_sent() && self.close_received && flushed.is_ready() {
+				return Ok(Async::Ready(()));
+			}
+			match self.client.poll()? {
+				Async::Ready(Some(message)) => self.receive(message)?,
+				Async::Ready(None) => return Ok(Async::Ready(())),
+				Async::NotReady => return Ok(Async::NotReady),
+			}
+		}
+	}
+}
+
+impl<S> Future for Driver<S>
+where
+	S: AsyncStream + Send + 'static,
+{
+	type Item = ();
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<(), ()> {
+		match self.poll_connection() {
+			Ok(Async::NotReady) => Ok(Async::NotReady),
+			Ok(Async::Ready(())) | Err(_) => {
+				// end the channels' streams and fail their sinks
+				for (_, entry) in self.channels.drain() {
+					let mut window = entry.window.lock().unwrap_or_else(|e| e.into_inner());
+					window.closed = true;
+					if let Some(task) = window.task.take() {
+						task.notify();
+					}
+				}
+				Ok(Async::Ready(()))
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{future, stream};
+	use tokio;
+	use driver::pair;
+
+	#[test]
+	fn channels_carry_payloads_both_ways() {
+		let test = pair().map_err(|e| panic!("{}", e)).and_then(|(client, server)| {
+			// the server echoes every channel, payloads reversed
+			let (_server_mux, incoming, server_driver) = connect(server, Context::Server);
+			tokio::spawn(server_driver);
+			tokio::spawn(incoming.for_each(|channel| {
+				let (sink, stream) = channel.split();
+				let echo = stream.map(|mut payload| {
+					payload.reverse();
+					payload
+				});
+				tokio::spawn(sink.send_all(echo).map(|_| ()).map_err(|e| panic!("{}", e)));
+				Ok(())
+			}));
+
+			let (mux, _incoming, driver) = connect(client, Context::Client);
+			tokio::spawn(driver);
+			let first = mux.open_named("first").unwrap();
+			let second = mux.open().unwrap();
+			assert_eq!((first.id(), first.name(), second.id()), (1, "first", 3));
+
+			// enough to use up the window a few times over
+			let big = (0..5).map(|_| vec![1u8; MAX_PAYLOAD]).collect::<Vec<_>>();
+			let (sink, echoes) = first.split();
+			let sent = sink.send_all(stream::iter_ok::<_, MuxError>(big))
+			               .and_then(|(mut sink, _)| future::poll_fn(move || sink.close()));
+			let first = sent.join(echoes.collect()).map(|((), echoed)| assert_eq!(echoed.len(), 5));
+			let second = second.send(vec![1, 2, 3])
+			                   .and_then(|second| second.into_future().map_err(|(e, _)| e))
+			                   .map(|(echoed, _)| assert_eq!(echoed, Some(vec![3, 2, 1])));
+			first.join(second).map(|_| ()).map_err(|e| panic!("{}", e))
+		});
+		tokio::run(test);
+	}
+}
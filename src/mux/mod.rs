@@ -0,0 +1,327 @@
+//! Many logical channels over one websocket connection.
+//!
+//! Each channel carries its own sequence of payloads, can be closed on its
+//! own and has a flow-control window, so a busy channel can't starve the
+//! others. `sync::Mux` multiplexes a sync `Client`, `async::connect` an async
+//! one; both speak the same format and can talk to each other.
+//!
+//! # Format
+//!
+//! Every frame is one binary message:
+//!
+//! ```text
+//! +------+-------------------+------------------+
+//! | kind | channel: u32, BE  | rest             |
+//! +------+-------------------+------------------+
+//! ```
+//!
+//! | kind | frame    | rest                                                  |
+//! |------|----------|-------------------------------------------------------|
+//! | 0    | `OPEN`   | the UTF-8 name of the channel, empty for no name      |
+//! | 1    | `DATA`   | one payload                                           |
+//! | 2    | `CLOSE`  | nothing, the sender sends no more data on the channel |
+//! | 3    | `WINDOW` | u32, BE: how many more bytes the sender accepts       |
+//!
+//! The side that made the connection opens channels with odd numbers, the
+//! side that accepted it with even ones, so both can open channels at once.
+//! Each side opens its channels in increasing order, so a number is never
+//! used twice, even once its channel is gone. Channel 0 is not used. A
+//! channel is gone once both sides closed it.
+//!
+//! Each side of a channel may send `INITIAL_WINDOW` bytes of payloads, more
+//! as the other side grants it with `WINDOW` frames. A payload is only sent
+//! once it fits in what is left of the window, and may be no bigger than
+//! `MAX_PAYLOAD`. Text messages are not allowed, control messages are
+//! answered as usual.
+use std::error::Error;
+use std::fmt;
+
+use message::OwnedMessage;
+use result::WebSocketError;
+
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "async")]
+pub mod async;
+
+/// The window each side of a new channel starts with, in bytes.
+pub const INITIAL_WINDOW: u32 = 64 * 1024;
+
+/// The biggest payload a channel sends, in bytes. Half of `INITIAL_WINDOW`,
+/// so a payload waiting for its window gets it once the peer reads on.
+pub const MAX_PAYLOAD: usize = INITIAL_WINDOW as usize / 2;
+
+const OPEN: u8 = 0;
+const DATA: u8 = 1;
+const CLOSE: u8 = 2;
+const WINDOW: u8 = 3;
+
+/// A frame of the multiplexing format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+	/// A channel is opened, with its name.
+	Open(u32, String),
+	/// A payload on a channel.
+	Data(u32, Vec<u8>),
+	/// The sender is done with a channel.
+	Close(u32),
+	/// The sender accepts this many more bytes on a channel.
+	Window(u32, u32),
+}
+
+impl Frame {
+	/// The channel the frame is about.
+	pub fn channel(&self) -> u32 {
+		match *self {
+			Frame::Open(channel, _) |
+			Frame::Data(channel, _) |
+			Frame::Close(channel) |
+			Frame::Window(channel, _) => channel,
+		}
+	}
+
+	/// The frame as a binary message.
+	pub fn to_message(&self) -> OwnedMessage {
+		let (kind, rest): (u8, &[u8]) = match *self {
+			Frame::Open(_, ref name) => (OPEN, name.as_bytes()),
+			Frame::Data(_, ref payload) => (DATA, payload),
+			Frame::Close(_) => (CLOSE, &[]),
+			Frame::Window(_, _) => (WINDOW, &[]),
+		};
+		let mut data = Vec::with_capacity(9 + rest.len());
+		data.push(kind);
+		data.extend(&be_bytes(self.channel()));
+		data.extend(rest);
+		if let Frame::Window(_, bytes) = *self {
+			data.extend(&be_bytes(bytes));
+		}
+		OwnedMessage::Binary(data)
+	}
+
+	/// The frame in a message, `None` for control messages.
+	pub fn from_message(message: &OwnedMessage) -> Result<Option<Frame>, MuxError> {
+		let data = match *message {
+			OwnedMessage::Binary(ref data) => data,
+			OwnedMessage::Text(_) => return Err(MuxError::Protocol("text message")),
+			_ => return Ok(None),
+		};
+		if data.len() < 5 {
+			return Err(MuxError::Protocol("frame too short"));
+		}
+		let (channel, rest) = (from_be_bytes(&data[1..5]), &data[5..]);
+		if channel == 0 {
+			return Err(MuxError::Protocol("channel 0"));
+		}
+		Ok(Some(match data[0] {
+			OPEN => match String::from_utf8(rest.to_vec()) {
+				Ok(name) => Frame::Open(channel, name),
+				Err(_) => return Err(MuxError::Protocol("channel name is not UTF-8")),
+			},
+			DATA => Frame::Data(channel, rest.to_vec()),
+			CLOSE if rest.is_empty() => Frame::Close(channel),
+			WINDOW if rest.len() == 4 => Frame::Window(channel, from_be_bytes(rest)),
+			_ => return Err(MuxError::Protocol("invalid frame")),
+		}))
+	}
+}
+
+fn be_bytes(n: u32) -> [u8; 4] {
+	[(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn from_be_bytes(bytes: &[u8]) -> u32 {
+	bytes.iter().fold(0, |n, &b| n << 8 | u32::from(b))
+}
+
+/// What can go wrong on a multiplexed connection.
+#[derive(Debug)]
+pub enum MuxError {
+	/// The peer broke the format, the connection can't be used any more.
+	Protocol(&'static str),
+	/// The channel is closed for sending, or was never opened.
+	ChannelClosed(u32),
+	/// The payload is bigger than `MAX_PAYLOAD`, with its size.
+	PayloadTooLarge(usize),
+	/// The connection was closed.
+	Closed,
+	/// The connection failed, see the `WebSocketError`.
+	WebSocket(WebSocketError),
+}
+
+impl fmt::Display for MuxError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			MuxError::Protocol(e) => write!(fmt, "multiplexing protocol error: {}", e),
+			MuxError::ChannelClosed(channel) => write!(fmt, "channel {} is closed", channel),
+			MuxError::PayloadTooLarge(len) => write!(fmt, "payload of {} bytes is larger than {}", len, MAX_PAYLOAD),
+			MuxError::Closed => fmt.write_str("connection closed"),
+			MuxError::WebSocket(ref e) => fmt::Display::fmt(e, fmt),
+		}
+	}
+}
+
+impl Error for MuxError {
+	fn description(&self) -> &str {
+		match *self {
+			MuxError::Protocol(_) => "multiplexing protocol error",
+			MuxError::ChannelClosed(_) => "channel closed",
+			MuxError::PayloadTooLarge(_) => "payload too large",
+			MuxError::Closed => "connection closed",
+			MuxError::WebSocket(ref e) => e.description(),
+		}
+	}
+
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			MuxError::WebSocket(ref e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<WebSocketError> for MuxError {
+	fn from(err: WebSocketError) -> MuxError {
+		MuxError::WebSocket(err)
+	}
+}
+
+/// The numbers of the channels one side opens, and the last one the peer
+/// opened.
+#[derive(Debug)]
+struct Numbers {
+	next: u32,
+	peer: u32,
+}
+
+impl Numbers {
+	/// `client` is whether this side made the connection.
+	fn new(client: bool) -> Numbers {
+		Numbers {
+			next: if client { 1 } else { 2 },
+			peer: 0,
+		}
+	}
+
+	fn next(&mut self) -> Result<u32, MuxError> {
+		let channel = self.next;
+		if channel > u32::max_value() - 2 {
+			return Err(MuxError::Protocol("out of channel numbers"));
+		}
+		self.next += 2;
+		Ok(channel)
+	}
+
+	/// Whether the peer may open `channel`, which is one of its numbers it
+	/// didn't use yet.
+	fn peer_opens(&mut self, channel: u32) -> bool {
+		if channel % 2 == self.next % 2 || channel <= self.peer {
+			return false;
+		}
+		self.peer = channel;
+		true
+	}
+}
+
+/// How much one side of a channel may still send, both sides keep count.
+#[derive(Debug)]
+struct Credit(i64);
+
+impl Credit {
+	fn new() -> Credit {
+		Credit(i64::from(INITIAL_WINDOW))
+	}
+
+	fn fits(&self, bytes: usize) -> bool {
+		bytes as i64 <= self.0
+	}
+
+	fn take(&mut self, bytes: usize) {
+		self.0 -= bytes as i64;
+	}
+
+	fn grant(&mut self, bytes: u32) {
+		self.0 += i64::from(bytes);
+	}
+}
+
+/// The payloads that were read, to grant back to the peer in batches.
+#[derive(Debug)]
+struct Consumed {
+	window: u32,
+	bytes: u32,
+}
+
+impl Consumed {
+	fn new(window: u32) -> Consumed {
+		Consumed {
+			window: window,
+			bytes: 0,
+		}
+	}
+
+	/// The grant that makes the window `window` bytes if it is not the
+	/// initial one.
+	fn initial_grant(&self) -> Option<u32> {
+		self.window.checked_sub(INITIAL_WINDOW).and_then(|bytes| if bytes > 0 { Some(bytes) } else { None })
+	}
+
+	/// Count a payload that was read, returns the bytes to grant once half
+	/// the window was read.
+	fn add(&mut self, bytes: usize) -> Option<u32> {
+		self.bytes = self.bytes.saturating_add(bytes as u32);
+		if self.bytes >= self.window / 2 {
+			Some(::std::mem::replace(&mut self.bytes, 0))
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn frames_round_trip() {
+		let frames = vec![
+			Frame::Open(1, "logs".to_string()),
+			Frame::Data(258, vec![1, 2, 3]),
+			Frame::Close(7),
+			Frame::Window(3, 70000),
+		];
+		for frame in frames {
+			assert_eq!(Frame::from_message(&frame.to_message()).unwrap(), Some(frame));
+		}
+		assert_eq!(Frame::Data(258, vec![9]).to_message(), OwnedMessage::Binary(vec![1, 0, 0, 1, 2, 9]));
+		assert!(Frame::from_message(&OwnedMessage::Binary(vec![2, 0, 0, 0, 0])).is_err());
+		assert_eq!(Frame::from_message(&OwnedMessage::Ping(vec![])).unwrap(), None);
+	}
+
+	#[test]
+	fn peers_open_each_number_once() {
+		let mut numbers = Numbers::new(true);
+		assert!(!numbers.peer_opens(1));
+		assert!(numbers.peer_opens(4));
+		assert!(!numbers.peer_opens(4));
+		assert!(!numbers.peer_opens(2));
+		assert!(numbers.peer_opens(6));
+	}
+
+	#[test]
+	fn windows_are_granted_back_in_halves() {
+		let mut credit = Credit::new();
+		credit.take(INITIAL_WINDOW as usize - 10);
+		assert!(credit.fits(10));
+		assert!(!credit.fits(11));
+		credit.take(10);
+		assert!(!credit.fits(1));
+
+		let mut consumed = Consumed::new(INITIAL_WINDOW);
+		assert_eq!(consumed.initial_grant(), None);
+		assert_eq!(consumed.add(20000), None);
+		assert_eq!(consumed.add(20000), Some(40000));
+		credit.grant(40000);
+		assert!(credit.fits(40000));
+		assert_eq!(Consumed::new(2 * INITIAL_WINDOW).initial_grant(), Some(INITIAL_WINDOW));
+	}
+}
@@ -0,0 +1,328 @@
+//! Channels over a sync `Client`.
+//!
+//!```rust,no_run
+//!use websocket::ClientBuilder;
+//!use websocket::codec::ws::Context;
+//!use websocket::mux::sync::{Event, Mux};
+//!
+//!let client = ClientBuilder::new("ws://127.0.0.1:2794").unwrap()
+//!    .connect_insecure().unwrap();
+//!let mut mux = Mux::new(client, Context::Client);
+//!
+//!let logs = mux.open_named("logs").unwrap();
+//!let metrics = mux.open().unwrap();
+//!mux.send(logs, b"tail").unwrap();
+//!mux.send(metrics, b"cpu").unwrap();
+//!
+//!loop {
+//!    match mux.recv().unwrap() {
+//!        Event::Data { channel, payload } if channel == logs => println!("{:?}", payload),
+//!        Event::Closed(channel) => mux.close(channel).unwrap(),
+//!        other => println!("{:?}", other),
+//!    }
+//!}
+//!```
+use std::collections::{HashMap, VecDeque};
+use std::cmp;
+
+use client::sync::Client;
+use codec::ws::Context;
+use message::OwnedMessage;
+use stream::sync::Stream;
+use super::{Consumed, Credit, Frame, MuxError, Numbers, INITIAL_WINDOW, MAX_PAYLOAD};
+
+/// What happened on a channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+	/// The peer opened a channel.
+	Opened {
+		/// The number of the channel.
+		channel: u32,
+		/// Its name, empty if it has none.
+		name: String,
+	},
+	/// A payload arrived on a channel.
+	Data {
+		/// The channel it arrived on.
+		channel: u32,
+		/// What the peer sent.
+		payload: Vec<u8>,
+	},
+	/// The peer sends no more data on a channel. Close it as well once done
+	/// with it.
+	Closed(u32),
+}
+
+struct Channel {
+	send: Credit,
+	receive: Credit,
+	consumed: Consumed,
+	closed: bool,
+	peer_closed: bool,
+}
+
+/// A sync websocket connection that carries channels.
+///
+/// Reading happens in `recv`, and in `send` while a channel has to wait for
+/// its window, so what arrives meanwhile is kept for `recv`.
+pub struct Mux<S: Stream> {
+	client: Client<S>,
+	numbers: Numbers,
+	window: u32,
+	channels: HashMap<u32, Channel>,
+	events: VecDeque<Event>,
+}
+
+impl<S: Stream> Mux<S> {
+	/// Carry channels over `client`, `context` is the side of the connection
+	/// this is.
+	pub fn new(client: Client<S>, context: Context) -> Self {
+		Mux {
+			client: client,
+			numbers: Numbers::new(context == Context::Client),
+			window: INITIAL_WINDOW,
+			channels: HashMap::new(),
+			events: VecDeque::new(),
+		}
+	}
+
+	/// How many bytes each channel may have on its way here, no less than
+	/// `INITIAL_WINDOW`, which is the default.
+	pub fn window(mut self, bytes: u32) -> Self {
+		self.window = cmp::max(bytes, INITIAL_WINDOW);
+		self
+	}
+
+	/// Open a channel without a name, returns its number.
+	pub fn open(&mut self) -> Result<u32, MuxError> {
+		self.open_named("")
+	}
+
+	/// Open a channel with a name, returns its number.
+	pub fn open_named(&mut self, name: &str) -> Result<u32, MuxError> {
+		let channel = self.numbers.next()?;
+		self.write(&Frame::Open(channel, name.to_string()))?;
+		self.add(channel)?;
+		Ok(channel)
+	}
+
+	/// Send a payload on a channel, waiting for the peer to grant a window
+	/// if what is left doesn't fit it. It may be no bigger than
+	/// `MAX_PAYLOAD`.
+	pub fn send(&mut self, channel: u32, payload: &[u8]) -> Result<(), MuxError> {
+		if payload.len() > MAX_PAYLOAD {
+			return Err(MuxError::PayloadTooLarge(payload.len()));
+		}
+		loop {
+			match self.channels.get_mut(&channel) {
+				Some(ref state) if state.closed => return Err(MuxError::ChannelClosed(channel)),
+				Some(state) => {
+					if state.send.fits(payload.len()) {
+						state.send.take(payload.len());
+						break;
+					}
+				}
+				None => return Err(MuxError::ChannelClosed(channel)),
+			}
+			self.read()?;
+		}
+		self.write(&Frame::Data(channel, payload.to_vec()))
+	}
+
+	/// Close a channel for sending, payloads may still arrive until the peer
+	/// closes it too.
+	pub fn close(&mut self, channel: u32) -> Result<(), MuxError> {
+		let gone = match self.channels.get_mut(&channel) {
+			Some(ref state) if state.closed => return Err(MuxError::ChannelClosed(channel)),
+			Some(state) => {
+				state.closed = true;
+				state.peer_closed
+			}
+			None => return Err(MuxError::ChannelClosed(channel)),
+		};
+		if gone {
+			self.channels.remove(&channel);
+		}
+		self.write(&Frame::Close(channel))
+	}
+
+	/// Wait for what happens next on any channel.
+	pub fn recv(&mut self) -> Result<Event, MuxError> {
+		loop {
+			if let Some(event) = self.events.pop_front() {
+				if let Event::Data { channel, ref payload } = event {
+					self.consume(channel, payload.len())?;
+				}
+				return Ok(event);
+			}
+			self.read()?;
+		}
+	}
+
+	/// The websocket connection.
+	pub fn into_inner(self) -> Client<S> {
+		self.client
+	}
+
+	fn add(&mut self, channel: u32) -> Result<(), MuxError> {
+		let state = Channel {
+			send: Credit::new(),
+			receive: Credit::new(),
+			consumed: Consumed::new(self.window),
+			closed: false,
+			peer_closed: false,
+		};
+		let grant = state.consumed.initial_grant();
+		self.channels.insert(channel, state);
+		self.grant(channel, grant)
+	}
+
+	fn consume(&mut self, channel: u32, bytes: usize) -> Result<(), MuxError> {
+		let grant = match self.channels.get_mut(&channel) {
+			Some(state) => state.consumed.add(bytes),
+			None => return Ok(()),
+		};
+		self.grant(channel, grant)
+	}
+
+	fn grant(&mut self, channel: u32, bytes: Option<u32>) -> Result<(), MuxError> {
+		if let Some(bytes) = bytes {
+			if let Some(state) = self.channels.get_mut(&channel) {
+				state.receive.grant(bytes);
+			}
+			self.write(&Frame::Window(channel, bytes))?;
+		}
+		Ok(())
+	}
+
+	fn write(&mut self, frame: &Frame) -> Result<(), MuxError> {
+		self.client.send_message(&frame.to_message())?;
+		Ok(())
+	}
+
+	/// Read one message and keep what it means for `recv`.
+	fn read(&mut self) -> Result<(), MuxError> {
+		let message = self.client.recv_message()?;
+		let frame = match message {
+			OwnedMessage::Ping(data) => {
+				self.client.send_message(&OwnedMessage::Pong(data))?;
+				return Ok(());
+			}
+			OwnedMessage::Close(_) => {
+				let _ = self.client.send_message(&OwnedMessage::Close(None));
+				return Err(MuxError::Closed);
+			}
+			message => match Frame::from_message(&message)? {
+				Some(frame) => frame,
+				None => return Ok(()),
+			},
+		};
+
+		match frame {
+			Frame::Open(channel, name) => {
+				if !self.numbers.peer_opens(channel) {
+					return Err(MuxError::Protocol("channel can't be opened"));
+				}
+				self.add(channel)?;
+				self.events.push_back(Event::Opened {
+					channel: channel,
+					name: name,
+				});
+			}
+			Frame::Data(channel, payload) => {
+				match self.channels.get_mut(&channel) {
+					Some(ref state) if state.peer_closed => return Err(MuxError::Protocol("data on a closed channel")),
+					Some(state) => {
+						if !state.receive.fits(payload.len()) {
+							return Err(MuxError::Protocol("window exceeded"));
+						}
+						state.receive.take(payload.len());
+					}
+					None => return Err(MuxError::Protocol("data on a closed channel")),
+				}
+				self.events.push_back(Event::Data {
+					channel: channel,
+					payload: payload,
+				});
+			}
+			Frame::Close(channel) => {
+				let gone = match self.channels.get_mut(&channel) {
+					Some(ref state) if state.peer_closed => return Err(MuxError::Protocol("close of a closed channel")),
+					Some(state) => {
+						state.peer_closed = true;
+						state.closed
+					}
+					None => return Err(MuxError::Protocol("close of a closed channel")),
+				};
+				if gone {
+					self.channels.remove(&channel);
+				}
+				self.events.push_back(Event::Closed(channel));
+			}
+			Frame::Window(channel, bytes) => {
+				// the channel may be gone already
+				if let Some(state) = self.channels.get_mut(&channel) {
+					state.send.grant(bytes);
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use client::builder::ClientBuilder;
+	use server::sync::Server;
+
+	#[test]
+	fn channels_wait_for_their_window() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+
+		let echo = thread::spawn(move || {
+			let client = server.accept().ok().unwrap().accept().unwrap();
+			let mut mux = Mux::new(client, Context::Server);
+			let mut received = Vec::new();
+			loop {
+				match mux.recv().unwrap() {
+					Event::Opened { channel, name } => received.push((channel, name.into_bytes())),
+					Event::Data { channel, payload } => {
+						received.push((channel, payload.len().to_string().into_bytes()));
+						mux.send(channel, &payload[..1]).unwrap();
+					}
+					Event::Closed(channel) => {
+						mux.close(channel).unwrap();
+						return received;
+					}
+				}
+			}
+		});
+
+		let client = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+		let mut mux = Mux::new(client, Context::Client);
+		let channel = mux.open_named("bulk").unwrap();
+		assert_eq!(channel, 1);
+		assert!(mux.send(channel, &vec![7; MAX_PAYLOAD + 1]).is_err());
+		let payload = vec![7; 30000];
+		// more than the window, the last one waits for the echo to read some
+		for _ in 0..3 {
+			mux.send(channel, &payload).unwrap();
+		}
+		mux.close(channel).unwrap();
+		for _ in 0..3 {
+			assert_eq!(mux.recv().unwrap(), Event::Data {
+				channel: 1,
+				payload: vec![7],
+			});
+		}
+		assert_eq!(mux.recv().unwrap(), Event::Closed(1));
+		assert!(mux.send(channel, b"late").is_err());
+
+		let received = echo.join().unwrap();
+		assert_eq!(received[0], (1, b"bulk".to_vec()));
+		assert_eq!(received[1..], [(1, b"30000".to_vec()), (1, b"30000".to_vec()), (1, b"30000".to_vec())]);
+	}
+}
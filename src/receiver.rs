@@ -1,11 +1,14 @@
 //! The default implementation of a WebSocket Receiver.
 
-use std::io::{BufReader, Read};
+use std::io::{self, BufReader, Read};
 use std::io::Result as IoResult;
+use std::time::Duration;
 
 use dataframe::{DataFrame, Opcode};
+use intercept::{Chain, Interceptor};
 use result::{WebSocketResult, WebSocketError};
 use ws;
+use ws::dataframe::DataFrame as DataFrameable;
 use ws::receiver::Receiver as ReceiverTrait;
 use ws::receiver::{MessageIterator, DataFrameIterator};
 use message::OwnedMessage;
@@ -46,11 +49,27 @@ where
 		self.receiver.recv_message(&mut self.stream)
 	}
 
+	/// Reads a message if a complete one is available without blocking.
+	///
+	/// See `Receiver::try_recv_message`.
+	pub fn try_recv_message(&mut self) -> WebSocketResult<Option<OwnedMessage>> {
+		self.receiver.try_recv_message(&mut self.stream)
+	}
+
 	/// An iterator over incoming messsages.
 	/// This iterator will block until new messages arrive and will never halt.
 	pub fn incoming_messages<'a>(&'a mut self) -> MessageIterator<'a, Receiver, BufReader<R>> {
 		self.receiver.incoming_messages(&mut self.stream)
 	}
+
+	/// Run the messages and data frames this reader receives through
+	/// `interceptor`, before the ones added before it.
+	pub fn intercept<I>(&mut self, interceptor: I)
+	where
+		I: Interceptor + 'static,
+	{
+		self.receiver.interceptors.push(interceptor);
+	}
 }
 
 impl<S> Reader<S>
@@ -68,13 +87,21 @@ where
 	pub fn shutdown_all(&self) -> IoResult<()> {
 		self.stream.get_ref().as_tcp().shutdown(Shutdown::Both)
 	}
+
+	/// See [`TcpStream::set_read_timeout`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.set_read_timeout).
+	pub fn set_read_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().as_tcp().set_read_timeout(timeout)
+	}
 }
 
 /// A Receiver that wraps a Reader and provides a default implementation using
 /// DataFrames and Messages.
 pub struct Receiver {
 	buffer: Vec<DataFrame>,
+	partial: Vec<u8>,
 	mask: bool,
+	interceptors: Chain,
 }
 
 impl Receiver {
@@ -82,11 +109,63 @@ impl Receiver {
 	pub fn new(mask: bool) -> Receiver {
 		Receiver {
 			buffer: Vec::new(),
+			partial: Vec::new(),
 			mask: mask,
+			interceptors: Chain::new(),
+		}
+	}
+
+	/// Hands the receiver bytes that were already read off the stream, like
+	/// frames a peer sent right behind its handshake, to parse before reading
+	/// any more.
+	pub(crate) fn push_buffered(&mut self, bytes: &[u8]) {
+		self.partial.extend_from_slice(bytes);
+	}
+
+	/// Takes back the bytes that were read off the stream but not handed out
+	/// yet. The frames of an unfinished message are written out again ahead of
+	/// the bytes that were not parsed.
+	pub(crate) fn take_buffered(&mut self) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		for frame in self.buffer.drain(..) {
+			// writing to a Vec can't fail
+			let _ = frame.write_to(&mut bytes, self.mask);
+		}
+		bytes.append(&mut self.partial);
+		bytes
+	}
+
+	/// The interceptors that messages and data frames pass once they are
+	/// received.
+	pub fn interceptors(&mut self) -> &mut Chain {
+		&mut self.interceptors
+	}
+
+	/// Reads a message if a complete one is available, or returns `Ok(None)`
+	/// if the reader would block or timed out first.
+	///
+	/// This is meant for non-blocking streams and streams with a read timeout.
+	/// Whatever was read of an unfinished message is kept in the receiver and
+	/// the next call continues with it.
+	pub fn try_recv_message<R>(&mut self, reader: &mut R) -> WebSocketResult<Option<OwnedMessage>>
+	where
+		R: Read,
+	{
+		match self.recv_message(reader) {
+			Ok(message) => Ok(Some(message)),
+			Err(WebSocketError::IoError(ref e)) if would_block(e) => Ok(None),
+			Err(e) => Err(e),
 		}
 	}
 }
 
+fn would_block(error: &io::Error) -> bool {
+	match error.kind() {
+		io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => true,
+		_ => false,
+	}
+}
+
 
 impl ws::Receiver for Receiver {
 	type F = DataFrame;
@@ -94,11 +173,25 @@ impl ws::Receiver for Receiver {
 	type M = OwnedMessage;
 
 	/// Reads a single data frame from the remote endpoint.
+	///
+	/// If the reader would block partway through the frame, what was read so
+	/// far is kept for the next call and a `WouldBlock` error is returned.
 	fn recv_dataframe<R>(&mut self, reader: &mut R) -> WebSocketResult<DataFrame>
 	where
 		R: Read,
 	{
-		DataFrame::read_dataframe(reader, self.mask)
+		loop {
+			let frame = match DataFrame::read_dataframe_partial(reader, self.mask, &mut self.partial)? {
+				Some(frame) => frame,
+				None => return Err(io::Error::new(io::ErrorKind::WouldBlock, "incomplete data frame").into()),
+			};
+			if self.interceptors.is_empty() {
+				return Ok(frame);
+			}
+			if let Some(frame) = self.interceptors.receive_dataframe(frame)? {
+				return Ok(frame);
+			}
+		}
 	}
 
 	/// Returns the data frames that constitute one message.
@@ -144,4 +237,21 @@ impl ws::Receiver for Receiver {
 
 		Ok(::std::mem::replace(&mut self.buffer, Vec::new()))
 	}
+
+	/// Reads a single message, skipping those an interceptor drops.
+	fn recv_message<'m, R>(&mut self, reader: &mut R) -> WebSocketResult<OwnedMessage>
+	where
+		R: Read,
+	{
+		loop {
+			let dataframes = self.recv_message_dataframes(reader)?;
+			let message = <OwnedMessage as ws::Message>::from_dataframes(dataframes)?;
+			if self.interceptors.is_empty() {
+				return Ok(message);
+			}
+			if let Some(message) = self.interceptors.receive_message(message)? {
+				return Ok(message);
+			}
+		}
+	}
 }
@@ -0,0 +1,260 @@
+//! Reliable delivery on top of a `ReconnectingClient`.
+//!
+//!```rust,no_run
+//!# extern crate websocket;
+//!# extern crate tokio;
+//!# fn main() {
+//!use websocket::{ClientBuilder, OwnedMessage};
+//!use websocket::client::reconnect::{Event, ReconnectingClient};
+//!use websocket::futures::{Future, Sink, Stream};
+//!use websocket::reliable::async::Reliable;
+//!
+//!let builder = ClientBuilder::new("ws://127.0.0.1:2794").unwrap();
+//!let client = Reliable::new(ReconnectingClient::insecure(builder), 1000);
+//!
+//!let f = client.send(OwnedMessage::Text("BUY 100 ACME".to_string()))
+//!    .and_then(|client| client.for_each(|event| {
+//!        if let Event::Message(confirmation) = event {
+//!            println!("{:?}", confirmation);
+//!        }
+//!        Ok(())
+//!    }));
+//!
+//!tokio::run(f.map_err(|e| println!("{}", e)));
+//!# }
+//!```
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::task::{self, Task};
+
+use client::reconnect::{Event, QueuePolicy, ReconnectingClient};
+use message::OwnedMessage;
+use result::WebSocketError;
+use stream::async::Stream as AsyncStream;
+use super::{Session, SessionError};
+
+/// A `ReconnectingClient` whose text and binary messages are delivered
+/// exactly once, in order, across reconnects.
+///
+/// It is a `Stream` of the client's `Event`s, with only the messages that
+/// were not delivered before, and a `Sink` of messages. The sink is not
+/// ready while too many messages wait to be acknowledged, acknowledgements
+/// only arrive while the stream is polled.
+pub struct Reliable<S> {
+	client: ReconnectingClient<S>,
+	session: Arc<Mutex<Session>>,
+	outbox: VecDeque<OwnedMessage>,
+	task: Option<Task>,
+}
+
+impl<S> Reliable<S>
+where
+	S: AsyncStream + Send,
+{
+	/// Deliver over `client`, keeping up to `capacity` messages until they are
+	/// acknowledged.
+	///
+	/// This takes over the `on_reconnect` hook of the client, to say hello,
+	/// and its queue policy: messages sent while disconnected are sent again
+	/// after the hello anyway.
+	pub fn new(client: ReconnectingClient<S>, capacity: usize) -> Self {
+		let session = Arc::new(Mutex::new(Session::new(capacity)));
+		let hello = session.clone();
+		let client = client.queue(QueuePolicy::Drop).on_reconnect(move |_| {
+			vec![hello.lock().unwrap_or_else(|e| e.into_inner()).hello()]
+		});
+		Reliable {
+			client: client,
+			session: session,
+			outbox: VecDeque::new(),
+			task: None,
+		}
+	}
+
+	/// How many messages wait to be acknowledged.
+	pub fn unacked(&self) -> usize {
+		self.session().unacked()
+	}
+
+	fn session(&self) -> ::std::sync::MutexGuard<Session> {
+		self.session.lock().unwrap_or_else(|e| e.into_inner())
+	}
+
+	/// Hand the outbox to the client, `Ready` once all of it is.
+	fn write(&mut self) -> Poll<(), WebSocketError> {
+		while let Some(message) = self.outbox.pop_front() {
+			if let AsyncSink::NotReady(message) = self.client.start_send(message)? {
+				self.outbox.push_front(message);
+				return Ok(Async::NotReady);
+			}
+		}
+		self.client.poll_complete()
+	}
+}
+
+impl<S> Stream for Reliable<S>
+where
+	S: AsyncStream + Send,
+{
+	type Item = Event;
+	type Error = SessionError;
+
+	fn poll(&mut self) -> Poll<Option<Event>, SessionError> {
+		loop {
+			self.write()?;
+			let message = match self.client.poll()? {
+				Async::Ready(Some(Event::Message(message))) => message,
+				Async::Ready(event) => return Ok(Async::Ready(event)),
+				Async::NotReady => return Ok(Async::NotReady),
+			};
+
+			let received = self.session().receive(message)?;
+			self.outbox.extend(received.replies);
+			if !self.session().is_full() {
+				if let Some(task) = self.task.take() {
+					task.notify();
+				}
+			}
+			match received.message {
+				Some(OwnedMessage::Ping(data)) => self.outbox.push_back(OwnedMessage::Pong(data)),
+				Some(message) => return Ok(Async::Ready(Some(Event::Message(message)))),
+				None => (),
+			}
+		}
+	}
+}
+
+impl<S> Sink for Reliable<S>
+where
+	S: AsyncStream + Send,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = SessionError;
+
+	fn start_send(&mut self, message: OwnedMessage) -> StartSend<OwnedMessage, SessionError> {
+		let sent = self.session().send(message);
+		match sent {
+			Ok(message) => {
+				self.outbox.push_back(message);
+				self.write()?;
+				Ok(AsyncSink::Ready)
+			}
+			Err(SessionError::Full(message)) => {
+				self.task = Some(task::current());
+				Ok(AsyncSink::NotReady(message))
+			}
+			Err(e) => Err(e),
+		}
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), SessionError> {
+		Ok(self.write()?)
+	}
+
+	/// Close the connection and stop reconnecting, messages that were not
+	/// acknowledged yet are not sent again.
+	fn close(&mut self) -> Poll<(), SessionError> {
+		if self.write()?.is_not_ready() {
+			return Ok(Async::NotReady);
+		}
+		Ok(self.client.close()?)
+	}
+}
+
+impl<S> fmt::Debug for Reliable<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Reliable")
+		 .field("client", &self.client)
+		 .field("session", &self.session)
+		 .finish()
+	}
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+	use super::*;
+	use std::sync::mpsc::channel;
+	use std::thread;
+	use std::time::Duration;
+	use futures::{future, Future};
+	use tokio;
+	use client::builder::ClientBuilder;
+	use client::reconnect::Backoff;
+	use server::sync::Server;
+
+	fn text(text: &str) -> OwnedMessage {
+		OwnedMessage::Text(text.to_string())
+	}
+
+	#[test]
+	fn messages_survive_a_lost_connection() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+
+		let (delivered_tx, delivered) = channel();
+		thread::spawn(move || {
+			let mut session = Session::new(10);
+			let mut seen = Vec::new();
+
+			// read the first message and hang up before acknowledging it
+			let mut client = server.accept().ok().unwrap().accept().unwrap();
+			client.send_message(&session.hello()).unwrap();
+			while seen.is_empty() {
+				let received = session.receive(client.recv_message().unwrap()).unwrap();
+				seen.extend(received.message);
+			}
+			drop(client);
+
+			let mut client = server.accept().ok().unwrap().accept().unwrap();
+			client.send_message(&session.hello()).unwrap();
+			let mut done = false;
+			loop {
+				let received = session.receive(client.recv_message().unwrap()).unwrap();
+				for reply in received.replies {
+					client.send_message(&reply).unwrap();
+				}
+				seen.extend(received.message);
+				if seen.len() == 2 && !done {
+					client.send_message(&session.send(text("done")).unwrap()).unwrap();
+					done = true;
+				} else if done && session.unacked() == 0 {
+					delivered_tx.send(seen).unwrap();
+					return;
+				}
+			}
+		});
+
+		let client = ReconnectingClient::insecure(ClientBuilder::new(&url).unwrap()).backoff(Backoff {
+			initial: Duration::from_millis(10),
+			max: Duration::from_millis(10),
+			jitter: false,
+		});
+		let (reply_tx, reply) = channel();
+		thread::spawn(move || {
+			let f = Reliable::new(client, 10)
+				.send(text("one"))
+				.and_then(|client| client.send(text("two")))
+				.and_then(|client| {
+					client.filter_map(|event| match event {
+						Event::Message(message) => Some(message),
+						_ => None,
+					})
+					.into_future()
+					.map_err(|(e, _)| e)
+				})
+				.and_then(move |(message, mut client)| {
+					reply_tx.send(message).unwrap();
+					// closing sends the acknowledgement first
+					future::poll_fn(move || client.get_mut().close())
+				});
+			tokio::run(f.map_err(|e| panic!("{}", e)));
+		});
+
+		let timeout = Duration::from_secs(10);
+		assert_eq!(reply.recv_timeout(timeout).unwrap(), Some(text("done")));
+		assert_eq!(delivered.recv_timeout(timeout).unwrap(), vec![text("one"), text("two")]);
+	}
+}
@@ -0,0 +1,384 @@
+//! Delivery that survives reconnecting.
+//!
+//! A `Session` numbers the text and binary messages it sends and keeps them
+//! until the peer acknowledges them. Whenever a connection is made both
+//! sides send a hello telling the other how far they got, and each side sends
+//! again what the other has not acknowledged yet, in order. Messages that
+//! arrive twice are only delivered once.
+//!
+//! `Session` only turns messages into what goes over the wire and back, so
+//! it can be used with any connection. `sync::ReliableClient` connects and
+//! reconnects a sync `Client` with it, `async::Reliable` does the same on top
+//! of a `ReconnectingClient`. A server keeps the `Session` of each client
+//! across connections, `hello_id` tells which one a new connection is for.
+//!
+//! # Format
+//!
+//! Everything is sent in binary messages, numbers are big endian:
+//!
+//! | first byte | message | then                                                 |
+//! |------------|---------|------------------------------------------------------|
+//! | 0          | hello   | u64 session id, u64 sequence number of the last message received |
+//! | 1          | data    | u64 sequence number, 0 for text or 1 for binary, the payload |
+//! | 2          | ack     | u64 sequence number, every message up to it was received |
+//!
+//! Sequence numbers start at 1. A data message that is not the next one
+//! expected is dropped, it is sent again after the next hello. If the peer
+//! starts a new session, the messages that were not acknowledged yet are
+//! numbered again to follow what it received.
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+use rand;
+
+use message::OwnedMessage;
+use result::WebSocketError;
+
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "async")]
+pub mod async;
+
+const HELLO: u8 = 0;
+const DATA: u8 = 1;
+const ACK: u8 = 2;
+
+const TEXT: u8 = 0;
+const BINARY: u8 = 1;
+
+/// What can go wrong with reliable delivery.
+#[derive(Debug)]
+pub enum SessionError {
+	/// The peer sent something that is not part of the format.
+	Protocol(&'static str),
+	/// As many messages as the session keeps are waiting to be acknowledged,
+	/// here is the one that could not be sent.
+	Full(OwnedMessage),
+	/// The connection was lost. Messages are kept and sent again once there
+	/// is a new one.
+	Disconnected,
+	/// The connection failed, see the `WebSocketError`. Messages are kept
+	/// and sent again once there is a new one.
+	WebSocket(WebSocketError),
+}
+
+impl fmt::Display for SessionError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SessionError::Protocol(e) => write!(fmt, "reliable delivery protocol error: {}", e),
+			SessionError::WebSocket(ref e) => fmt::Display::fmt(e, fmt),
+			_ => fmt.write_str(self.description()),
+		}
+	}
+}
+
+impl Error for SessionError {
+	fn description(&self) -> &str {
+		match *self {
+			SessionError::Protocol(_) => "reliable delivery protocol error",
+			SessionError::Full(_) => "too many messages wait to be acknowledged",
+			SessionError::Disconnected => "connection lost",
+			SessionError::WebSocket(ref e) => e.description(),
+		}
+	}
+
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			SessionError::WebSocket(ref e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<WebSocketError> for SessionError {
+	fn from(err: WebSocketError) -> SessionError {
+		SessionError::WebSocket(err)
+	}
+}
+
+/// What a received message means.
+#[derive(Debug, Default)]
+pub struct Received {
+	/// The message to hand on, if there is one. Control messages are handed
+	/// on as they are.
+	pub message: Option<OwnedMessage>,
+	/// What to send the peer in return, acknowledgements and messages sent
+	/// again.
+	pub replies: Vec<OwnedMessage>,
+}
+
+/// One side of a reliable conversation.
+#[derive(Debug)]
+pub struct Session {
+	id: u64,
+	capacity: usize,
+	next_seq: u64,
+	// the highest sequence number the peer acknowledged
+	acked: u64,
+	unacked: VecDeque<(u64, OwnedMessage)>,
+	peer: Option<u64>,
+	// the sequence number of the last message delivered
+	received: u64,
+}
+
+impl Session {
+	/// A new session, with a random id, that keeps up to `capacity` messages
+	/// that were not acknowledged yet.
+	pub fn new(capacity: usize) -> Session {
+		Session {
+			id: rand::random(),
+			capacity: capacity,
+			next_seq: 1,
+			acked: 0,
+			unacked: VecDeque::new(),
+			peer: None,
+			received: 0,
+		}
+	}
+
+	/// The id of this session.
+	pub fn id(&self) -> u64 {
+		self.id
+	}
+
+	/// The id of the peer's session, once it said hello.
+	pub fn peer_id(&self) -> Option<u64> {
+		self.peer
+	}
+
+	/// How many messages wait to be acknowledged.
+	pub fn unacked(&self) -> usize {
+		self.unacked.len()
+	}
+
+	/// Whether `send` would fail with `SessionError::Full`.
+	pub fn is_full(&self) -> bool {
+		self.unacked.len() >= self.capacity
+	}
+
+	/// The hello to send first thing on every connection.
+	pub fn hello(&self) -> OwnedMessage {
+		let mut data = Vec::with_capacity(17);
+		data.push(HELLO);
+		data.extend(&be_bytes(self.id));
+		data.extend(&be_bytes(self.received));
+		OwnedMessage::Binary(data)
+	}
+
+	/// Number a text or binary message and keep it until it is acknowledged,
+	/// returns what to send. Control messages are returned as they are.
+	pub fn send(&mut self, message: OwnedMessage) -> Result<OwnedMessage, SessionError> {
+		match message {
+			OwnedMessage::Text(_) | OwnedMessage::Binary(_) => (),
+			message => return Ok(message),
+		}
+		if self.is_full() {
+			return Err(SessionError::Full(message));
+		}
+		let seq = self.next_seq;
+		self.next_seq += 1;
+		let data = encode_data(seq, &message);
+		self.unacked.push_back((seq, message));
+		Ok(data)
+	}
+
+	/// Make sense of a message from the peer.
+	pub fn receive(&mut self, message: OwnedMessage) -> Result<Received, SessionError> {
+		let data = match message {
+			OwnedMessage::Binary(data) => data,
+			OwnedMessage::Text(_) => return Err(SessionError::Protocol("text message")),
+			message => {
+				return Ok(Received {
+					message: Some(message),
+					replies: Vec::new(),
+				})
+			}
+		};
+		match data.first() {
+			Some(&HELLO) if data.len() == 17 => Ok(self.receive_hello(from_be_bytes(&data[1..9]), from_be_bytes(&data[9..]))),
+			Some(&DATA) if data.len() >= 10 => self.receive_data(from_be_bytes(&data[1..9]), data[9], &data[10..]),
+			Some(&ACK) if data.len() == 9 => {
+				self.ack(from_be_bytes(&data[1..]));
+				Ok(Received::default())
+			}
+			_ => Err(SessionError::Protocol("invalid message")),
+		}
+	}
+
+	fn receive_hello(&mut self, peer: u64, received: u64) -> Received {
+		if self.peer != Some(peer) {
+			// a new session, whatever came before was for another one
+			self.peer = Some(peer);
+			self.received = 0;
+		}
+		if received < self.acked {
+			// the peer lost what it acknowledged, go on from where it is
+			for (i, &mut (ref mut seq, _)) in self.unacked.iter_mut().enumerate() {
+				*seq = received + 1 + i as u64;
+			}
+			self.next_seq = received + 1 + self.unacked.len() as u64;
+			self.acked = received;
+		}
+		self.ack(received);
+		Received {
+			message: None,
+			replies: self.unacked.iter().map(|&(seq, ref message)| encode_data(seq, message)).collect(),
+		}
+	}
+
+	fn receive_data(&mut self, seq: u64, kind: u8, payload: &[u8]) -> Result<Received, SessionError> {
+		let message = match kind {
+			TEXT => match String::from_utf8(payload.to_vec()) {
+				Ok(text) => OwnedMessage::Text(text),
+				Err(_) => return Err(SessionError::Protocol("text is not UTF-8")),
+			},
+			BINARY => OwnedMessage::Binary(payload.to_vec()),
+			_ => return Err(SessionError::Protocol("invalid message kind")),
+		};
+		if seq > self.received + 1 {
+			// a message before it was lost, it comes again in order
+			return Ok(Received::default());
+		}
+		let message = if seq == self.received + 1 {
+			self.received = seq;
+			Some(message)
+		} else {
+			// seen it already, the acknowledgement must have been lost
+			None
+		};
+		Ok(Received {
+			message: message,
+			replies: vec![encode_ack(self.received)],
+		})
+	}
+
+	fn ack(&mut self, seq: u64) {
+		while self.unacked.front().map_or(false, |&(first, _)| first <= seq) {
+			self.unacked.pop_front();
+		}
+		if seq > self.acked {
+			self.acked = seq;
+		}
+	}
+}
+
+/// The session id of a hello, to find the session a new connection
+/// continues.
+pub fn hello_id(message: &OwnedMessage) -> Option<u64> {
+	match *message {
+		OwnedMessage::Binary(ref data) if data.len() == 17 && data[0] == HELLO => Some(from_be_bytes(&data[1..9])),
+		_ => None,
+	}
+}
+
+fn encode_data(seq: u64, message: &OwnedMessage) -> OwnedMessage {
+	let (kind, payload) = match *message {
+		OwnedMessage::Text(ref text) => (TEXT, text.as_bytes()),
+		OwnedMessage::Binary(ref data) => (BINARY, &data[..]),
+		_ => unreachable!("only text and binary messages are numbered"),
+	};
+	let mut data = Vec::with_capacity(10 + payload.len());
+	data.push(DATA);
+	data.extend(&be_bytes(seq));
+	data.push(kind);
+	data.extend(payload);
+	OwnedMessage::Binary(data)
+}
+
+fn encode_ack(seq: u64) -> OwnedMessage {
+	let mut data = Vec::with_capacity(9);
+	data.push(ACK);
+	data.extend(&be_bytes(seq));
+	OwnedMessage::Binary(data)
+}
+
+fn be_bytes(n: u64) -> [u8; 8] {
+	let mut bytes = [0; 8];
+	for (i, byte) in bytes.iter_mut().enumerate() {
+		*byte = (n >> (56 - 8 * i)) as u8;
+	}
+	bytes
+}
+
+fn from_be_bytes(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0, |n, &b| n << 8 | u64::from(b))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn text(text: &str) -> OwnedMessage {
+		OwnedMessage::Text(text.to_string())
+	}
+
+	/// Hand everything in `messages` to `to`, returns what it delivers and
+	/// what it replies.
+	fn deliver(to: &mut Session, messages: Vec<OwnedMessage>) -> (Vec<OwnedMessage>, Vec<OwnedMessage>) {
+		let (mut delivered, mut replies) = (Vec::new(), Vec::new());
+		for message in messages {
+			let received = to.receive(message).unwrap();
+			delivered.extend(received.message);
+			replies.extend(received.replies);
+		}
+		(delivered, replies)
+	}
+
+	#[test]
+	fn replays_what_was_lost_once() {
+		let (mut client, mut server) = (Session::new(10), Session::new(10));
+		deliver(&mut server, vec![client.hello()]);
+		deliver(&mut client, vec![server.hello()]);
+
+		let one = client.send(text("one")).unwrap();
+		client.send(text("two")).unwrap();
+		let three = client.send(text("three")).unwrap();
+		// "two" and every acknowledgement are lost with the connection
+		let (delivered, _) = deliver(&mut server, vec![one, three]);
+		assert_eq!(delivered, vec![text("one")]);
+		assert_eq!(client.unacked(), 3);
+
+		// reconnect, the hello acknowledges "one" so only the rest is replayed
+		let (_, replay) = deliver(&mut client, vec![server.hello()]);
+		assert_eq!(replay.len(), 2);
+		let (delivered, acks) = deliver(&mut server, replay);
+		assert_eq!(delivered, vec![text("two"), text("three")]);
+		deliver(&mut client, acks);
+		assert_eq!(client.unacked(), 0);
+	}
+
+	#[test]
+	fn keeps_a_bounded_number_of_messages() {
+		let (mut client, mut server) = (Session::new(1), Session::new(1));
+		let ping = client.send(OwnedMessage::Ping(vec![1])).unwrap();
+		assert_eq!(ping, OwnedMessage::Ping(vec![1]));
+		let one = client.send(text("one")).unwrap();
+		match client.send(text("two")) {
+			Err(SessionError::Full(message)) => assert_eq!(message, text("two")),
+			other => panic!("unexpected {:?}", other),
+		}
+		assert_eq!(hello_id(&server.hello()), Some(server.id()));
+
+		let (_, acks) = deliver(&mut server, vec![one]);
+		deliver(&mut client, acks);
+		assert!(!client.is_full());
+	}
+
+	#[test]
+	fn numbers_again_for_a_new_peer_session() {
+		let mut client = Session::new(10);
+		let mut server = Session::new(10);
+		deliver(&mut client, vec![server.hello()]);
+		let one = client.send(text("one")).unwrap();
+		let (_, acks) = deliver(&mut server, vec![one]);
+		deliver(&mut client, acks);
+		client.send(text("two")).unwrap();
+
+		// the server restarted and lost everything
+		let mut server = Session::new(10);
+		let (_, replay) = deliver(&mut client, vec![server.hello()]);
+		let (delivered, _) = deliver(&mut server, replay);
+		assert_eq!(delivered, vec![text("two")]);
+	}
+}
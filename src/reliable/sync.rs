@@ -0,0 +1,264 @@
+//! Reliable delivery over sync clients that connect again when needed.
+//!
+//!```rust,no_run
+//!use websocket::{ClientBuilder, OwnedMessage};
+//!use websocket::reliable::sync::ReliableClient;
+//!
+//!let builder = ClientBuilder::new("ws://127.0.0.1:2794").unwrap();
+//!let mut client = ReliableClient::insecure(builder, 1000);
+//!
+//!client.send(OwnedMessage::Text("BUY 100 ACME".to_string())).unwrap();
+//!loop {
+//!    match client.recv() {
+//!        Ok(confirmation) => println!("{:?}", confirmation),
+//!        // the next call connects again and the order is sent again
+//!        // if it was not acknowledged
+//!        Err(e) => println!("{}", e),
+//!    }
+//!}
+//!```
+use std::collections::VecDeque;
+
+use client::builder::ClientBuilder;
+use client::sync::Client;
+use message::OwnedMessage;
+use result::WebSocketResult;
+#[cfg(feature = "sync-ssl")]
+use stream::sync::NetworkStream;
+use stream::sync::{Stream, TcpStream};
+use super::{hello_id, Session, SessionError};
+
+/// A sync client whose text and binary messages are delivered exactly once,
+/// in order, even when the connection has to be made again.
+///
+/// There is no connection until the first `send` or `recv`, and after one
+/// is lost the next `send` or `recv` connects again.
+pub struct ReliableClient<S: Stream> {
+	connect: Box<FnMut() -> WebSocketResult<Client<S>> + Send>,
+	client: Option<Client<S>>,
+	session: Session,
+	inbox: VecDeque<OwnedMessage>,
+}
+
+#[cfg(feature = "sync-ssl")]
+impl ReliableClient<Box<NetworkStream + Send>> {
+	/// Connect with `builder`, over TLS if it is a `wss://` url, keeping up
+	/// to `capacity` messages until they are acknowledged.
+	///
+	/// See `ClientBuilder::connect`.
+	pub fn new(mut builder: ClientBuilder<'static>, capacity: usize) -> Self {
+		ReliableClient::with_connector(move || builder.connect(None), capacity)
+	}
+}
+
+impl ReliableClient<TcpStream> {
+	/// Connect with `builder`, without TLS, keeping up to `capacity` messages
+	/// until they are acknowledged.
+	///
+	/// See `ClientBuilder::connect_insecure`.
+	pub fn insecure(mut builder: ClientBuilder<'static>, capacity: usize) -> Self {
+		ReliableClient::with_connector(move || builder.connect_insecure(), capacity)
+	}
+}
+
+impl<S: Stream> ReliableClient<S> {
+	/// Connect with whatever `connect` returns, keeping up to `capacity`
+	/// messages until they are acknowledged.
+	pub fn with_connector<F>(connect: F, capacity: usize) -> Self
+	where
+		F: FnMut() -> WebSocketResult<Client<S>> + Send + 'static,
+	{
+		ReliableClient {
+			connect: Box::new(connect),
+			client: None,
+			session: Session::new(capacity),
+			inbox: VecDeque::new(),
+		}
+	}
+
+	/// The session, to see how many messages wait to be acknowledged.
+	pub fn session(&self) -> &Session {
+		&self.session
+	}
+
+	/// Whether there is a connection right now.
+	pub fn is_connected(&self) -> bool {
+		self.client.is_some()
+	}
+
+	/// Drop the connection, if there is one, and connect again.
+	///
+	/// This waits for the peer's hello, so what it has not acknowledged yet
+	/// is sent again before this returns.
+	pub fn reconnect(&mut self) -> Result<(), SessionError> {
+		self.client = None;
+		let mut client = (self.connect)()?;
+		client.send_message(&self.session.hello())?;
+		self.client = Some(client);
+		while !self.read()? {}
+		Ok(())
+	}
+
+	/// Send a message.
+	///
+	/// Once this returns, a text or binary message is kept until the peer
+	/// acknowledges it, even if sending it failed. It fails with
+	/// `SessionError::Full` while too many messages wait, acknowledgements
+	/// are read by `recv`.
+	pub fn send(&mut self, message: OwnedMessage) -> Result<(), SessionError> {
+		let message = self.session.send(message)?;
+		self.write(&message)
+	}
+
+	/// Wait for the next message that was not delivered yet.
+	pub fn recv(&mut self) -> Result<OwnedMessage, SessionError> {
+		loop {
+			if let Some(message) = self.inbox.pop_front() {
+				return Ok(message);
+			}
+			self.read()?;
+		}
+	}
+
+	fn write(&mut self, message: &OwnedMessage) -> Result<(), SessionError> {
+		if self.client.is_none() {
+			// the peer's hello gets the message sent again
+			return self.reconnect();
+		}
+		let written = self.client.as_mut().map(|client| client.send_message(message));
+		if let Some(Err(e)) = written {
+			self.client = None;
+			return Err(e.into());
+		}
+		Ok(())
+	}
+
+	/// Read a message, returns whether it was the peer's hello.
+	fn read(&mut self) -> Result<bool, SessionError> {
+		if self.client.is_none() {
+			self.reconnect()?;
+		}
+		let read = match self.client {
+			Some(ref mut client) => client.recv_message(),
+			None => return Err(SessionError::Disconnected),
+		};
+		let message = match read {
+			Ok(message) => message,
+			Err(e) => {
+				self.client = None;
+				return Err(e.into());
+			}
+		};
+
+		let hello = hello_id(&message).is_some();
+		let received = self.session.receive(message)?;
+		for reply in received.replies {
+			self.write(&reply)?;
+		}
+		match received.message {
+			Some(OwnedMessage::Ping(data)) => self.write(&OwnedMessage::Pong(data)).map(|_| false),
+			Some(OwnedMessage::Pong(_)) | None => Ok(hello),
+			Some(OwnedMessage::Close(_)) => {
+				if let Some(mut client) = self.client.take() {
+					let _ = client.send_message(&OwnedMessage::Close(None));
+				}
+				Err(SessionError::Disconnected)
+			}
+			Some(message) => {
+				self.inbox.push_back(message);
+				Ok(false)
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use std::time::Duration;
+	use server::sync::Server;
+
+	fn text(text: &str) -> OwnedMessage {
+		OwnedMessage::Text(text.to_string())
+	}
+
+	#[test]
+	fn delivers_without_receiving() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+
+		let peer = thread::spawn(move || {
+			let mut session = Session::new(10);
+			let mut delivered = Vec::new();
+			let mut client = server.accept().ok().unwrap().accept().unwrap();
+			client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+			client.send_message(&session.hello()).unwrap();
+			while delivered.len() < 2 {
+				let received = session.receive(client.recv_message().unwrap()).unwrap();
+				for reply in received.replies {
+					client.send_message(&reply).unwrap();
+				}
+				delivered.extend(received.message);
+			}
+			delivered
+		});
+
+		// only ever sends, the first message must not wait for a recv
+		let builder = ClientBuilder::new(&url).unwrap();
+		let mut client = ReliableClient::insecure(builder, 10);
+		client.send(text("one")).unwrap();
+		client.send(text("two")).unwrap();
+		assert_eq!(peer.join().unwrap(), vec![text("one"), text("two")]);
+	}
+
+	#[test]
+	fn messages_survive_a_lost_connection() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+
+		let peer = thread::spawn(move || {
+			let mut session = Session::new(10);
+			let mut delivered = Vec::new();
+
+			// read the first message and hang up before acknowledging it
+			let mut client = server.accept().ok().unwrap().accept().unwrap();
+			client.send_message(&session.hello()).unwrap();
+			while delivered.is_empty() {
+				let received = session.receive(client.recv_message().unwrap()).unwrap();
+				delivered.extend(received.message);
+			}
+			drop(client);
+
+			let mut client = server.accept().ok().unwrap().accept().unwrap();
+			client.send_message(&session.hello()).unwrap();
+			let mut done = false;
+			loop {
+				let received = session.receive(client.recv_message().unwrap()).unwrap();
+				for reply in received.replies {
+					client.send_message(&reply).unwrap();
+				}
+				delivered.extend(received.message);
+				if delivered.len() == 2 && !done {
+					client.send_message(&session.send(text("done")).unwrap()).unwrap();
+					done = true;
+				} else if done && session.unacked() == 0 {
+					return delivered;
+				}
+			}
+		});
+
+		let builder = ClientBuilder::new(&url).unwrap();
+		let mut client = ReliableClient::insecure(builder, 10);
+		client.send(text("one")).unwrap();
+		let _ = client.send(text("two"));
+		let reply = loop {
+			match client.recv() {
+				Ok(reply) => break reply,
+				Err(_) => continue,
+			}
+		};
+		assert_eq!(reply, text("done"));
+		assert_eq!(peer.join().unwrap(), vec![text("one"), text("two")]);
+	}
+}
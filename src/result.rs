@@ -9,6 +9,7 @@ use http;
 use httparse;
 use url::ParseError;
 use server::upgrade::HyperIntoWsError;
+use header::HeaderError;
 
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 use native_tls::Error as TlsError;
@@ -118,7 +119,7 @@ impl From<io::Error> for WebSocketError {
 	}
 }
 
-#[cfg(feature = "async")]
+#[cfg(any(feature = "sync", feature = "async"))]
 impl From<codec::http::HttpCodecError> for WebSocketError {
 	fn from(src: codec::http::HttpCodecError) -> Self {
 		match src {
@@ -158,6 +159,17 @@ impl From<WSUrlErrorKind> for WebSocketError {
 	}
 }
 
+impl From<HeaderError> for WebSocketError {
+	fn from(err: HeaderError) -> WebSocketError {
+		match err {
+			HeaderError::NotVisibleAscii(_) => {
+				WebSocketError::ProtocolError("Header value is not visible ASCII")
+			}
+			HeaderError::Invalid(_) => WebSocketError::ProtocolError("Invalid header value"),
+		}
+	}
+}
+
 impl From<httparse::Error> for WebSocketError {
 	fn from(err: httparse::Error) -> WebSocketError {
 		WebSocketError::HttpError(err.into())
@@ -179,6 +191,12 @@ impl From<HyperIntoWsError> for WebSocketError {
 			NoUpgradeHeader => ProtocolError("Missing Upgrade WebSocket header"),
 			NoWsConnectionHeader => ProtocolError("Invalid Connection WebSocket header"),
 			NoConnectionHeader => ProtocolError("Missing Connection WebSocket header"),
+			Timeout => ProtocolError("Timed out while reading the handshake request"),
+			OriginNotAllowed => ProtocolError("Origin not allowed"),
+			NoRoute => ProtocolError("No route matches the request"),
+			Rejected(_) => ProtocolError("The request was rejected"),
+			AnsweredHttp => ProtocolError("The request was answered as plain HTTP"),
+			InvalidHeader(e) => e.into(),
 		}
 	}
 }
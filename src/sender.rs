@@ -1,7 +1,10 @@
 //! The default implementation of a WebSocket Sender.
 
-use std::io::Write;
+use std::io::{self, Write};
 use std::io::Result as IoResult;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use intercept::{Chain, Interceptor};
 use result::WebSocketResult;
 use ws::dataframe::DataFrame;
 use stream::sync::AsTcpStream;
@@ -42,6 +45,22 @@ where
 	}
 }
 
+impl<W> Writer<W> {
+	/// Run the messages and data frames this writer sends through
+	/// `interceptor`, after the ones added before it.
+	pub fn intercept<I>(&mut self, interceptor: I)
+	where
+		I: Interceptor + 'static,
+	{
+		self.sender.interceptors.push(interceptor);
+	}
+
+	/// Turn this writer into a handle that can be cloned and shared between threads.
+	pub fn into_shared(self) -> SharedWriter<W> {
+		SharedWriter::new(self)
+	}
+}
+
 impl<S> Writer<S>
 where
 	S: AsTcpStream + Write,
@@ -57,18 +76,175 @@ where
 	pub fn shutdown_all(&self) -> IoResult<()> {
 		self.stream.as_tcp().shutdown(Shutdown::Both)
 	}
+
+	/// See [`TcpStream::set_write_timeout`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.set_write_timeout).
+	pub fn set_write_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.as_tcp().set_write_timeout(timeout)
+	}
+}
+
+/// A `Writer` that can be cloned and used from many threads at once.
+///
+/// Every message is serialized up front and written in one go while holding
+/// a lock, so messages sent from different threads never interleave on the
+/// wire. Once a close frame was sent, the connection was shut down or a write
+/// failed, every clone gets an error when sending.
+///
+///```no_run
+///# extern crate websocket;
+///# fn main() {
+///use std::thread;
+///use websocket::{ClientBuilder, Message};
+///
+///let client = ClientBuilder::new("ws://127.0.0.1:1234").unwrap()
+///                     .connect_insecure().unwrap();
+///let (_receiver, sender) = client.split().unwrap();
+///let sender = sender.into_shared();
+///
+///for i in 0..4 {
+///    let sender = sender.clone();
+///    thread::spawn(move || {
+///        sender.send_message(&Message::text(format!("Hello from {}", i))).unwrap();
+///    });
+///}
+///# }
+///```
+pub struct SharedWriter<W> {
+	inner: Arc<Mutex<Shared<W>>>,
+}
+
+struct Shared<W> {
+	writer: Writer<W>,
+	closed: bool,
+}
+
+impl<W> SharedWriter<W> {
+	/// Share a writer.
+	pub fn new(writer: Writer<W>) -> Self {
+		SharedWriter {
+			inner: Arc::new(Mutex::new(Shared {
+				writer: writer,
+				closed: false,
+			})),
+		}
+	}
+
+	/// Whether this connection can no longer be written to.
+	pub fn is_closed(&self) -> bool {
+		self.lock().closed
+	}
+
+	fn lock(&self) -> MutexGuard<Shared<W>> {
+		// the state is consistent between writes, a panic elsewhere can't break it
+		self.inner.lock().unwrap_or_else(|e| e.into_inner())
+	}
+}
+
+impl<W> SharedWriter<W>
+where
+	W: Write,
+{
+	/// Sends a single data frame to the remote endpoint.
+	pub fn send_dataframe<D>(&self, dataframe: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+	{
+		let mut shared = self.lock();
+		if !shared.writer.sender.interceptors.is_empty() {
+			return match shared.writer.sender.interceptors.send_dataframe(dataframe)? {
+				Some(frame) => shared.write_dataframe(&frame),
+				None => Ok(()),
+			};
+		}
+		shared.write_dataframe(dataframe)
+	}
+
+	/// Sends a single message to the remote endpoint.
+	pub fn send_message<M>(&self, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		let mut shared = self.lock();
+		if !shared.writer.sender.interceptors.is_empty() {
+			return match shared.writer.sender.interceptors.send_message(message)? {
+				Some(frame) => shared.write_dataframe(&frame),
+				None => Ok(()),
+			};
+		}
+		let mut frames = Vec::with_capacity(message.message_size(shared.writer.sender.is_masked()));
+		message.serialize(&mut frames, shared.writer.sender.is_masked())?;
+		shared.write(&frames)
+	}
+}
+
+impl<S> SharedWriter<S>
+where
+	S: AsTcpStream + Write,
+{
+	/// Shuts down both sides of the connection, every clone gets an error
+	/// when sending from now on.
+	pub fn shutdown_all(&self) -> IoResult<()> {
+		let mut shared = self.lock();
+		shared.closed = true;
+		shared.writer.shutdown_all()
+	}
+}
+
+impl<W> Clone for SharedWriter<W> {
+	fn clone(&self) -> Self {
+		SharedWriter { inner: self.inner.clone() }
+	}
+}
+
+impl<W> Shared<W>
+where
+	W: Write,
+{
+	fn write_dataframe<D>(&mut self, dataframe: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+	{
+		let mut frame = Vec::with_capacity(dataframe.frame_size(self.writer.sender.is_masked()));
+		dataframe.write_to(&mut frame, self.writer.sender.is_masked())?;
+		self.write(&frame)
+	}
+
+	fn write(&mut self, frames: &[u8]) -> WebSocketResult<()> {
+		if self.closed {
+			return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the connection is closed").into());
+		}
+		let mut result = self.writer.stream.write_all(frames);
+		if result.is_ok() {
+			result = self.writer.stream.flush();
+		}
+		// a close frame can't be fragmented, the first frame tells it apart
+		let close = frames.first().map_or(false, |b| b & 0x0F == 0x8);
+		self.closed = result.is_err() || close;
+		Ok(result?)
+	}
 }
 
 /// A Sender that wraps a Writer and provides a default implementation using
 /// DataFrames and Messages.
 pub struct Sender {
 	mask: bool,
+	interceptors: Chain,
 }
 
 impl Sender {
 	/// Create a new WebSocketSender using the specified Writer.
 	pub fn new(mask: bool) -> Sender {
-		Sender { mask: mask }
+		Sender {
+			mask: mask,
+			interceptors: Chain::new(),
+		}
+	}
+
+	/// The interceptors that messages and data frames pass before they are
+	/// sent.
+	pub fn interceptors(&mut self) -> &mut Chain {
+		&mut self.interceptors
 	}
 }
 
@@ -76,4 +252,59 @@ impl ws::Sender for Sender {
 	fn is_masked(&self) -> bool {
 		self.mask
 	}
+
+	fn send_dataframe<D, W>(&mut self, writer: &mut W, dataframe: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+		W: Write,
+	{
+		if self.interceptors.is_empty() {
+			return dataframe.write_to(writer, self.mask);
+		}
+		match self.interceptors.send_dataframe(dataframe)? {
+			Some(frame) => frame.write_to(writer, self.mask),
+			None => Ok(()),
+		}
+	}
+
+	fn send_message<M, W>(&mut self, writer: &mut W, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+		W: Write,
+	{
+		if self.interceptors.is_empty() {
+			return message.serialize(writer, self.mask);
+		}
+		match self.interceptors.send_message(message)? {
+			Some(frame) => frame.write_to(writer, self.mask),
+			None => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use message::Message;
+
+	#[test]
+	fn shared_writer_reports_close_to_all_clones() {
+		let writer = Writer {
+			stream: Vec::new(),
+			sender: Sender::new(false),
+		};
+		let first = writer.into_shared();
+		let second = first.clone();
+
+		first.send_message(&Message::text("a")).unwrap();
+		second.send_message(&Message::text("b")).unwrap();
+		assert!(!second.is_closed());
+
+		first.send_message(&Message::close()).unwrap();
+		assert!(second.is_closed());
+		assert!(second.send_message(&Message::text("c")).is_err());
+
+		let stream = first.lock().writer.stream.clone();
+		assert_eq!(&stream[..], &[0x81, 1, b'a', 0x81, 1, b'b', 0x88, 0][..]);
+	}
 }
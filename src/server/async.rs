@@ -2,11 +2,19 @@
 use std::io;
 use std::net::ToSocketAddrs;
 use std::net::SocketAddr;
-use server::{WsServer, NoTlsAcceptor};
+use server::{WsServer, NoTlsAcceptor, OptionalTlsAcceptor};
 use tokio::net::{TcpListener, TcpStream};
 use futures::{Stream, Future};
-use server::upgrade::async::{IntoWs, Upgrade};
+use futures::future::{self, Either};
+use server::upgrade::{HandshakeStream, HyperIntoWsError};
+use server::fallback::{self, HttpHandler};
+use server::guard::Guard;
+use codec::http::RequestHead;
+use tokio_io::io::write_all;
+pub use server::upgrade::{HandshakeConfig, OriginPolicy};
+use server::upgrade::async::{IntoWs, Upgrade, respond_to_error};
 use server::InvalidConnection;
+use stream::async::Stream as AsyncStream;
 use bytes::BytesMut;
 pub use tokio::reactor::Handle;
 
@@ -14,6 +22,10 @@ pub use tokio::reactor::Handle;
 use native_tls::TlsAcceptor;
 #[cfg(any(feature = "async-ssl"))]
 use tokio_tls::{TlsAcceptorExt, TlsStream};
+#[cfg(any(feature = "async-ssl"))]
+use std::time::{Duration, Instant};
+#[cfg(any(feature = "async-ssl"))]
+use tokio::timer::Delay;
 
 /// The asynchronous specialization of a websocket server.
 /// Use this struct to create asynchronous servers.
@@ -32,6 +44,16 @@ pub type Incoming<S: Send> = Box<
 		+ Send,
 >;
 
+impl<S> WsServer<S, TcpListener>
+where
+	S: OptionalTlsAcceptor + Send,
+{
+	/// Get the socket address of this server
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.listener.local_addr()
+	}
+}
+
 /// Asynchronous methods for creating an async server and accepting incoming connections.
 impl WsServer<NoTlsAcceptor, TcpListener> {
 	/// Bind a websocket server to an address.
@@ -43,6 +65,10 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::from_std(tcp, handle)?,
 			ssl_acceptor: NoTlsAcceptor,
+			handshake: HandshakeConfig::default(),
+			origin_policy: None,
+			http_fallback: None,
+			guard: None,
 		})
 	}
 
@@ -56,6 +82,10 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 	/// (https://github.com/cyderize/rust-websocket/blob/master/examples/async-server.rs)
 	/// example for a good echo server example.
 	pub fn incoming(self) -> Incoming<TcpStream> {
+		let config = self.handshake;
+		let origin = self.origin_policy;
+		let http_fallback = self.http_fallback;
+		let guard = self.guard;
 		let future = self.listener
 		                 .incoming()
 		                 .map_err(|e| {
@@ -66,18 +96,10 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 				error: e.into(),
 			}
 		})
-		                 .and_then(|stream| {
+		                 .and_then(move |stream| {
 			let a = stream.local_addr().unwrap();
-			stream.into_ws()
-			      .map_err(|(stream, req, buf, err)| {
-				InvalidConnection {
-					stream: Some(stream),
-					parsed: req,
-					buffer: Some(buf),
-					error: err,
-				}
-			})
-			      .map(move |u| (u, a))
+			handshake(stream, config, origin.clone(), http_fallback.clone(), guard.clone())
+				.map(move |u| (u, a))
 		});
 		Box::new(future)
 	}
@@ -102,6 +124,10 @@ impl WsServer<TlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::from_std(tcp, handle)?,
 			ssl_acceptor: acceptor,
+			handshake: HandshakeConfig::default(),
+			origin_policy: None,
+			http_fallback: None,
+			guard: None,
 		})
 	}
 
@@ -116,6 +142,10 @@ impl WsServer<TlsAcceptor, TcpListener> {
 	/// example for a good echo server example.
 	pub fn incoming(self) -> Incoming<TlsStream<TcpStream>> {
 		let acceptor = self.ssl_acceptor;
+		let config = self.handshake;
+		let origin = self.origin_policy;
+		let http_fallback = self.http_fallback;
+		let guard = self.guard;
 		let future = self.listener
 		                 .incoming()
 		                 .map_err(|e| {
@@ -128,30 +158,198 @@ impl WsServer<TlsAcceptor, TcpListener> {
 		})
 		                 .and_then(move |stream| {
 			let a = stream.local_addr().unwrap();
-			acceptor.accept_async(stream)
-			        .map_err(|e| {
-				InvalidConnection {
-					stream: None,
-					parsed: None,
-					buffer: None,
-					// TODO: better error types
-					error: io::Error::new(io::ErrorKind::Other, e).into(),
-				}
-			})
-			        .map(move |s| (s, a))
+			tls_accept(&acceptor, stream, config.timeout)
+				.map_err(|e| {
+					InvalidConnection {
+						stream: None,
+						parsed: None,
+						buffer: None,
+						error: e,
+					}
+				})
+				.map(move |s| (s, a))
 		})
-		                 .and_then(|(stream, a)| {
-			stream.into_ws()
-			      .map_err(|(stream, req, buf, err)| {
-				InvalidConnection {
-					stream: Some(stream),
-					parsed: req,
-					buffer: Some(buf),
-					error: err,
-				}
-			})
-			      .map(move |u| (u, a))
+		                 .and_then(move |(stream, a)| {
+			handshake(stream, config, origin.clone(), http_fallback.clone(), guard.clone())
+				.map(move |u| (u, a))
 		});
 		Box::new(future)
 	}
 }
+
+/// The TLS handshake of a freshly accepted stream, given up on once `timeout`
+/// has passed.
+#[cfg(any(feature = "async-ssl"))]
+fn tls_accept(
+	acceptor: &TlsAcceptor,
+	stream: TcpStream,
+	timeout: Option<Duration>,
+) -> Box<Future<Item = TlsStream<TcpStream>, Error = HyperIntoWsError> + Send> {
+	// TODO: better error types
+	let accept = acceptor.accept_async(stream).map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+	let timeout = match timeout {
+		Some(timeout) => timeout,
+		None => return Box::new(accept.map_err(HyperIntoWsError::from)),
+	};
+	let future = accept.select2(Delay::new(Instant::now() + timeout)).then(|raced| match raced {
+		Ok(Either::A((stream, _))) => Ok(stream),
+		Ok(Either::B(_)) => Err(HyperIntoWsError::Timeout),
+		Err(Either::A((e, _))) => Err(e.into()),
+		Err(Either::B((e, _))) => Err(io::Error::new(io::ErrorKind::Other, e).into()),
+	});
+	Box::new(future)
+}
+
+/// Read the handshake of a freshly accepted stream, answering requests that
+/// broke the server's limits or come from a forbidden origin before handing
+/// them back as an `InvalidConnection`. Plain HTTP requests go to the
+/// `http_fallback` if there is one.
+fn handshake<S>(
+	stream: S,
+	config: HandshakeConfig,
+	origin: Option<OriginPolicy>,
+	http_fallback: Option<HttpHandler>,
+	guard: Option<Guard>,
+) -> Box<Future<Item = Upgrade<S>, Error = InvalidConnection<S, BytesMut>> + Send>
+where
+	S: AsyncStream + Send + 'static,
+{
+	let future = HandshakeStream(stream, config).into_ws().then(move |result| -> Handshake<S> {
+		let (stream, req, buf, err) = match result {
+			Ok(upgrade) => return Box::new(future::ok(upgrade)),
+			Err(failed) => failed,
+		};
+		let handler = http_fallback.filter(|_| fallback::is_plain_http(&err));
+		match (handler, req) {
+			(Some(handler), Some(request)) => serve_http(stream, &handler, request, buf),
+			(_, req) => {
+				let failed = respond_to_error(stream, &err).then(move |answered| {
+					Err(InvalidConnection {
+						// the peer is getting an error either way, a failed answer only loses the stream
+						stream: answered.ok(),
+						parsed: req,
+						buffer: Some(buf),
+						error: err,
+					})
+				});
+				Box::new(failed)
+			}
+		}
+	})
+		.and_then(move |upgrade| match origin {
+			Some(ref policy) => {
+				let checked = upgrade.enforce_origin(policy).map_err(|(stream, err)| {
+					InvalidConnection {
+						stream: stream,
+						parsed: None,
+						buffer: None,
+						error: err,
+					}
+				});
+				Either::A(checked)
+			}
+			None => Either::B(future::ok(upgrade)),
+		})
+		.and_then(move |upgrade| match guard {
+			Some(ref guard) => Either::A(check_guard(upgrade, guard)),
+			None => Either::B(future::ok(upgrade)),
+		});
+	Box::new(future)
+}
+
+/// Run `guard` on an upgrade, keeping the context it returns on the upgrade or
+/// answering with its rejection.
+fn check_guard<S>(mut upgrade: Upgrade<S>, guard: &Guard) -> Handshake<S>
+where
+	S: AsyncStream + Send + 'static,
+{
+	let future = guard.check_async(&upgrade.request).then(move |checked| -> Handshake<S> {
+		let rejection = match checked {
+			Ok(context) => {
+				upgrade.set_context(context);
+				return Box::new(future::ok(upgrade));
+			}
+			Err(rejection) => rejection,
+		};
+		let status = rejection.status;
+		let rejected = upgrade.send_rejection(rejection).then(move |sent| {
+			let (stream, error) = match sent {
+				Ok(stream) => (Some(stream), HyperIntoWsError::Rejected(status)),
+				Err(e) => (None, e.into()),
+			};
+			Err(InvalidConnection {
+				stream: stream,
+				parsed: None,
+				buffer: None,
+				error: error,
+			})
+		});
+		Box::new(rejected)
+	});
+	Box::new(future)
+}
+
+type Handshake<S> = Box<Future<Item = Upgrade<S>, Error = InvalidConnection<S, BytesMut>> + Send>;
+
+/// Answer a plain HTTP request and let the connection go, reading another
+/// request here would hold up every connection accepted after it.
+fn serve_http<S>(stream: S, handler: &HttpHandler, request: RequestHead, buffer: BytesMut) -> Handshake<S>
+where
+	S: AsyncStream + Send + 'static,
+{
+	let response = match fallback::serve(handler, request) {
+		Ok(response) => response,
+		Err(e) => {
+			return Box::new(future::err(InvalidConnection {
+				stream: Some(stream),
+				parsed: None,
+				buffer: Some(buffer),
+				error: e,
+			}))
+		}
+	};
+
+	let future = write_all(stream, response).then(move |written| {
+		let (stream, error) = match written {
+			Ok((stream, _)) => (Some(stream), HyperIntoWsError::AnsweredHttp),
+			Err(e) => (None, e.into()),
+		};
+		Err(InvalidConnection {
+			stream: stream,
+			parsed: None,
+			buffer: Some(buffer),
+			error: error,
+		})
+	});
+	Box::new(future)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use http::StatusCode;
+	use tokio::runtime::Runtime;
+	use client::builder::ClientBuilder;
+	use server::guard::Rejection;
+
+	#[test]
+	fn guard_rejects_before_handing_out() {
+		let mut runtime = Runtime::new().unwrap();
+		let mut server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		server.guard = Some(Guard::new_async(|_: &RequestHead| {
+			future::err::<(), _>(Rejection::new(StatusCode::FORBIDDEN))
+		}));
+
+		let peer = thread::spawn(move || ClientBuilder::new(&url).unwrap().connect_insecure().is_err());
+
+		match runtime.block_on(server.incoming().into_future()) {
+			Err((InvalidConnection { error: HyperIntoWsError::Rejected(status), .. }, _)) => {
+				assert_eq!(status, StatusCode::FORBIDDEN)
+			}
+			_ => panic!("expected the request to be rejected"),
+		}
+		assert!(peer.join().unwrap());
+	}
+}
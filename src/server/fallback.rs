@@ -0,0 +1,107 @@
+//! Answer plain HTTP requests that arrive on a websocket server.
+//!
+//! Health checks, metrics scrapers and browsers looking for a landing page
+//! all send ordinary requests to the websocket port. Give the server an
+//! `HttpHandler` and those requests are handed to it as an `http::Request`
+//! instead of failing the handshake, its `http::Response` is sent back with
+//! `Connection: close` and the connection is handed back as an
+//! `InvalidConnection` to be dropped.
+//!
+//! Every connection gets one answer, so that an idle client can not hold up
+//! accepting the next one. Only the head of a request is read, its body (if
+//! any) is not available to the handler.
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use http::{Request, Response};
+use http::header::{HeaderValue, CONNECTION};
+
+use codec::http::{HttpServerCodec, RequestHead, ResponseHead};
+use server::upgrade::HyperIntoWsError;
+
+/// A function that answers the plain HTTP requests a server receives.
+///
+/// ```rust,no_run
+/// # extern crate websocket;
+/// # extern crate http;
+/// # fn main() {
+/// use std::sync::Arc;
+/// use http::{Response, StatusCode};
+/// use websocket::sync::Server;
+///
+/// let mut server = Server::bind("127.0.0.1:2794").unwrap();
+/// server.http_fallback = Some(Arc::new(|request: http::Request<()>| {
+///     let status = match request.uri().path() {
+///         "/health" => StatusCode::OK,
+///         _ => StatusCode::NOT_FOUND,
+///     };
+///     Response::builder().status(status).body(Vec::new()).unwrap()
+/// }));
+/// # }
+/// ```
+pub type HttpHandler = Arc<Fn(Request<()>) -> Response<Vec<u8>> + Send + Sync>;
+
+/// Whether a failed handshake is simply a request that never tried to be an
+/// upgrade, as opposed to a broken upgrade attempt.
+pub(crate) fn is_plain_http(error: &HyperIntoWsError) -> bool {
+	match *error {
+		HyperIntoWsError::MethodNotGet |
+		HyperIntoWsError::NoUpgradeHeader |
+		HyperIntoWsError::NoWsUpgradeHeader |
+		HyperIntoWsError::NoConnectionHeader |
+		HyperIntoWsError::NoWsConnectionHeader => true,
+		_ => false,
+	}
+}
+
+/// Run `handler` on a request, returning the encoded response. The response
+/// always closes the connection.
+pub(crate) fn serve(handler: &HttpHandler, request: RequestHead) -> Result<BytesMut, HyperIntoWsError> {
+	let mut http_request = Request::new(());
+	*http_request.method_mut() = request.subject.0;
+	*http_request.uri_mut() = request.subject.1;
+	*http_request.version_mut() = request.version;
+	*http_request.headers_mut() = request.headers;
+
+	let (parts, body) = handler(http_request).into_parts();
+	let mut headers = parts.headers;
+	headers.insert(CONNECTION, HeaderValue::from_static("close"));
+
+	let mut buf = BytesMut::new();
+	let response = ResponseHead {
+		version: parts.version,
+		subject: parts.status,
+		headers: headers,
+	};
+	try!(HttpServerCodec.encode_with_body(response, &body, &mut buf));
+	Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use http::{Method, Version};
+	use http::header::HeaderMap;
+	use codec::http::RequestLine;
+
+	#[test]
+	fn serve_encodes_response_and_closes() {
+		let handler: HttpHandler = Arc::new(|request: Request<()>| {
+			assert_eq!(request.uri().path(), "/health");
+			Response::new(b"ok".to_vec())
+		});
+		let mut headers = HeaderMap::new();
+		headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+		let request = RequestHead {
+			version: Version::HTTP_11,
+			subject: RequestLine(Method::GET, "/health".parse().unwrap()),
+			headers: headers,
+		};
+
+		let buf = serve(&handler, request).unwrap();
+		assert_eq!(
+			&buf[..],
+			&b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 2\r\n\r\nok"[..]
+		);
+	}
+}
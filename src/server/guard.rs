@@ -0,0 +1,205 @@
+//! Check a request before accepting it and keep what was learned about it.
+//!
+//! A guard is a function that looks at the `RequestHead` of an upgrade (e.g.
+//! for a bearer token or a session cookie) and either returns a context value,
+//! like the authenticated user, or a `Rejection` to answer the client with.
+//! Give a server a `Guard` and it runs it on every upgrade before handing it
+//! out, the context is kept on the `Upgrade` and ends up in a `WithContext`
+//! next to the client once it is accepted with `accept_with_context`.
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # extern crate http;
+//! # fn main() {
+//! use http::StatusCode;
+//! use http::header::AUTHORIZATION;
+//! use websocket::server::guard::{Guard, Rejection};
+//! use websocket::sync::Server;
+//!
+//! let mut server = Server::bind("127.0.0.1:2794").unwrap();
+//! server.guard = Some(Guard::new(|request| {
+//!     match request.headers.get(AUTHORIZATION).and_then(|a| a.to_str().ok()) {
+//!         Some("Bearer open-sesame") => Ok("ali baba".to_string()),
+//!         _ => Err(Rejection::new(StatusCode::UNAUTHORIZED).body("who are you?")),
+//!     }
+//! }));
+//!
+//! for upgrade in server.filter_map(Result::ok) {
+//!     let client = upgrade.accept_with_context().unwrap();
+//!     println!("{} connected", client.context::<String>().unwrap());
+//! }
+//! # }
+//! ```
+use std::any::Any;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use http::StatusCode;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+#[cfg(feature = "async")]
+use futures::{Future, IntoFuture};
+#[cfg(feature = "async")]
+use tokio::runtime::current_thread;
+
+use codec::http::RequestHead;
+
+/// The context a guard returned for a request, whatever its type was.
+pub type AnyContext = Box<Any + Send>;
+
+/// A check a server runs on every upgrade request before handing it out.
+///
+/// The sync server only runs a guard made with `new_async` on the workers of
+/// its handshake pool, where each check gets a single threaded tokio runtime
+/// to finish on. Its `accept` and `incoming` answer such requests with
+/// `500 Internal Server Error` rather than hold up every later connection.
+#[derive(Clone)]
+pub struct Guard(Check);
+
+#[derive(Clone)]
+enum Check {
+	Now(Arc<Fn(&RequestHead) -> Result<AnyContext, Rejection> + Send + Sync>),
+	#[cfg(feature = "async")]
+	Later(
+		Arc<
+			Fn(&RequestHead) -> Box<Future<Item = AnyContext, Error = Rejection> + Send>
+				+ Send
+				+ Sync,
+		>,
+	),
+}
+
+impl Guard {
+	/// A guard that answers right away.
+	pub fn new<T, F>(guard: F) -> Self
+	where
+		F: Fn(&RequestHead) -> Result<T, Rejection> + Send + Sync + 'static,
+		T: Any + Send,
+	{
+		Guard(Check::Now(Arc::new(move |request: &RequestHead| {
+			guard(request).map(|context| Box::new(context) as AnyContext)
+		})))
+	}
+
+	/// A guard that answers with a future, e.g. to look a token up in a
+	/// database.
+	#[cfg(feature = "async")]
+	pub fn new_async<T, F, R>(guard: F) -> Self
+	where
+		F: Fn(&RequestHead) -> R + Send + Sync + 'static,
+		R: IntoFuture<Item = T, Error = Rejection>,
+		R::Future: Send + 'static,
+		T: Any + Send,
+	{
+		Guard(Check::Later(Arc::new(move |request: &RequestHead| {
+			let checked = guard(request).into_future().map(|context| Box::new(context) as AnyContext);
+			Box::new(checked) as Box<Future<Item = AnyContext, Error = Rejection> + Send>
+		})))
+	}
+
+	/// Whether the guard answers with a future, i.e. it was made with
+	/// `new_async`.
+	pub fn is_async(&self) -> bool {
+		match self.0 {
+			Check::Now(_) => false,
+			#[cfg(feature = "async")]
+			Check::Later(_) => true,
+		}
+	}
+
+	/// Run the guard, blocking until it answers. A future is driven on a
+	/// runtime of its own, so it has a reactor and a timer to use.
+	pub(crate) fn check(&self, request: &RequestHead) -> Result<AnyContext, Rejection> {
+		match self.0 {
+			Check::Now(ref guard) => guard(request),
+			#[cfg(feature = "async")]
+			Check::Later(ref guard) => current_thread::block_on_all(guard(request)),
+		}
+	}
+
+	/// Run the guard without blocking.
+	#[cfg(feature = "async")]
+	pub(crate) fn check_async(
+		&self,
+		request: &RequestHead,
+	) -> Box<Future<Item = AnyContext, Error = Rejection> + Send> {
+		match self.0 {
+			Check::Now(ref guard) => Box::new(guard(request).into_future()),
+			Check::Later(ref guard) => guard(request),
+		}
+	}
+}
+
+/// The answer a guard gives to a request it does not let through.
+#[derive(Clone, Debug)]
+pub struct Rejection {
+	/// The status of the response, e.g. `401 Unauthorized`.
+	pub status: StatusCode,
+	/// Extra headers to send, e.g. `WWW-Authenticate`.
+	pub headers: HeaderMap,
+	/// The body of the response, may be empty.
+	pub body: Vec<u8>,
+}
+
+impl Rejection {
+	/// A rejection with the given status, no extra headers and an empty body.
+	pub fn new(status: StatusCode) -> Self {
+		Rejection {
+			status: status,
+			headers: HeaderMap::new(),
+			body: Vec::new(),
+		}
+	}
+
+	/// Add a header to the response.
+	pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+		self.headers.append(name, value);
+		self
+	}
+
+	/// Set the body of the response.
+	pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+		self.body = body.into();
+		self
+	}
+}
+
+/// An accepted client along with the context its guard returned.
+///
+/// This derefs to the client, so it can be used just like one while the
+/// context stays at hand for whoever handles the messages.
+#[derive(Debug)]
+pub struct WithContext<C, T> {
+	/// The accepted client.
+	pub client: C,
+	/// The context the guard returned for this connection.
+	pub context: T,
+}
+
+impl<C, T> WithContext<C, T> {
+	/// Take the client and its context apart.
+	pub fn into_parts(self) -> (C, T) {
+		(self.client, self.context)
+	}
+}
+
+impl<C> WithContext<C, Option<AnyContext>> {
+	/// The context the server's guard returned, if there was a guard and its
+	/// context is a `T`.
+	pub fn context<T: Any>(&self) -> Option<&T> {
+		self.context.as_ref().and_then(|context| context.downcast_ref())
+	}
+}
+
+impl<C, T> Deref for WithContext<C, T> {
+	type Target = C;
+
+	fn deref(&self) -> &C {
+		&self.client
+	}
+}
+
+impl<C, T> DerefMut for WithContext<C, T> {
+	fn deref_mut(&mut self) -> &mut C {
+		&mut self.client
+	}
+}
@@ -4,16 +4,37 @@ use native_tls::TlsAcceptor;
 
 use codec::http::RequestHead;
 use stream::Stream;
-use self::upgrade::HyperIntoWsError;
+use self::upgrade::{HyperIntoWsError, HandshakeConfig, OriginPolicy};
+#[cfg(any(feature = "sync", feature = "async"))]
+use self::guard::Guard;
+#[cfg(any(feature = "sync", feature = "async"))]
+use self::fallback::HttpHandler;
 
 pub mod upgrade;
 
+#[cfg(any(feature = "sync", feature = "async"))]
+pub mod router;
+
+pub mod guard;
+
+#[cfg(any(feature = "sync", feature = "async"))]
+pub mod fallback;
+
 #[cfg(feature = "async")]
 pub mod async;
 
+#[cfg(feature = "async")]
+pub mod service;
+
 #[cfg(feature = "sync")]
 pub mod sync;
 
+#[cfg(feature = "sync")]
+pub mod pool;
+
+#[cfg(feature = "sync")]
+pub mod runtime;
+
 /// Marker struct for a struct not being secure
 #[derive(Clone)]
 pub struct NoTlsAcceptor;
@@ -96,4 +117,16 @@ where
 	listener: L,
 	/// The SSL acceptor given to the server
 	pub ssl_acceptor: S,
+	/// The limits put on reading the handshake of every incoming connection.
+	/// Requests that break these limits are answered with a 408, 414 or 431.
+	pub handshake: HandshakeConfig,
+	/// Which origins may connect, `None` lets every origin through.
+	/// Requests from other origins are answered with a 403.
+	pub origin_policy: Option<OriginPolicy>,
+	/// Answers the requests that are not websocket upgrades, `None` fails them
+	/// like any other broken handshake.
+	pub http_fallback: Option<HttpHandler>,
+	/// Checks every upgrade request before it is handed out, `None` lets every
+	/// request through. Requests it turns down are answered with its `Rejection`.
+	pub guard: Option<Guard>,
 }
@@ -0,0 +1,201 @@
+//! Read handshakes on a pool of threads so one slow client can't hold up the server.
+//!
+//! A plain sync `Server` reads the handshake of every connection on the thread
+//! that called `accept`, a client that connects and then says nothing blocks
+//! everyone after it. A `HandshakePool` keeps accepting sockets on a thread of
+//! its own and hands them to a fixed number of workers that read the handshakes,
+//! the finished `Upgrade`s come out of the pool in the order they are ready.
+use std::cmp;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use server::InvalidConnection;
+use server::sync::AcceptResult;
+use stream::sync::Stream;
+
+/// A worker also waits this long for a handshake when the server's
+/// `HandshakeConfig` has no timeout, without one a single silent client would
+/// take a worker out of the pool for good.
+pub use server::upgrade::DEFAULT_HANDSHAKE_TIMEOUT_SECS;
+
+/// The upgrades of a server whose handshakes are read by worker threads.
+///
+/// Create one with `Server::into_handshake_pool`, then use it like the server
+/// itself: it is an iterator of `AcceptResult`s.
+///
+/// ```rust,no_run
+/// # extern crate websocket;
+/// # fn main() {
+/// use std::thread;
+/// use websocket::sync::Server;
+///
+/// let pool = Server::bind("127.0.0.1:1234").unwrap()
+///     .into_handshake_pool(8)
+///     .unwrap();
+///
+/// for upgrade in pool.filter_map(Result::ok) {
+///     thread::spawn(move || {
+///         let client = upgrade.accept().unwrap();
+///         // ...
+///     });
+/// }
+/// # }
+/// ```
+pub struct HandshakePool<S>
+where
+	S: Stream + Send,
+{
+	results: Receiver<AcceptResult<S>>,
+}
+
+impl<S> HandshakePool<S>
+where
+	S: Stream + Send,
+{
+	/// Wait for the next finished handshake. Returns `None` once the
+	/// accepting thread and all workers are gone.
+	pub fn accept(&self) -> Option<AcceptResult<S>> {
+		self.results.recv().ok()
+	}
+
+	/// Get the next finished handshake if there is one, without waiting.
+	pub fn try_accept(&self) -> Option<AcceptResult<S>> {
+		self.results.try_recv().ok()
+	}
+}
+
+impl<S> Iterator for HandshakePool<S>
+where
+	S: Stream + Send,
+{
+	type Item = AcceptResult<S>;
+
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		self.accept()
+	}
+}
+
+/// Start accepting on `listener` and run `handshake` on every new socket
+/// using `workers` threads.
+pub(crate) fn spawn<S, F>(
+	listener: TcpListener,
+	workers: usize,
+	handshake: F,
+) -> io::Result<HandshakePool<S>>
+where
+	S: Stream + Send + 'static,
+	F: Fn(TcpStream) -> AcceptResult<S> + Send + Sync + 'static,
+{
+	let workers = cmp::max(workers, 1);
+	// both queues are bounded so a slow consumer pushes back on the listener,
+	// letting the kernel's backlog hold the sockets instead of our memory
+	let (results_tx, results_rx) = mpsc::sync_channel(workers);
+	let (streams_tx, streams_rx) = mpsc::sync_channel::<TcpStream>(workers);
+	let streams_rx = Arc::new(Mutex::new(streams_rx));
+	let handshake = Arc::new(handshake);
+
+	for i in 0..workers {
+		let streams = streams_rx.clone();
+		let results = results_tx.clone();
+		let handshake = handshake.clone();
+		thread::Builder::new()
+			.name(format!("websocket-handshake-{}", i))
+			.spawn(move || loop {
+				let stream = match streams.lock() {
+					Ok(streams) => streams.recv(),
+					Err(_) => return,
+				};
+				let stream = match stream {
+					Ok(stream) => stream,
+					Err(_) => return,
+				};
+				if results.send(handshake(stream)).is_err() {
+					return;
+				}
+			})?;
+	}
+
+	thread::Builder::new()
+		.name("websocket-accept".to_string())
+		.spawn(move || for stream in listener.incoming() {
+			let sent = match stream {
+				Ok(stream) => streams_tx.send(stream).is_ok(),
+				Err(e) => {
+					let failed = InvalidConnection {
+						stream: None,
+						parsed: None,
+						buffer: None,
+						error: e.into(),
+					};
+					results_tx.send(Err(failed)).is_ok()
+				}
+			};
+			if !sent {
+				return;
+			}
+		})?;
+
+	Ok(HandshakePool { results: results_rx })
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+	use std::net::TcpStream;
+	use server::sync::Server;
+
+	#[test]
+	fn silent_client_does_not_block_others() {
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let pool = server.into_handshake_pool(2).unwrap();
+
+		// connects but never sends a request
+		let _silent = TcpStream::connect(addr).unwrap();
+
+		let mut client = TcpStream::connect(addr).unwrap();
+		client.write_all(b"GET /chat HTTP/1.1\r\n\
+			Host: localhost\r\n\
+			Upgrade: websocket\r\n\
+			Connection: Upgrade\r\n\
+			Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+			Sec-WebSocket-Version: 13\r\n\
+			\r\n")
+		      .unwrap();
+
+		let upgrade = pool.accept().unwrap().ok().unwrap();
+		assert_eq!(upgrade.request.subject.1.path(), "/chat");
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn tls_streams_get_their_read_timeout_back() {
+		use std::thread;
+		use native_tls::{Certificate, Pkcs12, TlsAcceptor, TlsConnector};
+		use client::builder::ClientBuilder;
+
+		let identity = Pkcs12::from_der(include_bytes!("../../fixtures/localhost.p12"), "websocket").unwrap();
+		let acceptor = TlsAcceptor::builder(identity).unwrap().build().unwrap();
+		let server = Server::bind_secure("127.0.0.1:0", acceptor).unwrap();
+		let port = server.local_addr().unwrap().port();
+		let pool = server.into_handshake_pool(1).unwrap();
+
+		let client = thread::spawn(move || {
+			let root = Certificate::from_der(include_bytes!("../../fixtures/localhost.der")).unwrap();
+			let mut connector = TlsConnector::builder().unwrap();
+			connector.add_root_certificate(root).unwrap();
+			ClientBuilder::new(&format!("wss://localhost:{}", port))
+				.unwrap()
+				.connect_secure(Some(connector.build().unwrap()))
+				.unwrap()
+		});
+
+		let upgrade = pool.accept().unwrap().ok().unwrap();
+		assert_eq!(upgrade.stream.get_ref().read_timeout().unwrap(), None);
+		upgrade.accept().ok().unwrap();
+		client.join().unwrap();
+	}
+}
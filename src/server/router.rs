@@ -0,0 +1,313 @@
+//! Route websocket upgrades to handlers by their path and host.
+//!
+//! Instead of matching on `upgrade.request.subject` by hand, register a handler
+//! for every endpoint and let a `Router` accept the connection and pass it on:
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # fn main() {
+//! use std::net::TcpStream;
+//! use websocket::sync::{Client, Server};
+//! use websocket::server::router::{Params, Router};
+//!
+//! let router = Router::new()
+//!     .route("/chat", |client: Client<TcpStream>, _: Params| {
+//!         // ...
+//!     })
+//!     .route("/rooms/:id", |client: Client<TcpStream>, params: Params| {
+//!         println!("joined room {}", params.get("id").unwrap());
+//!     });
+//!
+//! let server = Server::bind("127.0.0.1:2794").unwrap();
+//! for upgrade in server.filter_map(Result::ok) {
+//!     // unmatched paths are answered with a 404
+//!     let _ = router.dispatch(upgrade);
+//! }
+//! # }
+//! ```
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use http::header::HOST;
+use url::percent_encoding::percent_decode;
+
+use codec::http::RequestHead;
+use header::Host;
+use server::upgrade::HyperIntoWsError;
+
+#[cfg(feature = "sync")]
+use client::sync::Client as SyncClient;
+#[cfg(feature = "sync")]
+use server::upgrade::sync::Upgrade as SyncUpgrade;
+#[cfg(feature = "sync")]
+use stream::sync::Stream as SyncStream;
+
+#[cfg(feature = "async")]
+use futures::Future;
+#[cfg(feature = "async")]
+use client::async::Client as AsyncClient;
+#[cfg(feature = "async")]
+use result::WebSocketError;
+#[cfg(feature = "async")]
+use server::upgrade::async::Upgrade as AsyncUpgrade;
+#[cfg(feature = "async")]
+use stream::async::Stream as AsyncStream;
+
+use http::StatusCode;
+
+/// Something that takes over an accepted connection of type `C`.
+///
+/// This is implemented for all closures taking the client and its `Params`.
+pub trait RouteHandler<C>: Send + Sync {
+	/// Handle a freshly accepted connection.
+	fn handle(&self, client: C, params: Params);
+}
+
+impl<C, F> RouteHandler<C> for F
+where
+	F: Fn(C, Params) + Send + Sync,
+{
+	fn handle(&self, client: C, params: Params) {
+		self(client, params)
+	}
+}
+
+/// The parameters extracted from a request path, e.g. `id` for `/rooms/:id`.
+///
+/// Values are percent-decoded, so `/rooms/a%20b` has an `id` of `a b`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+	/// The value of the parameter called `name`, without the leading `:`.
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.0
+		    .iter()
+		    .find(|&&(ref n, _)| n == name)
+		    .map(|&(_, ref v)| v.as_str())
+	}
+
+	/// All parameters as `(name, value)` pairs, in the order they appear in the path.
+	pub fn iter(&self) -> ::std::slice::Iter<(String, String)> {
+		self.0.iter()
+	}
+}
+
+#[derive(Debug)]
+enum Segment {
+	Literal(String),
+	Param(String),
+}
+
+struct Route<C> {
+	host: Option<String>,
+	path: Vec<Segment>,
+	handler: Arc<RouteHandler<C>>,
+}
+
+/// Maps request paths (and optionally hosts) to handlers.
+///
+/// Paths are split on `/`, segments starting with `:` match any single segment
+/// and are handed to the handler in `Params`. Segments are percent-decoded
+/// after splitting, so an encoded `/` stays within its segment, and a path
+/// that doesn't decode to UTF-8 matches no route. Routes are tried in the
+/// order they were added, the first match wins.
+///
+/// `C` is the client handlers receive, a `sync::Client<S>` or an `async::Client<S>`.
+pub struct Router<C> {
+	routes: Vec<Route<C>>,
+}
+
+impl<C> Router<C> {
+	/// Create a router without any routes, it answers everything with a 404.
+	pub fn new() -> Self {
+		Router { routes: Vec::new() }
+	}
+
+	/// Send requests for `path` on any host to `handler`.
+	pub fn route<H>(self, path: &str, handler: H) -> Self
+	where
+		H: RouteHandler<C> + 'static,
+	{
+		self.add(None, path, handler)
+	}
+
+	/// Send requests for `path` to `handler`, but only if their `Host` header
+	/// names `host` (compared case insensitively, the port is ignored).
+	pub fn route_host<H>(self, host: &str, path: &str, handler: H) -> Self
+	where
+		H: RouteHandler<C> + 'static,
+	{
+		self.add(Some(host.to_ascii_lowercase()), path, handler)
+	}
+
+	fn add<H>(mut self, host: Option<String>, path: &str, handler: H) -> Self
+	where
+		H: RouteHandler<C> + 'static,
+	{
+		let path = segments(path)
+			.into_iter()
+			.map(|s| if s.starts_with(':') {
+				Segment::Param(s[1..].to_owned())
+			} else {
+				Segment::Literal(s.to_owned())
+			})
+			.collect();
+		self.routes.push(Route {
+			host: host,
+			path: path,
+			handler: Arc::new(handler),
+		});
+		self
+	}
+
+	/// Find the handler for a request, along with the parameters of its path.
+	pub fn find(&self, request: &RequestHead) -> Option<(Arc<RouteHandler<C>>, Params)> {
+		let host = request.headers
+		                  .get(HOST)
+		                  .and_then(|h| Host::try_from(h).ok())
+		                  .map(|h| h.hostname().to_ascii_lowercase());
+		let path = segments(request.subject.1.path())
+			.into_iter()
+			.map(|s| percent_decode(s.as_bytes()).decode_utf8())
+			.collect::<Result<Vec<_>, _>>();
+		let path = match path {
+			Ok(path) => path,
+			Err(_) => return None,
+		};
+
+		self.routes.iter().filter_map(|route| {
+			if let Some(ref wanted) = route.host {
+				if host.as_ref() != Some(wanted) {
+					return None;
+				}
+			}
+			if route.path.len() != path.len() {
+				return None;
+			}
+			let mut params = Vec::new();
+			for (segment, part) in route.path.iter().zip(path.iter()) {
+				match *segment {
+					Segment::Literal(ref literal) if literal == part.as_ref() => (),
+					Segment::Literal(_) => return None,
+					Segment::Param(ref name) => params.push((name.clone(), part.to_string())),
+				}
+			}
+			Some((route.handler.clone(), Params(params)))
+		}).next()
+	}
+}
+
+impl<C> Default for Router<C> {
+	fn default() -> Self {
+		Router::new()
+	}
+}
+
+#[cfg(feature = "sync")]
+impl<S> Router<SyncClient<S>>
+where
+	S: SyncStream + Send,
+{
+	/// Accept the upgrade and run the handler of its route on this thread,
+	/// or answer with a `404 Not Found` if no route matches.
+	///
+	/// The stream is handed back along with `HyperIntoWsError::NoRoute` if
+	/// nothing matched, or the IO error that happened while answering.
+	pub fn dispatch(&self, upgrade: SyncUpgrade<S>) -> Result<(), (S, HyperIntoWsError)> {
+		match self.find(&upgrade.request) {
+			Some((handler, params)) => {
+				let client = try!(upgrade.accept().map_err(|(s, e)| (s, e.into())));
+				handler.handle(client, params);
+				Ok(())
+			}
+			None => {
+				match upgrade.internal_reject(Some(not_found_headers()), StatusCode::NOT_FOUND, None) {
+					Ok(stream) => Err((stream, HyperIntoWsError::NoRoute)),
+					Err((stream, e)) => Err((stream, e.into())),
+				}
+			}
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+impl<S> Router<AsyncClient<S>>
+where
+	S: AsyncStream + Send + 'static,
+{
+	/// Asynchronously accept the upgrade and run the handler of its route once
+	/// the handshake is done, or answer with a `404 Not Found` if no route matches.
+	pub fn dispatch(
+		&self,
+		upgrade: AsyncUpgrade<S>,
+	) -> Box<Future<Item = (), Error = WebSocketError> + Send> {
+		match self.find(&upgrade.request) {
+			Some((handler, params)) => {
+				let future = upgrade.accept()
+				                    .map(move |(client, _)| handler.handle(client, params));
+				Box::new(future)
+			}
+			None => {
+				let future = upgrade.internal_reject(Some(not_found_headers()), StatusCode::NOT_FOUND)
+				                    .map_err(WebSocketError::from)
+				                    .and_then(|_| {
+					Err::<(), _>(WebSocketError::from(HyperIntoWsError::NoRoute))
+				});
+				Box::new(future)
+			}
+		}
+	}
+}
+
+fn not_found_headers() -> ::http::header::HeaderMap {
+	HyperIntoWsError::NoRoute
+		.response()
+		.map(|r| r.headers)
+		.unwrap_or_default()
+}
+
+fn segments(path: &str) -> Vec<&str> {
+	path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use http::{Method, Version};
+	use http::header::{HeaderMap, HeaderValue};
+	use codec::http::RequestLine;
+
+	fn request(host: &'static str, path: &str) -> RequestHead {
+		let mut headers = HeaderMap::new();
+		headers.insert(HOST, HeaderValue::from_static(host));
+		RequestHead {
+			version: Version::HTTP_11,
+			subject: RequestLine(Method::GET, path.parse().unwrap()),
+			headers: headers,
+		}
+	}
+
+	#[test]
+	fn routes_by_path_and_host() {
+		let router: Router<&'static str> = Router::new()
+			.route_host("admin.example.com", "/", |_: &'static str, _: Params| ())
+			.route("/rooms/:id/users/:user", |_: &'static str, _: Params| ())
+			.route("/chat", |_: &'static str, _: Params| ());
+
+		let (_, params) = router.find(&request("example.com", "/rooms/42/users/bob?x=1")).unwrap();
+		assert_eq!(params.get("id"), Some("42"));
+		assert_eq!(params.get("user"), Some("bob"));
+
+		assert!(router.find(&request("example.com", "/chat/")).is_some());
+		assert!(router.find(&request("Admin.Example.com:8080", "/")).is_some());
+		assert!(router.find(&request("example.com", "/")).is_none());
+		assert!(router.find(&request("example.com", "/rooms/42")).is_none());
+
+		let (_, params) = router.find(&request("example.com", "/rooms/a%20b/users/x%2Fy")).unwrap();
+		assert_eq!(params.get("id"), Some("a b"));
+		assert_eq!(params.get("user"), Some("x/y"));
+		assert!(router.find(&request("example.com", "/ch%61t")).is_some());
+		assert!(router.find(&request("example.com", "/rooms/%FF/users/bob")).is_none());
+	}
+}
@@ -0,0 +1,336 @@
+//! A ready-made multi-threaded sync server.
+//!
+//! Instead of spawning a thread for every upgrade and looping over its
+//! messages by hand, implement `Handler` for the state of a connection and let
+//! a `Runtime` drive it:
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # fn main() {
+//! use websocket::OwnedMessage;
+//! use websocket::sync::Server;
+//! use websocket::server::runtime::{Handler, Outbound, Runtime};
+//! use websocket::server::upgrade::sync::Upgrade;
+//!
+//! struct Echo;
+//!
+//! impl Handler for Echo {
+//!     fn on_message(&mut self, out: &Outbound, message: OwnedMessage) {
+//!         let _ = out.send(&message);
+//!     }
+//! }
+//!
+//! let server = Server::bind("127.0.0.1:2794").unwrap();
+//! Runtime::new(|_: &mut Upgrade<_>| Echo)
+//!     .threads(32)
+//!     .max_connections(1000)
+//!     .run(server)
+//!     .unwrap();
+//! # }
+//! ```
+//!
+//! Pings are answered and close frames are echoed by the runtime, handlers
+//! only see the other messages. A handler that panics takes its connection
+//! down with it, the thread goes on serving the next one.
+use std::cmp;
+use std::io;
+use std::net::TcpStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use http::StatusCode;
+use http::header::{HeaderMap, HeaderValue, CONNECTION};
+
+use message::{CloseData, OwnedMessage};
+use result::{WebSocketError, WebSocketResult};
+use sender::SharedWriter;
+use server::NoTlsAcceptor;
+use server::sync::Server;
+use server::upgrade::sync::Upgrade;
+use ws;
+
+/// The callbacks of a single connection run by a `Runtime`.
+///
+/// A handler is created for every accepted connection and all of its callbacks
+/// are called on the thread serving that connection.
+pub trait Handler {
+	/// Called once the handshake is done, before any message is read.
+	fn on_open(&mut self, _out: &Outbound) {}
+
+	/// Called for every text, binary and pong message the peer sends.
+	fn on_message(&mut self, out: &Outbound, message: OwnedMessage);
+
+	/// Called exactly once when the connection is over, with the close frame
+	/// the peer sent if it sent one.
+	fn on_close(&mut self, _close: Option<CloseData>) {}
+
+	/// Called when accepting the connection or reading from it fails,
+	/// `on_close` follows right after.
+	fn on_error(&mut self, _error: WebSocketError) {}
+}
+
+/// Sends messages to the peer of a connection.
+///
+/// It can be cloned and moved to other threads, every clone writes to the
+/// same connection and a message is always sent as a whole.
+#[derive(Clone)]
+pub struct Outbound {
+	writer: SharedWriter<TcpStream>,
+}
+
+impl Outbound {
+	/// Send a message to the peer.
+	pub fn send<M>(&self, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		self.writer.send_message(message)
+	}
+
+	/// Start closing the connection by sending a close frame. `on_close` is
+	/// called once the peer answers.
+	pub fn close(&self, close: Option<CloseData>) -> WebSocketResult<()> {
+		if self.writer.is_closed() {
+			return Ok(());
+		}
+		self.send(&OwnedMessage::Close(close))
+	}
+
+	/// Drop the connection without a closing handshake.
+	pub fn shutdown(&self) -> io::Result<()> {
+		self.writer.shutdown_all()
+	}
+}
+
+/// Runs a sync `Server`, serving every connection with its own `Handler`.
+///
+/// Handshakes are read by a `HandshakePool`, accepted connections are served
+/// by a fixed pool of threads. Every connection keeps its thread until it is
+/// closed, connections beyond `threads` wait in line for a free one. Once
+/// `max_connections` are served or waiting, new clients are turned away with
+/// a `503 Service Unavailable`.
+pub struct Runtime<F> {
+	factory: Arc<F>,
+	threads: usize,
+	max_connections: usize,
+	handshake_workers: usize,
+}
+
+impl<F, H> Runtime<F>
+where
+	F: Fn(&mut Upgrade<TcpStream>) -> H + Send + Sync + 'static,
+	H: Handler,
+{
+	/// Create a runtime that builds the handler of every connection with
+	/// `factory`, which gets to look at the upgrade first, e.g. to take the
+	/// context the server's guard returned for it.
+	///
+	/// By default 64 connections are served at once on 64 threads.
+	pub fn new(factory: F) -> Self {
+		Runtime {
+			factory: Arc::new(factory),
+			threads: 64,
+			max_connections: 64,
+			handshake_workers: 4,
+		}
+	}
+
+	/// The number of threads serving connections. This also raises
+	/// `max_connections` if it is lower.
+	pub fn threads(mut self, threads: usize) -> Self {
+		self.threads = cmp::max(threads, 1);
+		self.max_connections = cmp::max(self.max_connections, self.threads);
+		self
+	}
+
+	/// The number of connections that may be open at once, whether they are
+	/// being served or waiting for a thread.
+	pub fn max_connections(mut self, max: usize) -> Self {
+		self.max_connections = cmp::max(max, 1);
+		self
+	}
+
+	/// The number of threads reading handshakes, see `HandshakePool`.
+	pub fn handshake_workers(mut self, workers: usize) -> Self {
+		self.handshake_workers = cmp::max(workers, 1);
+		self
+	}
+
+	/// Serve connections from `server` until accepting fails for good.
+	///
+	/// This blocks the calling thread, only errors starting the threads are
+	/// returned.
+	pub fn run(self, server: Server<NoTlsAcceptor>) -> io::Result<()> {
+		let upgrades = server.into_handshake_pool(self.handshake_workers)?;
+		let active = Arc::new(AtomicUsize::new(0));
+		let (queue_tx, queue_rx) = mpsc::channel::<Upgrade<TcpStream>>();
+		let queue_rx = Arc::new(Mutex::new(queue_rx));
+
+		for i in 0..self.threads {
+			let queue = queue_rx.clone();
+			let factory = self.factory.clone();
+			let active = active.clone();
+			thread::Builder::new()
+				.name(format!("websocket-connection-{}", i))
+				.spawn(move || loop {
+					let upgrade = match queue.lock() {
+						Ok(queue) => queue.recv(),
+						Err(_) => return,
+					};
+					let mut upgrade = match upgrade {
+						Ok(upgrade) => upgrade,
+						Err(_) => return,
+					};
+					let _slot = Slot(active.clone());
+					// a panicking handler only takes its own connection down
+					let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+						let handler = factory(&mut upgrade);
+						serve(upgrade, handler);
+					}));
+				})?;
+		}
+
+		// failed handshakes were already answered by the pool
+		for upgrade in upgrades.filter_map(Result::ok) {
+			if active.load(Ordering::SeqCst) >= self.max_connections {
+				let mut headers = HeaderMap::new();
+				headers.insert(CONNECTION, HeaderValue::from_static("close"));
+				let _ = upgrade.reject_with_status(StatusCode::SERVICE_UNAVAILABLE, headers, None);
+				continue;
+			}
+			active.fetch_add(1, Ordering::SeqCst);
+			if queue_tx.send(upgrade).is_err() {
+				break;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// A connection counted in `active`, until it is dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+fn serve<H: Handler>(upgrade: Upgrade<TcpStream>, mut handler: H) {
+	let split = upgrade.accept()
+	                   .map_err(|(_, e)| WebSocketError::from(e))
+	                   .and_then(|client| client.split().map_err(WebSocketError::from));
+	let (mut reader, writer) = match split {
+		Ok(split) => split,
+		Err(e) => {
+			handler.on_error(e);
+			handler.on_close(None);
+			return;
+		}
+	};
+	let out = Outbound { writer: writer.into_shared() };
+	handler.on_open(&out);
+
+	let mut close = None;
+	for message in reader.incoming_messages() {
+		match message {
+			Ok(OwnedMessage::Ping(data)) => {
+				let _ = out.send(&OwnedMessage::Pong(data));
+			}
+			Ok(OwnedMessage::Close(data)) => {
+				// only answered if we did not start the closing handshake
+				let _ = out.close(None);
+				close = data;
+				break;
+			}
+			Ok(message) => handler.on_message(&out, message),
+			Err(e) => {
+				handler.on_error(e);
+				break;
+			}
+		}
+	}
+
+	let _ = out.shutdown();
+	handler.on_close(close);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::mpsc::Sender;
+	use client::builder::ClientBuilder;
+
+	struct Echo(Sender<&'static str>);
+
+	impl Handler for Echo {
+		fn on_open(&mut self, _: &Outbound) {
+			self.0.send("open").unwrap();
+		}
+
+		fn on_message(&mut self, out: &Outbound, message: OwnedMessage) {
+			out.send(&message).unwrap();
+		}
+
+		fn on_close(&mut self, _: Option<CloseData>) {
+			self.0.send("close").unwrap();
+		}
+	}
+
+	#[test]
+	fn serves_handlers_up_to_the_connection_cap() {
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		let (events_tx, events) = mpsc::channel();
+		let events_tx = Mutex::new(events_tx);
+		thread::spawn(move || {
+			Runtime::new(move |_: &mut Upgrade<_>| Echo(events_tx.lock().unwrap().clone()))
+				.threads(1)
+				.max_connections(1)
+				.run(server)
+				.unwrap();
+		});
+
+		let mut client = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+		assert_eq!(events.recv().unwrap(), "open");
+		client.send_message(&OwnedMessage::Text("hi".to_string())).unwrap();
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Text("hi".to_string()));
+
+		// the only slot is taken
+		assert!(ClientBuilder::new(&url).unwrap().connect_insecure().is_err());
+
+		client.send_message(&OwnedMessage::Close(None)).unwrap();
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Close(None));
+		assert_eq!(events.recv().unwrap(), "close");
+	}
+
+	#[test]
+	fn panicking_handlers_give_their_slot_back() {
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		let (events_tx, events) = mpsc::channel();
+		let events_tx = Mutex::new(events_tx);
+		thread::spawn(move || {
+			Runtime::new(move |upgrade: &mut Upgrade<_>| {
+				if upgrade.request.subject.1.path() == "/panic" {
+					panic!("no handler for this one");
+				}
+				Echo(events_tx.lock().unwrap().clone())
+			}).threads(1)
+			  .max_connections(1)
+			  .run(server)
+			  .unwrap();
+		});
+
+		assert!(ClientBuilder::new(&format!("{}/panic", url)).unwrap().connect_insecure().is_err());
+
+		// the thread and the only slot are free again
+		let mut client = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+		assert_eq!(events.recv().unwrap(), "open");
+		client.send_message(&OwnedMessage::Text("hi".to_string())).unwrap();
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Text("hi".to_string()));
+	}
+}
@@ -0,0 +1,381 @@
+//! Run an async server by handing every connection to a handler.
+//!
+//! This does what `examples/async-server.rs` does by hand: every accepted
+//! connection gets its own task on the tokio runtime and its own `Handler`,
+//! which sees the messages the peer sends and answers through an `Outbound`.
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # extern crate tokio;
+//! # fn main() {
+//! use websocket::OwnedMessage;
+//! use websocket::async::Server;
+//! use websocket::server::service::{self, Handler, Outbound};
+//! use websocket::server::upgrade::async::Upgrade;
+//! use tokio::reactor::Handle;
+//!
+//! struct Echo;
+//!
+//! impl Handler for Echo {
+//!     fn on_message(&mut self, out: &Outbound, message: OwnedMessage) {
+//!         let _ = out.send(message);
+//!     }
+//! }
+//!
+//! let server = Server::bind("127.0.0.1:2794", &Handle::default()).unwrap();
+//! tokio::run(service::serve(server.incoming(), |_: &mut Upgrade<_>| Echo));
+//! # }
+//! ```
+//!
+//! Pings are answered and close frames are echoed for the handler, it only
+//! sees the other messages.
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{future, Future, Sink, Stream};
+use futures::future::{Either, Loop};
+use futures::stream::{SplitSink, SplitStream};
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::sync::oneshot;
+use tokio;
+use tokio::timer::Delay;
+
+use client::async::Client;
+use message::{CloseData, OwnedMessage};
+use result::WebSocketError;
+use server::async::Incoming;
+use server::upgrade::async::Upgrade;
+use stream::async::Stream as AsyncStream;
+
+/// The callbacks of a single connection served by `serve`.
+///
+/// A handler is created for every accepted connection, its callbacks are
+/// called one at a time on the connection's task, so they should not block.
+pub trait Handler {
+	/// Called once the handshake is done, before any message is read.
+	fn on_open(&mut self, _out: &Outbound) {}
+
+	/// Called for every text, binary and pong message the peer sends.
+	fn on_message(&mut self, out: &Outbound, message: OwnedMessage);
+
+	/// Called exactly once when the connection is over, with the close frame
+	/// the peer sent if it sent one.
+	fn on_close(&mut self, _close: Option<CloseData>) {}
+
+	/// Called when accepting the connection, reading from it or writing to it
+	/// fails, `on_close` follows once the connection is over.
+	fn on_error(&mut self, _error: WebSocketError) {}
+}
+
+/// How long a connection waits for the peer to answer the close frame it sent
+/// before it gives up on the peer and is over.
+pub const CLOSE_TIMEOUT_SECS: u64 = 5;
+
+/// Queues messages to be sent to the peer of a connection.
+///
+/// It can be cloned and moved to other tasks and threads, messages are sent
+/// in the order they were queued by the connection's task.
+#[derive(Clone)]
+pub struct Outbound {
+	queue: UnboundedSender<OwnedMessage>,
+	closing: Arc<AtomicBool>,
+}
+
+impl Outbound {
+	/// Queue a message for the peer. This fails once the connection is gone
+	/// or closing.
+	pub fn send(&self, message: OwnedMessage) -> Result<(), WebSocketError> {
+		if self.closing.load(Ordering::SeqCst) {
+			return Err(WebSocketError::IoError(io::ErrorKind::BrokenPipe.into()));
+		}
+		self.queue(message)
+	}
+
+	/// Start closing the connection by sending a close frame. `on_close` is
+	/// called once the peer answers, or after `CLOSE_TIMEOUT_SECS` if it does
+	/// not. Nothing can be sent after this.
+	pub fn close(&self, close: Option<CloseData>) -> Result<(), WebSocketError> {
+		if self.closing.swap(true, Ordering::SeqCst) {
+			return Ok(());
+		}
+		self.queue(OwnedMessage::Close(close))
+	}
+
+	fn queue(&self, message: OwnedMessage) -> Result<(), WebSocketError> {
+		self.queue
+		    .unbounded_send(message)
+		    .map_err(|_| WebSocketError::IoError(io::ErrorKind::BrokenPipe.into()))
+	}
+}
+
+/// Accept every upgrade of `incoming` and serve it with the handler `factory`
+/// builds for it, each connection on a task of its own. The factory gets to
+/// look at the upgrade first, e.g. to take the context the server's guard
+/// returned for it.
+///
+/// Connections are spawned with `tokio::spawn`, so the returned future must
+/// run on a tokio runtime. Failed handshakes were already answered by the
+/// server and are skipped, the future only ends if the listener does.
+pub fn serve<S, F, H>(incoming: Incoming<S>, factory: F) -> Box<Future<Item = (), Error = ()> + Send>
+where
+	S: AsyncStream + Send + 'static,
+	F: Fn(&mut Upgrade<S>) -> H + Send + 'static,
+	H: Handler + Send + 'static,
+{
+	let future = incoming.then(|upgrade| Ok::<_, ()>(upgrade.ok()))
+	                     .filter_map(|upgrade| upgrade)
+	                     .for_each(move |(mut upgrade, _)| {
+		let handler = factory(&mut upgrade);
+		tokio::spawn(connection(upgrade, handler));
+		Ok(())
+	});
+	Box::new(future)
+}
+
+/// Serve a single upgrade with `handler` until it is closed.
+pub fn connection<S, H>(upgrade: Upgrade<S>, mut handler: H) -> Box<Future<Item = (), Error = ()> + Send>
+where
+	S: AsyncStream + Send + 'static,
+	H: Handler + Send + 'static,
+{
+	let future = upgrade.accept().then(move |accepted| -> Box<Future<Item = (), Error = ()> + Send> {
+		let client = match accepted {
+			Ok((client, _)) => client,
+			Err(e) => {
+				handler.on_error(e);
+				handler.on_close(None);
+				return Box::new(future::ok(()));
+			}
+		};
+
+		let (sink, stream) = client.split();
+		let (queue_tx, queue_rx) = mpsc::unbounded();
+		let (stop_tx, stop_rx) = oneshot::channel();
+		let out = Outbound {
+			queue: queue_tx,
+			closing: Arc::new(AtomicBool::new(false)),
+		};
+		handler.on_open(&out);
+
+		let future = read(stream, handler, out, stop_rx)
+			.select2(write(sink, queue_rx))
+			.then(move |finished| -> Box<Future<Item = (), Error = ()> + Send> {
+				match finished {
+					// the peer closed, let the answer get out before we are done
+					Ok(Either::A(((mut handler, Some(close)), writer))) => {
+						Box::new(writer.then(move |written| {
+							if let Err(e) = written {
+								handler.on_error(e);
+							}
+							handler.on_close(close);
+							Ok(())
+						}))
+					}
+					// the connection broke, nothing more can be written
+					Ok(Either::A(((mut handler, None), _))) => {
+						handler.on_close(None);
+						Box::new(future::ok(()))
+					}
+					// we sent a close, wait for the peer's answer
+					Ok(Either::B(((), reader))) => {
+						Box::new(closing(reader, stop_tx).map(|(mut handler, close)| {
+							handler.on_close(close.unwrap_or(None));
+						}))
+					}
+					Err(Either::B((e, reader))) => {
+						Box::new(closing(reader, stop_tx).map(move |(mut handler, close)| {
+							handler.on_error(e);
+							handler.on_close(close.unwrap_or(None));
+						}))
+					}
+					Err(Either::A(((), _))) => Box::new(future::ok(())),
+				}
+			});
+		Box::new(future)
+	});
+	Box::new(future)
+}
+
+/// What `read` resolves to.
+type Reading<H> = Box<Future<Item = (H, Option<Option<CloseData>>), Error = ()> + Send>;
+
+/// Feed the peer's messages to `handler` until the connection is closed, or
+/// until `stop` fires.
+///
+/// Resolves to the handler along with the peer's close frame, or `None` if
+/// the connection ended without one.
+fn read<S, H>(
+	stream: SplitStream<Client<S>>,
+	handler: H,
+	out: Outbound,
+	stop: oneshot::Receiver<()>,
+) -> Reading<H>
+where
+	S: AsyncStream + Send + 'static,
+	H: Handler + Send + 'static,
+{
+	let future = future::loop_fn((stream, handler, stop), move |(stream, mut handler, stop)| {
+		let out = out.clone();
+		stream.into_future().select2(stop).then(move |next| match next {
+			Ok(Either::A(((Some(OwnedMessage::Ping(data)), stream), stop))) => {
+				let _ = out.send(OwnedMessage::Pong(data));
+				Ok(Loop::Continue((stream, handler, stop)))
+			}
+			Ok(Either::A(((Some(OwnedMessage::Close(close)), _), _))) => {
+				// only answered if we did not start the closing handshake
+				let _ = out.close(None);
+				Ok(Loop::Break((handler, Some(close))))
+			}
+			Ok(Either::A(((Some(message), stream), stop))) => {
+				handler.on_message(&out, message);
+				Ok(Loop::Continue((stream, handler, stop)))
+			}
+			Ok(Either::A(((None, _), _))) => Ok(Loop::Break((handler, None))),
+			Err(Either::A(((e, _), _))) => {
+				handler.on_error(e);
+				Ok(Loop::Break((handler, None)))
+			}
+			Ok(Either::B(_)) | Err(Either::B(_)) => Ok(Loop::Break((handler, None))),
+		})
+	});
+	Box::new(future)
+}
+
+/// Let `reader` wait for the peer to answer our close frame, and stop it if
+/// that takes longer than `CLOSE_TIMEOUT_SECS`.
+fn closing<H>(reader: Reading<H>, stop: oneshot::Sender<()>) -> Reading<H>
+where
+	H: Send + 'static,
+{
+	let deadline = Delay::new(Instant::now() + Duration::from_secs(CLOSE_TIMEOUT_SECS));
+	let future = reader.select2(deadline).then(move |finished| -> Reading<H> {
+		match finished {
+			Ok(Either::A((read, _))) => Box::new(future::ok(read)),
+			Err(Either::A(((), _))) => Box::new(future::err(())),
+			// a broken timer gives up just the same
+			Ok(Either::B((_, reader))) | Err(Either::B((_, reader))) => {
+				let _ = stop.send(());
+				reader
+			}
+		}
+	});
+	Box::new(future)
+}
+
+/// Send the queued messages to the peer, up to and including a close frame.
+fn write<S>(
+	sink: SplitSink<Client<S>>,
+	queue: UnboundedReceiver<OwnedMessage>,
+) -> Box<Future<Item = (), Error = WebSocketError> + Send>
+where
+	S: AsyncStream + Send + 'static,
+{
+	let future = future::loop_fn((sink, queue), |(sink, queue)| {
+		queue.into_future()
+		     .map_err(|_| WebSocketError::IoError(io::ErrorKind::BrokenPipe.into()))
+		     .and_then(|(message, queue)| match message {
+			Some(message) => {
+				let last = message.is_close();
+				Either::A(sink.send(message).map(move |sink| if last {
+					Loop::Break(())
+				} else {
+					Loop::Continue((sink, queue))
+				}))
+			}
+			None => Either::B(future::ok(Loop::Break(()))),
+		})
+	});
+	Box::new(future)
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+	use super::*;
+	use std::sync::mpsc::{channel, Sender};
+	use std::sync::Mutex;
+	use std::thread;
+	use client::builder::ClientBuilder;
+	use server::async::Server;
+	use tokio::reactor::Handle;
+
+	struct Echo(Sender<&'static str>);
+
+	impl Handler for Echo {
+		fn on_message(&mut self, out: &Outbound, message: OwnedMessage) {
+			out.send(message).unwrap();
+		}
+
+		fn on_close(&mut self, close: Option<CloseData>) {
+			assert!(close.is_none());
+			self.0.send("close").unwrap();
+		}
+	}
+
+	struct Leaver(Sender<&'static str>);
+
+	impl Handler for Leaver {
+		fn on_open(&mut self, out: &Outbound) {
+			out.close(None).unwrap();
+			if out.send(OwnedMessage::Text("late".to_string())).is_err() {
+				self.0.send("refused").unwrap();
+			}
+		}
+
+		fn on_message(&mut self, _: &Outbound, _: OwnedMessage) {}
+
+		fn on_close(&mut self, _: Option<CloseData>) {
+			self.0.send("close").unwrap();
+		}
+	}
+
+	/// Serve a server on a thread of its own, returning its URL.
+	fn spawn<F, H>(factory: F) -> String
+	where
+		F: Fn(&mut Upgrade<::tokio::net::TcpStream>) -> H + Send + 'static,
+		H: Handler + Send + 'static,
+	{
+		let (addr_tx, addr) = channel();
+		thread::spawn(move || {
+			tokio::run(future::lazy(move || {
+				let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+				addr_tx.send(server.listener.local_addr().unwrap()).unwrap();
+				serve(server.incoming(), factory)
+			}));
+		});
+		format!("ws://{}", addr.recv().unwrap())
+	}
+
+	#[test]
+	fn echoes_and_closes() {
+		let (events_tx, events) = channel();
+		let events_tx = Mutex::new(events_tx);
+		let url = spawn(move |_: &mut Upgrade<_>| Echo(events_tx.lock().unwrap().clone()));
+
+		let mut client = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+		client.send_message(&OwnedMessage::Ping(b"hey".to_vec())).unwrap();
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Pong(b"hey".to_vec()));
+		client.send_message(&OwnedMessage::Text("hi".to_string())).unwrap();
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Text("hi".to_string()));
+
+		client.send_message(&OwnedMessage::Close(None)).unwrap();
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Close(None));
+		assert_eq!(events.recv().unwrap(), "close");
+	}
+
+	#[test]
+	fn gives_up_on_an_unanswered_close() {
+		let (events_tx, events) = channel();
+		let events_tx = Mutex::new(events_tx);
+		let url = spawn(move |_: &mut Upgrade<_>| Leaver(events_tx.lock().unwrap().clone()));
+
+		let mut client = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Close(None));
+		assert_eq!(events.recv().unwrap(), "refused");
+
+		// the client never answers
+		let waited = Duration::from_secs(CLOSE_TIMEOUT_SECS + 5);
+		assert_eq!(events.recv_timeout(waited).unwrap(), "close");
+	}
+}
@@ -2,14 +2,22 @@
 use std::net::{SocketAddr, ToSocketAddrs, TcpListener, TcpStream};
 use std::io;
 use std::convert::Into;
+use std::time::Duration;
+
+use http::StatusCode;
 
 #[cfg(feature = "sync-ssl")]
 use native_tls::{TlsStream, TlsAcceptor};
 
-use codec::http::RequestHead;
+use stream::sync::{Stream, AsTcpStream};
 use server::{WsServer, OptionalTlsAcceptor, NoTlsAcceptor, InvalidConnection};
-use server::upgrade::sync::{Upgrade, IntoWs, Buffer};
-pub use server::upgrade::HyperIntoWsError;
+use server::upgrade::HandshakeStream;
+use server::fallback::{self, HttpHandler};
+use server::guard::{Guard, Rejection};
+use server::pool::{self, HandshakePool};
+use server::upgrade::DEFAULT_HANDSHAKE_TIMEOUT_SECS;
+use server::upgrade::sync::{Upgrade, IntoWs, Buffer, respond_to_error};
+pub use server::upgrade::{HyperIntoWsError, HandshakeConfig, OriginPolicy};
 
 #[cfg(feature = "async")]
 use tokio::reactor::Handle;
@@ -93,6 +101,10 @@ where
 		Ok(WsServer {
 			listener: AsyncTcpListener::from_std(self.listener, handle)?,
 			ssl_acceptor: self.ssl_acceptor,
+			handshake: self.handshake,
+			origin_policy: self.origin_policy,
+			http_fallback: self.http_fallback,
+			guard: self.guard,
 		})
 	}
 }
@@ -146,10 +158,17 @@ impl WsServer<TlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::bind(&addr)?,
 			ssl_acceptor: acceptor,
+			handshake: HandshakeConfig::default(),
+			origin_policy: None,
+			http_fallback: None,
+			guard: None,
 		})
 	}
 
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
+	///
+	/// The timeout of the server's `HandshakeConfig` bounds the TLS handshake
+	/// as well as the websocket one.
 	pub fn accept(&mut self) -> AcceptResult<TlsStream<TcpStream>> {
 		let stream = match self.listener.accept() {
 			Ok(s) => s.0,
@@ -163,29 +182,43 @@ impl WsServer<TlsAcceptor, TcpListener> {
 			}
 		};
 
-		let stream = match self.ssl_acceptor.accept(stream) {
-			Ok(s) => s,
-			Err(err) => {
-				return Err(InvalidConnection {
-					stream: None,
-					parsed: None,
-					buffer: None,
-					error: io::Error::new(io::ErrorKind::Other, err).into(),
-				})
-			}
-		};
+		tls_handshake(
+			&self.ssl_acceptor,
+			stream,
+			self.handshake,
+			self.origin_policy.as_ref(),
+			self.http_fallback.as_ref(),
+			self.guard.as_ref(),
+			false,
+		)
+	}
 
-		match stream.into_ws() {
-			Ok(u) => Ok(u),
-			Err((s, r, b, e)) => {
-				Err(InvalidConnection {
-					stream: Some(s),
-					parsed: r,
-					buffer: b,
-					error: e.into(),
-				})
-			}
-		}
+	/// Keep accepting connections on a thread of its own and leave the TLS and
+	/// websocket handshakes to `workers` threads, so a client that is slow to
+	/// send its request does not hold up the ones after it.
+	///
+	/// Every handshake is bounded by the server's `HandshakeConfig` timeout, or
+	/// by `DEFAULT_HANDSHAKE_TIMEOUT_SECS` if it was set to `None`.
+	pub fn into_handshake_pool(
+		self,
+		workers: usize,
+	) -> io::Result<HandshakePool<TlsStream<TcpStream>>> {
+		let config = pool_config(self.handshake);
+		let acceptor = self.ssl_acceptor;
+		let origin_policy = self.origin_policy;
+		let http_fallback = self.http_fallback;
+		let guard = self.guard;
+		pool::spawn(self.listener, workers, move |stream| {
+			tls_handshake(
+				&acceptor,
+				stream,
+				config,
+				origin_policy.as_ref(),
+				http_fallback.as_ref(),
+				guard.as_ref(),
+				true,
+			)
+		})
 	}
 }
 
@@ -229,6 +262,10 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::bind(&addr)?,
 			ssl_acceptor: NoTlsAcceptor,
+			handshake: HandshakeConfig::default(),
+			origin_policy: None,
+			http_fallback: None,
+			guard: None,
 		})
 	}
 
@@ -246,17 +283,14 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 			}
 		};
 
-		match stream.into_ws() {
-			Ok(u) => Ok(u),
-			Err((s, r, b, e)) => {
-				Err(InvalidConnection {
-					stream: Some(s),
-					parsed: r,
-					buffer: b,
-					error: e.into(),
-				})
-			}
-		}
+		handshake(
+			stream,
+			self.handshake,
+			self.origin_policy.as_ref(),
+			self.http_fallback.as_ref(),
+			self.guard.as_ref(),
+			false,
+		)
 	}
 
 	/// Create a new independently owned handle to the underlying socket.
@@ -265,6 +299,27 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: inner,
 			ssl_acceptor: self.ssl_acceptor.clone(),
+			handshake: self.handshake,
+			origin_policy: self.origin_policy.clone(),
+			http_fallback: self.http_fallback.clone(),
+			guard: self.guard.clone(),
+		})
+	}
+
+	/// Keep accepting connections on a thread of its own and leave the
+	/// handshakes to `workers` threads, so a client that is slow to send its
+	/// request does not hold up the ones after it.
+	///
+	/// Every handshake is bounded by the server's `HandshakeConfig` timeout, or
+	/// by `DEFAULT_HANDSHAKE_TIMEOUT_SECS` if it was set to `None`.
+	pub fn into_handshake_pool(self, workers: usize) -> io::Result<HandshakePool<TcpStream>> {
+		let config = pool_config(self.handshake);
+		let origin_policy = self.origin_policy;
+		let http_fallback = self.http_fallback;
+		let guard = self.guard;
+		pool::spawn(self.listener, workers, move |stream| {
+			let guard = guard.as_ref();
+			handshake(stream, config, origin_policy.as_ref(), http_fallback.as_ref(), guard, true)
 		})
 	}
 }
@@ -277,6 +332,163 @@ impl Iterator for WsServer<NoTlsAcceptor, TcpListener> {
 	}
 }
 
+/// The TLS and websocket handshakes of a fresh connection, both bounded by the
+/// config's timeout. The stream gets back the read timeout it had before.
+#[cfg(feature = "sync-ssl")]
+fn tls_handshake(
+	acceptor: &TlsAcceptor,
+	stream: TcpStream,
+	config: HandshakeConfig,
+	origin: Option<&OriginPolicy>,
+	http_fallback: Option<&HttpHandler>,
+	guard: Option<&Guard>,
+	pooled: bool,
+) -> AcceptResult<TlsStream<TcpStream>> {
+	let previous = stream.read_timeout().unwrap_or(None);
+	if config.timeout.is_some() {
+		let _ = stream.set_read_timeout(config.timeout);
+	}
+	let stream = match acceptor.accept(stream) {
+		Ok(s) => s,
+		Err(err) => {
+			return Err(InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error: io::Error::new(io::ErrorKind::Other, err).into(),
+			})
+		}
+	};
+
+	let result = handshake(stream, config, origin, http_fallback, guard, pooled);
+	let stream = match result {
+		Ok(ref upgrade) => Some(&upgrade.stream),
+		Err(ref invalid) => invalid.stream.as_ref(),
+	};
+	if let Some(stream) = stream {
+		let _ = stream.as_tcp().set_read_timeout(previous);
+	}
+	result
+}
+
+fn pool_config(mut config: HandshakeConfig) -> HandshakeConfig {
+	if config.timeout.is_none() {
+		config.timeout = Some(Duration::from_secs(DEFAULT_HANDSHAKE_TIMEOUT_SECS));
+	}
+	config
+}
+
+fn handshake<S>(
+	stream: S,
+	config: HandshakeConfig,
+	origin: Option<&OriginPolicy>,
+	http_fallback: Option<&HttpHandler>,
+	guard: Option<&Guard>,
+	pooled: bool,
+) -> AcceptResult<S>
+where
+	S: Stream + AsTcpStream + Send,
+{
+	let (mut s, r, b, e) = match HandshakeStream(stream, config).into_ws() {
+		Ok(upgrade) => return check_origin(upgrade, origin).and_then(|u| check_guard(u, guard, pooled)),
+		Err(failed) => failed,
+	};
+
+	let handler = http_fallback.filter(|_| fallback::is_plain_http(&e));
+	let (handler, request) = match (handler, r) {
+		(Some(handler), Some(request)) => (handler, request),
+		(_, r) => {
+			// the peer is getting an error either way, so a failed answer is not interesting
+			let _ = respond_to_error(&mut s, &e);
+			return Err(InvalidConnection {
+				stream: Some(s),
+				parsed: r,
+				buffer: b,
+				error: e,
+			});
+		}
+	};
+
+	// answer once and let the connection go, waiting for another request here
+	// would hold up every later accept
+	let error = match fallback::serve(handler, request) {
+		Ok(response) => match s.write_all(&response) {
+			Ok(()) => HyperIntoWsError::AnsweredHttp,
+			Err(e) => e.into(),
+		},
+		Err(e) => e,
+	};
+	Err(InvalidConnection {
+		stream: Some(s),
+		parsed: None,
+		buffer: b,
+		error: error,
+	})
+}
+
+fn check_origin<S>(upgrade: Upgrade<S>, origin: Option<&OriginPolicy>) -> AcceptResult<S>
+where
+	S: Stream + AsTcpStream + Send,
+{
+	match origin {
+		Some(policy) => {
+			upgrade.enforce_origin(policy).map_err(|(s, e)| {
+				InvalidConnection {
+					stream: Some(s),
+					parsed: None,
+					buffer: None,
+					error: e,
+				}
+			})
+		}
+		None => Ok(upgrade),
+	}
+}
+
+/// Run `guard` on an upgrade, keeping the context it returns on the upgrade or
+/// answering the request with its rejection. Only a `pooled` handshake waits
+/// for a guard made with `Guard::new_async`.
+fn check_guard<S>(mut upgrade: Upgrade<S>, guard: Option<&Guard>, pooled: bool) -> AcceptResult<S>
+where
+	S: Stream + AsTcpStream + Send,
+{
+	let guard = match guard {
+		Some(guard) => guard,
+		None => return Ok(upgrade),
+	};
+	if guard.is_async() && !pooled {
+		let error = io::Error::new(io::ErrorKind::Other, "an async guard needs a handshake pool");
+		return refuse(upgrade, Rejection::new(StatusCode::INTERNAL_SERVER_ERROR), error.into());
+	}
+
+	match guard.check(&upgrade.request) {
+		Ok(context) => {
+			upgrade.set_context(context);
+			Ok(upgrade)
+		}
+		Err(rejection) => {
+			let error = HyperIntoWsError::Rejected(rejection.status);
+			refuse(upgrade, rejection, error)
+		}
+	}
+}
+
+fn refuse<S>(upgrade: Upgrade<S>, rejection: Rejection, error: HyperIntoWsError) -> AcceptResult<S>
+where
+	S: Stream + AsTcpStream + Send,
+{
+	let (stream, error) = match upgrade.send_rejection(rejection) {
+		Ok(stream) => (stream, error),
+		Err((stream, e)) => (stream, e.into()),
+	};
+	Err(InvalidConnection {
+		stream: Some(stream),
+		parsed: None,
+		buffer: None,
+		error: error,
+	})
+}
+
 mod tests {
 	#[test]
 	// test the set_nonblocking() method for Server<NoSslAcceptor>.
@@ -308,4 +520,145 @@ mod tests {
 		}
 
 	}
+
+	#[test]
+	fn accept_times_out_by_default() {
+		use super::*;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let timeout = Duration::from_secs(DEFAULT_HANDSHAKE_TIMEOUT_SECS);
+		assert_eq!(server.handshake.timeout, Some(timeout));
+		// the default is too long to sit through here
+		server.handshake.timeout = Some(Duration::from_millis(100));
+
+		let _silent = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+		match server.accept() {
+			Err(InvalidConnection { error: HyperIntoWsError::Timeout, .. }) => (),
+			_ => panic!("expected the handshake to time out"),
+		}
+	}
+
+	#[test]
+	fn answers_plain_http_once_and_moves_on() {
+		use super::*;
+		use std::io::{Read, Write};
+		use std::sync::Arc;
+		use std::thread;
+		use http::{Request, Response};
+		use client::builder::ClientBuilder;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		server.http_fallback = Some(Arc::new(|_: Request<()>| Response::new(b"ok".to_vec())));
+
+		let peer = thread::spawn(move || {
+			// an HTTP/1.1 client that would keep the connection alive
+			let mut http = TcpStream::connect(addr).unwrap();
+			http.write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+			let mut response = String::new();
+			http.read_to_string(&mut response).unwrap();
+
+			ClientBuilder::new(&format!("ws://{}", addr)).unwrap().connect_insecure().unwrap();
+			response
+		});
+
+		match server.accept() {
+			Err(InvalidConnection { error: HyperIntoWsError::AnsweredHttp, .. }) => (),
+			_ => panic!("expected the request to be answered"),
+		}
+		server.accept().ok().unwrap().accept().unwrap();
+
+		let response = peer.join().unwrap();
+		assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(response.contains("connection: close\r\n"));
+		assert!(response.ends_with("\r\n\r\nok"));
+	}
+
+	#[test]
+	fn guard_rejects_or_keeps_its_context() {
+		use super::*;
+		use std::thread;
+		use http::StatusCode;
+		use http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+		use client::builder::ClientBuilder;
+		use server::guard::Rejection;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		server.guard = Some(Guard::new(|request| match request.headers.get(AUTHORIZATION) {
+			Some(token) => Ok(token.to_str().unwrap().to_string()),
+			None => Err(Rejection::new(StatusCode::UNAUTHORIZED)),
+		}));
+
+		let peer = thread::spawn(move || {
+			let anonymous = ClientBuilder::new(&url).unwrap().connect_insecure();
+			let mut headers = HeaderMap::new();
+			headers.insert(AUTHORIZATION, HeaderValue::from_static("alice"));
+			let known = ClientBuilder::new(&url).unwrap().custom_headers(headers).connect_insecure();
+			(anonymous.is_err(), known.is_ok())
+		});
+
+		match server.accept() {
+			Err(InvalidConnection { error: HyperIntoWsError::Rejected(status), .. }) => {
+				assert_eq!(status, StatusCode::UNAUTHORIZED)
+			}
+			_ => panic!("expected the request to be rejected"),
+		}
+		let client = server.accept().ok().unwrap().accept_with_context().unwrap();
+		assert_eq!(client.context::<String>().map(|s| &s[..]), Some("alice"));
+		assert_eq!(peer.join().unwrap(), (true, true));
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_guards_only_run_on_the_handshake_pool() {
+		use super::*;
+		use std::thread;
+		use futures::future;
+		use codec::http::RequestHead;
+		use client::builder::ClientBuilder;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		server.guard = Some(Guard::new_async(|_: &RequestHead| future::ok::<_, Rejection>("alice")));
+
+		let refused = {
+			let url = url.clone();
+			thread::spawn(move || ClientBuilder::new(&url).unwrap().connect_insecure().is_err())
+		};
+		match server.accept() {
+			Err(InvalidConnection { error: HyperIntoWsError::Io(_), .. }) => (),
+			_ => panic!("expected the request to be refused"),
+		}
+		assert!(refused.join().unwrap());
+
+		let pool = server.into_handshake_pool(1).unwrap();
+		let peer = thread::spawn(move || ClientBuilder::new(&url).unwrap().connect_insecure().is_ok());
+		let mut upgrade = pool.accept().unwrap().ok().unwrap();
+		assert_eq!(upgrade.context::<&str>(), Some(&"alice"));
+		assert!(upgrade.take_context().is_some());
+		upgrade.accept().unwrap();
+		assert!(peer.join().unwrap());
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn tls_handshake_is_bounded_by_the_timeout() {
+		use super::*;
+		use std::sync::mpsc::channel;
+		use std::thread;
+		use native_tls::Pkcs12;
+
+		let identity = Pkcs12::from_der(include_bytes!("../../fixtures/localhost.p12"), "websocket").unwrap();
+		let acceptor = TlsAcceptor::builder(identity).unwrap().build().unwrap();
+		let mut server = Server::bind_secure("127.0.0.1:0", acceptor).unwrap();
+		server.handshake.timeout = Some(Duration::from_millis(100));
+
+		// connects but never starts the TLS handshake
+		let _silent = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+
+		let (failed_tx, failed) = channel();
+		thread::spawn(move || failed_tx.send(server.accept().is_err()).unwrap());
+		assert!(failed.recv_timeout(Duration::from_secs(10)).unwrap());
+	}
 }
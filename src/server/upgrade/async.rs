@@ -5,19 +5,26 @@
 //! an intermediate struct called `Upgrade` and the `Upgrade` struct itself.
 //! The `Upgrade` struct is used to inspect details of the websocket connection
 //! (e.g. what protocols it wants to use) and decide whether to accept or reject it.
-use super::{HyperIntoWsError, WsUpgrade, validate};
+use super::{HyperIntoWsError, WsUpgrade, HandshakeConfig, HandshakeStream, OriginPolicy};
+use super::{closing_headers, validate};
 use std::io::{self, ErrorKind};
+use std::time::Instant;
+use tokio::timer::Delay;
 use tokio_codec::{Framed, FramedParts, Decoder};
-use http::header::HeaderMap;
+use http::header::{HeaderMap, HeaderValue, CONTENT_LENGTH};
 use http::StatusCode;
 use stream::async::Stream;
-use futures::{Sink, Future};
+use futures::{Async, Poll, Sink, Future};
+use futures::future;
 use futures::Stream as StreamTrait;
 use futures::sink::Send;
-use codec::http::{RequestHead, HttpServerCodec};
+use codec::http::{RequestHead, HttpServerCodec, Limited};
 use codec::ws::{MessageCodec, Context};
 use bytes::BytesMut;
-use client::async::ClientNew;
+use client::async::{Client, ClientNew};
+use result::WebSocketError;
+use server::guard::{AnyContext, Rejection, WithContext};
+use tokio_io::io::write_all;
 
 use codec::http::MessageHead;
 
@@ -85,9 +92,35 @@ where
 		self.internal_accept(Some(custom_headers))
 	}
 
+	/// Asynchronously accept the handshake, keeping the context the server's
+	/// guard returned next to the client.
+	pub fn accept_with_context(
+		mut self,
+	) -> Box<Future<Item = (WithContext<Client<S>, Option<AnyContext>>, HeaderMap), Error = WebSocketError> + ::std::marker::Send> {
+		let context = self.context.take();
+		let future = self.internal_accept(None).map(move |(client, headers)| {
+			let client = WithContext {
+				client: client,
+				context: context,
+			};
+			(client, headers)
+		});
+		Box::new(future)
+	}
+
+	pub(crate) fn send_rejection(
+		self,
+		rejection: Rejection,
+	) -> Box<Future<Item = S, Error = io::Error> + ::std::marker::Send> {
+		let Rejection { status, headers, body } = rejection;
+		let future = self.reject_with_status(status, headers, Some(&body[..]))
+		                 .map(|framed| framed.into_inner());
+		Box::new(future)
+	}
+
 	fn internal_accept(mut self, custom_headers: Option<HeaderMap>) -> ClientNew<S> {
 		let status = self.prepare_headers(custom_headers);
-		let WsUpgrade { headers, stream, request, buffer } = self;
+		let WsUpgrade { headers, stream, request, buffer, .. } = self;
 		let mut parts = FramedParts::new(stream, HttpServerCodec);
 		parts.read_buf = buffer;
 
@@ -115,7 +148,7 @@ where
 	/// into it's original stream. The stream being returned is framed with the
 	/// `HttpServerCodec` since that was used to send the rejection message.
 	pub fn reject(self) -> Send<Framed<S, HttpServerCodec>> {
-		self.internal_reject(None)
+		self.internal_reject(None, StatusCode::BAD_REQUEST)
 	}
 
 	/// Asynchronously send a rejection message with custom headers and
@@ -123,19 +156,83 @@ where
 	///  The stream being returned is framed with the
 	/// `HttpServerCodec` since that was used to send the rejection message.
 	pub fn reject_with(self, headers: HeaderMap) -> Send<Framed<S, HttpServerCodec>> {
-		self.internal_reject(Some(headers))
+		self.internal_reject(Some(headers), StatusCode::BAD_REQUEST)
+	}
+
+	/// Asynchronously send a rejection message with any status, extra headers
+	/// and an optional body and deconstruct `self` into it's original stream.
+	/// The `Content-Length` header is set to fit the body.
+	/// The stream being returned is framed with the
+	/// `HttpServerCodec` since that was used to send the rejection message.
+	pub fn reject_with_status(
+		mut self,
+		status: StatusCode,
+		headers: HeaderMap,
+		body: Option<&[u8]>,
+	) -> Box<Future<Item = Framed<S, HttpServerCodec>, Error = io::Error> + ::std::marker::Send> {
+		self.headers.extend(headers.into_iter());
+		let WsUpgrade { headers, stream, request, buffer, .. } = self;
+
+		let mut codec = HttpServerCodec;
+		let mut response = BytesMut::new();
+		let head = MessageHead {
+			version: request.version,
+			subject: status,
+			headers: headers,
+		};
+		if let Err(e) = codec.encode_with_body(head, body.unwrap_or(&[]), &mut response) {
+			return Box::new(future::err(e));
+		}
+
+		let future = write_all(stream, response).map(move |(stream, _)| {
+			let mut parts = FramedParts::new(stream, codec);
+			parts.read_buf = buffer;
+			Framed::from_parts(parts)
+		});
+		Box::new(future)
 	}
 
-	fn internal_reject(mut self, headers: Option<HeaderMap>) -> Send<Framed<S, HttpServerCodec>> {
+	/// Asynchronously reject the client's request with a `403 Forbidden` if
+	/// its origin is not allowed by `policy`, otherwise resolve to the upgrade
+	/// untouched.
+	///
+	/// On a rejection the future fails with the stream (if the answer could be
+	/// sent) and `HyperIntoWsError::OriginNotAllowed` or the IO error.
+	pub fn enforce_origin(
+		self,
+		policy: &OriginPolicy,
+	) -> Box<Future<Item = Self, Error = (Option<S>, HyperIntoWsError)> + ::std::marker::Send> {
+		if self.origin_allowed(policy) {
+			return Box::new(future::ok(self));
+		}
+		let future = self.internal_reject(Some(closing_headers()), StatusCode::FORBIDDEN)
+		                 .then(|sent| match sent {
+			Ok(framed) => {
+				Err::<Self, _>((Some(framed.into_inner()), HyperIntoWsError::OriginNotAllowed))
+			}
+			Err(e) => Err((None, e.into())),
+		});
+		Box::new(future)
+	}
+
+	pub(crate) fn internal_reject(
+		mut self,
+		headers: Option<HeaderMap>,
+		status: StatusCode,
+	) -> Send<Framed<S, HttpServerCodec>> {
 		if let Some(custom) = headers {
 			self.headers.extend(custom.into_iter());
 		}
+		// without a length the peer would wait for the connection to close
+		if !self.headers.contains_key(CONTENT_LENGTH) {
+			self.headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+		}
 		let mut parts = FramedParts::new(self.stream, HttpServerCodec);
 		parts.read_buf = self.buffer;
 		let duplex = Framed::from_parts(parts);
 		duplex.send(MessageHead {
 			version: self.request.version,
-			subject: StatusCode::BAD_REQUEST,
+			subject: status,
 			headers: self.headers,
 		})
 	}
@@ -217,25 +314,36 @@ where
 	fn into_ws(
 		self,
 	) -> Box<Future<Item = Upgrade<Self::Stream>, Error = Self::Error> + ::std::marker::Send> {
-		let future = HttpServerCodec.framed(self)
-		                 .into_future()
-		                 .map_err(|(e, s)| {
-			let FramedParts { io, read_buf, .. } = s.into_parts();
-			(io, None, read_buf, e.into())
-		})
-		                 .and_then(|(m, s)| {
-			let FramedParts { io, read_buf, .. } = s.into_parts();
-			if let Some(msg) = m {
-				match validate(&msg.subject.0, &msg.version, &msg.headers) {
-					Ok(()) => Ok((msg, io, read_buf)),
-					Err(e) => Err((io, None, read_buf, e)),
-				}
-			} else {
-				let err = HyperIntoWsError::Io(io::Error::new(
-					ErrorKind::ConnectionReset,
-					"Connection dropped before handshake could be read",
-				));
-				Err((io, None, read_buf, err))
+		// like the sync version, a bare stream waits for its request as long as it takes
+		let config = HandshakeConfig {
+			timeout: None,
+			..HandshakeConfig::default()
+		};
+		HandshakeStream(self, config).into_ws()
+	}
+}
+
+/// Reads the handshake with the config's byte limits and timeout, the stream
+/// is handed back in the error if the timeout fires before a request was read.
+impl<S> IntoWs for HandshakeStream<S>
+where
+	S: Stream + ::std::marker::Send + 'static,
+{
+	type Stream = S;
+	type Error = (S, Option<RequestHead>, BytesMut, HyperIntoWsError);
+
+	fn into_ws(
+		self,
+	) -> Box<Future<Item = Upgrade<Self::Stream>, Error = Self::Error> + ::std::marker::Send> {
+		let HandshakeStream(stream, config) = self;
+		let future = ReadRequest {
+			framed: Some(config.codec().framed(stream)),
+			delay: config.timeout.map(|t| Delay::new(Instant::now() + t)),
+		}
+		                 .and_then(|(m, io, read_buf)| {
+			match validate(&m.subject.0, &m.version, &m.headers) {
+				Ok(()) => Ok((m, io, read_buf)),
+				Err(e) => Err((io, None, read_buf, e)),
 			}
 		})
 		                 .map(|(m, stream, buffer)| {
@@ -244,8 +352,109 @@ where
 				stream: stream,
 				request: m,
 				buffer: buffer,
+				context: None,
 			}
 		});
 		Box::new(future)
 	}
 }
+
+/// Reads a single request head off of a stream, giving up once `delay` fires.
+struct ReadRequest<S> {
+	framed: Option<Framed<S, Limited<HttpServerCodec>>>,
+	delay: Option<Delay>,
+}
+
+impl<S> Future for ReadRequest<S>
+where
+	S: Stream,
+{
+	type Item = (RequestHead, S, BytesMut);
+	type Error = (S, Option<RequestHead>, BytesMut, HyperIntoWsError);
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let polled = self.framed
+		                 .as_mut()
+		                 .expect("cannot poll ReadRequest twice")
+		                 .poll();
+
+		let error = match polled {
+			Ok(Async::Ready(Some(msg))) => {
+				let FramedParts { io, read_buf, .. } = self.framed.take().unwrap().into_parts();
+				return Ok(Async::Ready((msg, io, read_buf)));
+			}
+			Ok(Async::Ready(None)) => {
+				HyperIntoWsError::Io(io::Error::new(
+					ErrorKind::ConnectionReset,
+					"Connection dropped before handshake could be read",
+				))
+			}
+			Ok(Async::NotReady) => {
+				match self.delay.as_mut().map(|d| d.poll()) {
+					None | Some(Ok(Async::NotReady)) => return Ok(Async::NotReady),
+					Some(Ok(Async::Ready(()))) => HyperIntoWsError::Timeout,
+					Some(Err(e)) => HyperIntoWsError::Io(io::Error::new(ErrorKind::Other, e)),
+				}
+			}
+			Err(e) => e.into(),
+		};
+
+		let FramedParts { io, read_buf, .. } = self.framed.take().unwrap().into_parts();
+		Err((io, None, read_buf, error))
+	}
+}
+
+/// Answer a request that failed to be read with the status the error calls for
+/// (e.g. 408 on a timeout), see `HyperIntoWsError::response`.
+///
+/// The future resolves to the stream once the answer has been sent, or straight
+/// away if the error does not call for one.
+pub fn respond_to_error<S>(
+	stream: S,
+	error: &HyperIntoWsError,
+) -> Box<Future<Item = S, Error = io::Error> + ::std::marker::Send>
+where
+	S: Stream + ::std::marker::Send + 'static,
+{
+	match error.response() {
+		Some(response) => {
+			let future = HttpServerCodec
+				.framed(stream)
+				.send(response)
+				.map(|framed| framed.into_inner());
+			Box::new(future)
+		}
+		None => Box::new(future::ok(stream)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+	use http::{Method, Version};
+	use codec::http::RequestLine;
+
+	#[test]
+	fn reject_with_status_sends_the_body() {
+		let upgrade = WsUpgrade {
+			headers: HeaderMap::new(),
+			stream: Cursor::new(Vec::new()),
+			request: RequestHead {
+				version: Version::HTTP_11,
+				subject: RequestLine(Method::GET, "/".parse().unwrap()),
+				headers: HeaderMap::new(),
+			},
+			buffer: BytesMut::new(),
+			context: None,
+		};
+
+		let framed = upgrade.reject_with_status(StatusCode::UNAUTHORIZED, HeaderMap::new(), Some(b"{}"))
+		                    .wait()
+		                    .unwrap();
+		let sent = String::from_utf8(framed.into_inner().into_inner()).unwrap();
+		assert!(sent.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+		assert!(sent.contains("content-length: 2\r\n"));
+		assert!(sent.ends_with("\r\n\r\n{}"));
+	}
+}
@@ -1,10 +1,13 @@
 //! Allows you to take an existing request or stream of data and convert it into a
 //! WebSocket client.
+use std::any::Any;
+use std::convert::TryFrom;
 use std::error::Error;
 use std::io;
 use std::iter::Iterator;
 use std::fmt::{self, Formatter, Display};
-use std::str::{self, FromStr};
+use std::str::FromStr;
+use std::time::Duration;
 use stream::Stream;
 
 use unicase::Ascii;
@@ -18,11 +21,13 @@ use http::{self, Version};
 use httparse;
 
 use codec;
-use codec::http::RequestHead;
-use header::{WebSocketAccept, WebSocketKey, WebSocketVersion};
+use codec::http::{RequestHead, ResponseHead, HttpLimits, HttpServerCodec, Limited, MAX_REQUEST_LINE,
+                  MAX_HEAD_SIZE};
+use header::{HeaderError, WebSocketAccept, WebSocketKey, WebSocketVersion};
 use header::connection::{Connection, ConnectionOption};
 use header::upgrade::{Protocol, ProtocolName, Upgrade};
 use header::sec_websocket_extensions::Extension;
+use server::guard::AnyContext;
 
 #[cfg(feature = "async")]
 pub mod async;
@@ -30,6 +35,55 @@ pub mod async;
 #[cfg(feature = "sync")]
 pub mod sync;
 
+pub mod origin;
+pub use self::origin::OriginPolicy;
+
+/// How long a server waits for the handshake request of a new connection
+/// unless its `HandshakeConfig` says otherwise, in seconds.
+pub const DEFAULT_HANDSHAKE_TIMEOUT_SECS: u64 = 30;
+
+/// Limits on how a server reads the handshake request of an incoming connection.
+///
+/// A peer that trickles its request in byte by byte (or never finishes it) can
+/// otherwise hold on to a server for as long as it likes, so every read of an
+/// untrusted handshake should be bounded.
+#[derive(Clone, Copy, Debug)]
+pub struct HandshakeConfig {
+	/// How long the whole request head may take to arrive, `None` waits forever.
+	/// Defaults to `DEFAULT_HANDSHAKE_TIMEOUT_SECS`.
+	pub timeout: Option<Duration>,
+	/// The maximum length of the request line in bytes.
+	pub max_request_line: usize,
+	/// The maximum size of the request line plus all headers in bytes.
+	pub max_head_size: usize,
+}
+
+impl HandshakeConfig {
+	/// Create an `HttpServerCodec` that enforces this config's byte limits.
+	pub fn codec(&self) -> Limited<HttpServerCodec> {
+		Limited::new(HttpServerCodec, HttpLimits {
+			max_request_line: self.max_request_line,
+			max_head_size: self.max_head_size,
+		})
+	}
+}
+
+impl Default for HandshakeConfig {
+	fn default() -> Self {
+		HandshakeConfig {
+			timeout: Some(Duration::from_secs(DEFAULT_HANDSHAKE_TIMEOUT_SECS)),
+			max_request_line: MAX_REQUEST_LINE,
+			max_head_size: MAX_HEAD_SIZE,
+		}
+	}
+}
+
+/// A stream paired with the limits its handshake should be read with.
+///
+/// Calling `into_ws` on this instead of the bare stream enforces the byte limits
+/// and (for streams that support it) the timeout of the `HandshakeConfig`.
+pub struct HandshakeStream<S>(pub S, pub HandshakeConfig);
+
 /// Intermediate representation of a half created websocket session.
 /// Should be used to examine the client's handshake
 /// accept the protocols requested, route the path, etc.
@@ -53,6 +107,7 @@ where
 	pub request: RequestHead,
 	/// Some buffered data from the stream, if it exists.
 	pub buffer: B,
+	context: Option<AnyContext>,
 }
 
 impl<S, B> WsUpgrade<S, B>
@@ -60,6 +115,22 @@ where
 	S: Stream + Send,
 	B: Send,
 {
+	/// What the server's guard returned for this request, if the server has a
+	/// guard and its context is a `T`.
+	pub fn context<T: Any>(&self) -> Option<&T> {
+		self.context.as_ref().and_then(|context| context.downcast_ref())
+	}
+
+	/// Take what the server's guard returned for this request out of the
+	/// upgrade, `None` if the server has no guard.
+	pub fn take_context(&mut self) -> Option<AnyContext> {
+		self.context.take()
+	}
+
+	pub(crate) fn set_context(&mut self, context: AnyContext) {
+		self.context = Some(context);
+	}
+
 	/// Select a protocol to use in the handshake response.
 	pub fn use_protocols(mut self, protocols: Vec<&str>) -> Self {
 		self.headers.insert(
@@ -69,6 +140,55 @@ where
 		self
 	}
 
+	/// Choose the subprotocol to speak and use it in the handshake response.
+	///
+	/// `supported` lists the server's protocols in order of preference, the
+	/// first one the client also offered is picked. If the client offered none
+	/// of them the upgrade is handed back in `Err`, to be rejected or accepted
+	/// without a protocol.
+	///
+	/// ```rust,no_run
+	/// # use websocket::sync::Server;
+	/// # let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// # let upgrade = server.accept().ok().unwrap();
+	/// match upgrade.negotiate_protocol(&["chat.v2", "chat.v1"]) {
+	///     Ok(upgrade) => {
+	///         let client = upgrade.accept().unwrap();
+	///         println!("speaking {:?}", client.protocol());
+	///     }
+	///     Err(upgrade) => {
+	///         upgrade.reject().unwrap();
+	///     }
+	/// }
+	/// ```
+	pub fn negotiate_protocol(self, supported: &[&str]) -> Result<Self, Self> {
+		self.negotiate_protocol_with(|offered| {
+			supported.iter()
+			         .find(|p| offered.contains(*p))
+			         .map(|p| p.to_string())
+		})
+	}
+
+	/// Like `negotiate_protocol` but `select` picks the protocol out of the
+	/// ones the client offered. Returning a protocol that was not offered
+	/// counts as no match, a client must never be answered with one.
+	pub fn negotiate_protocol_with<F>(mut self, select: F) -> Result<Self, Self>
+	where
+		F: FnOnce(&[&str]) -> Option<String>,
+	{
+		let chosen = {
+			let offered = self.protocols();
+			select(&offered).filter(|p| offered.contains(&p.as_str()))
+		};
+		match chosen.and_then(|p| HeaderValue::from_str(&p).ok()) {
+			Some(protocol) => {
+				self.headers.insert(SEC_WEBSOCKET_PROTOCOL, protocol);
+				Ok(self)
+			}
+			None => Err(self),
+		}
+	}
+
 	/// Select multiple extensions to use in the connection
 	pub fn use_extensions<I>(mut self, extensions: I) -> Self
 	where
@@ -93,10 +213,9 @@ where
 		self.request
 		    .headers
 		    .get(SEC_WEBSOCKET_PROTOCOL)
+		    .and_then(|e| e.to_str().ok())
 		    .map(|e| {
-			str::from_utf8(e.as_bytes())
-				.unwrap()
-				.split(',')
+			e.split(',')
 				.filter_map(|x| match x.trim() {
 					"" => None,
 					y => Some(y),
@@ -111,10 +230,9 @@ where
 		self.request
 		    .headers
 		    .get(SEC_WEBSOCKET_EXTENSIONS)
+		    .and_then(|e| e.to_str().ok())
 		    .map(|e| {
-			str::from_utf8(e.as_bytes())
-				.unwrap()
-				.split(',')
+			e.split(',')
 				.filter_map(|x| match x.trim() {
 					"" => None,
 					y => Some(y),
@@ -142,27 +260,39 @@ where
 
 	/// The client's websocket version.
 	pub fn version(&self) -> Option<WebSocketVersion> {
-		match self.request.headers.get("Sec-WebSocket-Version") {
-			Some(value) => Some(WebSocketVersion::from_str(value.to_str().unwrap()).unwrap()),
-			_ => None,
-		}
+		self.request
+		    .headers
+		    .get(SEC_WEBSOCKET_VERSION)
+		    .and_then(|v| WebSocketVersion::try_from(v).ok())
 	}
 
 	/// Origin of the client
 	pub fn origin(&self) -> Option<&str> {
-		self.request.headers.get("Origin").map(|o| str::from_utf8(o.as_ref()).unwrap())
+		self.request.headers.get(ORIGIN).and_then(|o| o.to_str().ok())
+	}
+
+	/// Whether the client's origin is allowed by `policy`.
+	pub fn origin_allowed(&self, policy: &OriginPolicy) -> bool {
+		policy.allows(&self.request.headers)
 	}
 
 	#[cfg(feature = "sync")]
-	fn send(&mut self, status: StatusCode) -> io::Result<()> {
-		write!(
-			&mut self.stream,
-			"{:?} {}\r\n",
-			self.request.version,
-			status
-		)?;
-		write!(&mut self.stream, "{:?}\r\n", self.headers)?;
-		Ok(())
+	fn send(&mut self, status: StatusCode, body: Option<&[u8]>) -> io::Result<()> {
+		use bytes::BytesMut;
+		use tokio_codec::Encoder;
+
+		let mut buf = BytesMut::new();
+		let mut codec = HttpServerCodec;
+		let response = ResponseHead {
+			version: self.request.version,
+			subject: status,
+			headers: self.headers.clone(),
+		};
+		match body {
+			Some(body) => codec.encode_with_body(response, body, &mut buf)?,
+			None => codec.encode(response, &mut buf)?,
+		}
+		self.stream.write_all(&buf)
 	}
 
 	#[doc(hidden)]
@@ -170,14 +300,15 @@ where
 		if let Some(headers) = custom {
 			self.headers.extend(headers.into_iter());
 		}
-		// NOTE: we know there is a key because this is a valid request
-		// i.e. to construct this you must go through the validate function
-		let key = self.request.headers.get(SEC_WEBSOCKET_KEY).unwrap();
-		let key = WebSocketKey::from_str(key.to_str().unwrap()).unwrap();
-		self.headers.append(
-			HeaderName::from_bytes("Sec-WebSocket-Accept".as_bytes()).unwrap(),
-			WebSocketAccept::new(key).into(),
-		);
+		// NOTE: the key was checked by the validate function, which one must
+		// go through to construct this, but the request is public so stay careful
+		let key = self.request
+		              .headers
+		              .get(SEC_WEBSOCKET_KEY)
+		              .and_then(|k| WebSocketKey::try_from(k).ok());
+		if let Some(key) = key {
+			self.headers.append(SEC_WEBSOCKET_ACCEPT, WebSocketAccept::new(key).into());
+		}
 		self.headers.append(
 			HeaderName::from_bytes("Connection".as_bytes()).unwrap(),
 			Connection(vec![
@@ -216,12 +347,71 @@ pub enum HyperIntoWsError {
 	NoWsConnectionHeader,
 	/// A websocket upgrade request must contain a `Connection` header
 	NoConnectionHeader,
+	/// One of the websocket related headers of the request is malformed
+	InvalidHeader(HeaderError),
+	/// The request was not completely read before the handshake timeout
+	Timeout,
+	/// The request's `Origin` is not allowed by the server's `OriginPolicy`
+	OriginNotAllowed,
+	/// No route of a `Router` matches the request
+	NoRoute,
+	/// A guard turned the request away and it was answered with this status
+	Rejected(StatusCode),
+	/// The request was plain HTTP and has been answered by the server's
+	/// `HttpHandler`, the connection is done
+	AnsweredHttp,
 	/// IO error from reading the underlying socket
 	Io(io::Error),
 	///
 	Http(codec::http::HttpCodecError),
 }
 
+impl HyperIntoWsError {
+	/// The HTTP status a server should answer with when this error happens
+	/// while reading a request, if the peer deserves an answer at all.
+	pub fn status_code(&self) -> Option<StatusCode> {
+		use codec::http::HttpCodecError;
+		match *self {
+			HyperIntoWsError::Timeout => Some(StatusCode::REQUEST_TIMEOUT),
+			HyperIntoWsError::OriginNotAllowed => Some(StatusCode::FORBIDDEN),
+			HyperIntoWsError::NoRoute => Some(StatusCode::NOT_FOUND),
+			HyperIntoWsError::Rejected(status) => Some(status),
+			HyperIntoWsError::Http(HttpCodecError::RequestLineTooLong) => {
+				Some(StatusCode::URI_TOO_LONG)
+			}
+			HyperIntoWsError::Http(HttpCodecError::HeadersTooLarge) |
+			HyperIntoWsError::Http(HttpCodecError::TooLarge) => {
+				Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+			}
+			HyperIntoWsError::InvalidHeader(_) |
+			HyperIntoWsError::Http(HttpCodecError::Method) |
+			HyperIntoWsError::Http(HttpCodecError::Version) |
+			HyperIntoWsError::Http(HttpCodecError::Uri) |
+			HyperIntoWsError::Http(HttpCodecError::Header) => Some(StatusCode::BAD_REQUEST),
+			_ => None,
+		}
+	}
+
+	/// The response a server sends back when this error happens, see `status_code`.
+	pub fn response(&self) -> Option<ResponseHead> {
+		self.status_code().map(|status| {
+			ResponseHead {
+				version: Version::HTTP_11,
+				subject: status,
+				headers: closing_headers(),
+			}
+		})
+	}
+}
+
+/// Headers for a response without a body after which the connection is closed.
+fn closing_headers() -> HeaderMap {
+	let mut headers = HeaderMap::new();
+	headers.insert(CONNECTION, HeaderValue::from_static("close"));
+	headers.insert(http::header::CONTENT_LENGTH, HeaderValue::from_static("0"));
+	headers
+}
+
 impl Display for HyperIntoWsError {
 	fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
 		fmt.write_str(self.description())
@@ -240,6 +430,12 @@ impl Error for HyperIntoWsError {
 			NoUpgradeHeader => "Missing Upgrade WebSocket header",
 			NoWsConnectionHeader => "Invalid Connection WebSocket header",
 			NoConnectionHeader => "Missing Connection WebSocket header",
+			Timeout => "Timed out while reading the handshake request",
+			OriginNotAllowed => "Origin not allowed",
+			NoRoute => "No route matches the request",
+			Rejected(_) => "The request was rejected",
+			AnsweredHttp => "The request was answered as plain HTTP",
+			InvalidHeader(ref e) => e.description(),
 			Io(ref e) => e.description(),
 			Http(ref e) => e.description(),
 		}
@@ -249,6 +445,7 @@ impl Error for HyperIntoWsError {
 		match *self {
 			HyperIntoWsError::Io(ref e) => Some(e),
 			HyperIntoWsError::Http(ref e) => Some(e),
+			HyperIntoWsError::InvalidHeader(ref e) => Some(e),
 			_ => None,
 		}
 	}
@@ -260,13 +457,19 @@ impl From<io::Error> for HyperIntoWsError {
 	}
 }
 
+impl From<HeaderError> for HyperIntoWsError {
+	fn from(err: HeaderError) -> Self {
+		HyperIntoWsError::InvalidHeader(err)
+	}
+}
+
 impl From<httparse::Error> for HyperIntoWsError {
 	fn from(err: httparse::Error) -> Self {
 		HyperIntoWsError::Http(err.into())
 	}
 }
 
-#[cfg(feature = "async")]
+#[cfg(any(feature = "sync", feature = "async"))]
 impl From<::codec::http::HttpCodecError> for HyperIntoWsError {
 	fn from(src: ::codec::http::HttpCodecError) -> Self {
 		match src {
@@ -292,25 +495,19 @@ pub fn validate(
 		return Err(HyperIntoWsError::UnsupportedHttpVersion);
 	}
 
-	if let Some(version) = headers.get(SEC_WEBSOCKET_VERSION).map(|v| {
-		v.to_str().unwrap().parse::<WebSocketVersion>().unwrap()
-	})
-	{
-		if version != WebSocketVersion::WebSocket13 {
+	if let Some(version) = headers.get(SEC_WEBSOCKET_VERSION) {
+		if WebSocketVersion::try_from(version)? != WebSocketVersion::WebSocket13 {
 			return Err(HyperIntoWsError::UnsupportedWebsocketVersion);
 		}
 	}
 
-	if headers.get(SEC_WEBSOCKET_KEY).is_none() {
-		return Err(HyperIntoWsError::NoSecWsKeyHeader);
-	}
-
-	match headers.get(UPGRADE).map(|v| v.to_str().unwrap().parse().unwrap()) {
-		Some(Upgrade(ref upgrade)) => {
+	match headers.get(UPGRADE).map(Upgrade::try_from) {
+		Some(Ok(Upgrade(ref upgrade))) => {
 			if upgrade.iter().all(|u| u.name != ProtocolName::WebSocket) {
 				return Err(HyperIntoWsError::NoWsUpgradeHeader);
 			}
 		}
+		Some(Err(e)) => return Err(e.into()),
 		None => return Err(HyperIntoWsError::NoUpgradeHeader),
 	};
 
@@ -325,14 +522,128 @@ pub fn validate(
 		false
 	}
 
-	match headers.get(CONNECTION).map(|v| v.to_str().unwrap().parse().unwrap()) {
-		Some(Connection(ref connection)) => {
+	match headers.get(CONNECTION).map(Connection::try_from) {
+		Some(Ok(Connection(ref connection))) => {
 			if !check_connection_header(connection) {
 				return Err(HyperIntoWsError::NoWsConnectionHeader);
 			}
 		}
+		Some(Err(e)) => return Err(e.into()),
 		None => return Err(HyperIntoWsError::NoConnectionHeader),
 	};
 
+	// only checked once the request is known to be an upgrade attempt, so
+	// that plain HTTP requests fail as such
+	match headers.get(SEC_WEBSOCKET_KEY) {
+		Some(key) => {
+			WebSocketKey::try_from(key)?;
+		}
+		None => return Err(HyperIntoWsError::NoSecWsKeyHeader),
+	};
+
+
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn upgrade_request() -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+		headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+		headers.insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+		headers.insert(SEC_WEBSOCKET_KEY, HeaderValue::from_static("dGhlIHNhbXBsZSBub25jZQ=="));
+		headers
+	}
+
+	#[test]
+	fn validate_accepts_upgrade() {
+		validate(&Method::GET, &Version::HTTP_11, &upgrade_request()).unwrap();
+	}
+
+	#[test]
+	fn validate_tells_plain_requests_apart() {
+		match validate(&Method::GET, &Version::HTTP_11, &HeaderMap::new()) {
+			Err(HyperIntoWsError::NoUpgradeHeader) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+		let mut headers = upgrade_request();
+		headers.remove(SEC_WEBSOCKET_KEY);
+		match validate(&Method::GET, &Version::HTTP_11, &headers) {
+			Err(HyperIntoWsError::NoSecWsKeyHeader) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn validate_rejects_malformed_headers() {
+		let hostile = HeaderValue::from_bytes(b"\xffweb\xfesocket").unwrap();
+		for name in &[UPGRADE, CONNECTION, SEC_WEBSOCKET_VERSION, SEC_WEBSOCKET_KEY] {
+			let mut headers = upgrade_request();
+			headers.insert(name.clone(), hostile.clone());
+			match validate(&Method::GET, &Version::HTTP_11, &headers) {
+				Err(HyperIntoWsError::InvalidHeader(e)) => {
+					assert_eq!(e, HeaderError::NotVisibleAscii(e.header_name()))
+				}
+				other => panic!("{:?} was not rejected: {:?}", name, other),
+			}
+		}
+
+		let mut headers = upgrade_request();
+		headers.insert(SEC_WEBSOCKET_KEY, HeaderValue::from_static("too short"));
+		match validate(&Method::GET, &Version::HTTP_11, &headers) {
+			Err(HyperIntoWsError::InvalidHeader(HeaderError::Invalid(_))) => (),
+			other => panic!("bad key was not rejected: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn validate_leaves_optional_headers_alone() {
+		// left to the origin policy and protocol negotiation
+		let odd = [
+			(ORIGIN, "file://"),
+			(ORIGIN, "chrome-extension://abcdefghijklmnop"),
+			(SEC_WEBSOCKET_PROTOCOL, "chat v1, {json}"),
+			(SEC_WEBSOCKET_EXTENSIONS, "permessage-deflate; =;"),
+		];
+		for &(ref name, value) in &odd {
+			let mut headers = upgrade_request();
+			headers.insert(name.clone(), HeaderValue::from_static(value));
+			validate(&Method::GET, &Version::HTTP_11, &headers).unwrap();
+		}
+
+		let mut headers = upgrade_request();
+		headers.insert(ORIGIN, HeaderValue::from_bytes(b"\xffweb\xfesocket").unwrap());
+		validate(&Method::GET, &Version::HTTP_11, &headers).unwrap();
+	}
+
+	#[test]
+	fn negotiate_protocol_picks_server_preference() {
+		use std::io::Cursor;
+		use stream::ReadWritePair;
+
+		let upgrade = |offered: &'static str| {
+			let mut headers = upgrade_request();
+			headers.insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(offered));
+			WsUpgrade {
+				headers: HeaderMap::new(),
+				stream: ReadWritePair(Cursor::new(Vec::new()), Cursor::new(Vec::new())),
+				request: RequestHead {
+					version: Version::HTTP_11,
+					subject: codec::http::RequestLine(Method::GET, Uri::default()),
+					headers: headers,
+				},
+				buffer: (),
+				context: None,
+			}
+		};
+
+		let chosen = upgrade("v1, v2, v3").negotiate_protocol(&["v3", "v2"]).ok().unwrap();
+		assert_eq!(chosen.headers.get(SEC_WEBSOCKET_PROTOCOL).unwrap(), "v3");
+
+		assert!(upgrade("v1").negotiate_protocol(&["v2"]).is_err());
+		assert!(upgrade("v1").negotiate_protocol_with(|_| Some("v2".to_string())).is_err());
+	}
+}
@@ -0,0 +1,239 @@
+//! Decide which origins may open a websocket connection.
+//!
+//! Browsers send an `Origin` header with every websocket handshake but, unlike
+//! with normal requests, do not enforce any same-origin rules on the connection.
+//! A server that does not check the origin can be driven by any page the user
+//! visits (cross-site websocket hijacking), so use an `OriginPolicy` to say who
+//! is allowed in.
+use std::convert::TryFrom;
+
+use http::header::{HeaderMap, HOST, ORIGIN};
+
+use header::{Host, Origin};
+
+/// A list of rules an incoming request's `Origin` header is checked against.
+///
+/// A new policy allows nothing except requests without any `Origin` header,
+/// which is what non-browser clients send.
+///
+/// ```rust
+/// use websocket::header::Origin;
+/// use websocket::server::upgrade::OriginPolicy;
+///
+/// let policy = OriginPolicy::new()
+///     .allow(Origin::new("https", "example.com", None))
+///     .allow_subdomains("https", "example.com", None)
+///     .same_host(true)
+///     .secure(true);
+/// ```
+#[derive(Clone, Debug)]
+pub struct OriginPolicy {
+	allowed: Vec<Allowed>,
+	same_host: bool,
+	secure: bool,
+	null: bool,
+	missing: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Allowed {
+	Exact(Origin),
+	Subdomains {
+		scheme: String,
+		domain: String,
+		port: Option<u16>,
+	},
+}
+
+impl OriginPolicy {
+	/// Create a policy that only allows requests without an `Origin` header.
+	pub fn new() -> Self {
+		OriginPolicy {
+			allowed: Vec::new(),
+			same_host: false,
+			secure: false,
+			null: false,
+			missing: true,
+		}
+	}
+
+	/// Allow this exact origin. Schemes and hostnames are compared case
+	/// insensitively and a missing port matches the scheme's default port.
+	pub fn allow(mut self, origin: Origin) -> Self {
+		self.allowed.push(Allowed::Exact(origin));
+		self
+	}
+
+	/// Allow every subdomain of `domain` with the given scheme and port,
+	/// like a `*.example.com` wildcard. The domain itself is not matched,
+	/// add it with `allow` if it should be.
+	pub fn allow_subdomains<S, D>(mut self, scheme: S, domain: D, port: Option<u16>) -> Self
+	where
+		S: Into<String>,
+		D: Into<String>,
+	{
+		self.allowed.push(Allowed::Subdomains {
+			scheme: scheme.into().to_ascii_lowercase(),
+			domain: domain.into().to_ascii_lowercase(),
+			port: port,
+		});
+		self
+	}
+
+	/// Allow origins that point to the same host (and port) as the request's
+	/// `Host` header, with the scheme of the server, see `secure`.
+	pub fn same_host(mut self, allow: bool) -> Self {
+		self.same_host = allow;
+		self
+	}
+
+	/// Whether the server is reached over TLS, which makes its scheme
+	/// `https` rather than `http` for `same_host`, and the port a `Host`
+	/// header without one means 443 rather than 80. Defaults to `false`.
+	pub fn secure(mut self, secure: bool) -> Self {
+		self.secure = secure;
+		self
+	}
+
+	/// Whether to allow the `null` origin, which browsers send from sandboxed
+	/// frames and local files among others.
+	pub fn allow_null(mut self, allow: bool) -> Self {
+		self.null = allow;
+		self
+	}
+
+	/// Whether to allow requests that do not send an `Origin` header at all.
+	pub fn allow_missing(mut self, allow: bool) -> Self {
+		self.missing = allow;
+		self
+	}
+
+	/// Check the headers of a handshake request against this policy.
+	pub fn allows(&self, headers: &HeaderMap) -> bool {
+		let origin = match headers.get(ORIGIN) {
+			Some(origin) => origin,
+			None => return self.missing,
+		};
+		let origin = match Origin::try_from(origin) {
+			Ok(origin) => origin,
+			Err(_) => return false,
+		};
+		let (scheme, host) = match (origin.scheme(), origin.host()) {
+			(Some(scheme), Some(host)) => (scheme, host),
+			_ => return self.null,
+		};
+
+		if self.same_host {
+			let server_scheme = if self.secure { "https" } else { "http" };
+			let request_host = headers.get(HOST).and_then(|h| Host::try_from(h).ok());
+			if let Some(request_host) = request_host {
+				if scheme.eq_ignore_ascii_case(server_scheme) && same_host(server_scheme, host, &request_host) {
+					return true;
+				}
+			}
+		}
+
+		self.allowed.iter().any(|allowed| allowed.matches(scheme, host))
+	}
+}
+
+impl Default for OriginPolicy {
+	fn default() -> Self {
+		OriginPolicy::new()
+	}
+}
+
+impl Allowed {
+	fn matches(&self, scheme: &str, host: &Host) -> bool {
+		match *self {
+			Allowed::Exact(ref origin) => {
+				match (origin.scheme(), origin.host()) {
+					(Some(allowed_scheme), Some(allowed_host)) => {
+						allowed_scheme.eq_ignore_ascii_case(scheme) &&
+							same_host(scheme, host, allowed_host)
+					}
+					_ => false,
+				}
+			}
+			Allowed::Subdomains {
+				scheme: ref allowed_scheme,
+				ref domain,
+				port,
+			} => {
+				let hostname = host.hostname().to_ascii_lowercase();
+				allowed_scheme.eq_ignore_ascii_case(scheme) &&
+					hostname.len() > domain.len() + 1 &&
+					hostname.ends_with(domain.as_str()) &&
+					hostname.as_bytes()[hostname.len() - domain.len() - 1] == b'.' &&
+					port.or(default_port(scheme)) == host.port().or(default_port(scheme))
+			}
+		}
+	}
+}
+
+fn same_host(scheme: &str, host: &Host, other: &Host) -> bool {
+	host.hostname().eq_ignore_ascii_case(other.hostname()) &&
+		host.port().or(default_port(scheme)) == other.port().or(default_port(scheme))
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+	match &scheme.to_ascii_lowercase()[..] {
+		"http" | "ws" => Some(80),
+		"https" | "wss" => Some(443),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use http::header::HeaderValue;
+
+	fn request(origin: Option<&'static str>) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert(HOST, HeaderValue::from_static("chat.example.com"));
+		if let Some(origin) = origin {
+			headers.insert(ORIGIN, HeaderValue::from_static(origin));
+		}
+		headers
+	}
+
+	#[test]
+	fn exact_and_wildcard_origins() {
+		let policy = OriginPolicy::new()
+			.allow(Origin::new("https", "example.com", None))
+			.allow_subdomains("https", "example.org", None);
+
+		assert!(policy.allows(&request(Some("https://example.com"))));
+		assert!(policy.allows(&request(Some("https://EXAMPLE.com:443"))));
+		assert!(!policy.allows(&request(Some("http://example.com"))));
+		assert!(!policy.allows(&request(Some("https://example.com:8443"))));
+
+		assert!(policy.allows(&request(Some("https://a.b.example.org"))));
+		assert!(!policy.allows(&request(Some("https://example.org"))));
+		assert!(!policy.allows(&request(Some("https://evilexample.org"))));
+	}
+
+	#[test]
+	fn same_host_null_and_missing_origins() {
+		let policy = OriginPolicy::new();
+		assert!(policy.allows(&request(None)));
+		assert!(!policy.allows(&request(Some("null"))));
+		assert!(!policy.allows(&request(Some("https://chat.example.com"))));
+
+		let policy = policy.same_host(true).allow_null(true).allow_missing(false);
+		assert!(!policy.allows(&request(None)));
+		assert!(policy.allows(&request(Some("null"))));
+		assert!(policy.allows(&request(Some("http://chat.example.com"))));
+		assert!(!policy.allows(&request(Some("https://chat.example.com"))));
+		assert!(!policy.allows(&request(Some("http://chat.example.com:443"))));
+
+		// the Host header's port is the server's, not the origin's
+		let policy = policy.secure(true);
+		assert!(policy.allows(&request(Some("https://chat.example.com"))));
+		assert!(policy.allows(&request(Some("https://chat.example.com:443"))));
+		assert!(!policy.allows(&request(Some("http://chat.example.com"))));
+		assert!(!policy.allows(&request(Some("http://chat.example.com:443"))));
+		assert!(!policy.allows(&request(Some("https://chat.example.com.evil.net"))));
+	}
+}
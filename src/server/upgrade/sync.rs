@@ -1,17 +1,20 @@
 //! Allows you to take an existing request or stream of data and convert it into a
 //! WebSocket client.
-use std::io::{self, BufRead};
+use std::io::{self, Write};
 use std::net::TcpStream;
+use std::time::{Duration, Instant};
 
 use client::sync::Client;
-use codec::http::{RequestHead, RequestLine};
-use server::upgrade::{WsUpgrade, HyperIntoWsError, validate};
+use codec::http::{RequestHead, HttpServerCodec};
+use server::upgrade::{WsUpgrade, HyperIntoWsError, HandshakeConfig, HandshakeStream, OriginPolicy};
+use server::upgrade::{closing_headers, validate};
+use server::guard::{AnyContext, Rejection, WithContext};
 use stream::sync::{Stream, AsTcpStream};
 
-use std::io::BufReader;
-use http::{self, StatusCode};
-use http::header::HeaderMap;
-use httparse;
+use bytes::BytesMut;
+use http::StatusCode;
+use http::header::{HeaderMap, HeaderValue, CONTENT_LENGTH};
+use tokio_codec::{Decoder, Encoder};
 
 /// This crate uses buffered readers to read in the handshake quickly, in order to
 /// interface with other use cases that don't use buffered readers the buffered readers
@@ -61,15 +64,42 @@ where
 		self.internal_accept(Some(custom_headers))
 	}
 
+	/// Accept the handshake request and send a response, keeping the context
+	/// the server's guard returned next to the client.
+	///
+	/// See the `server::guard` module for an example.
+	pub fn accept_with_context(
+		mut self,
+	) -> Result<WithContext<Client<S>, Option<AnyContext>>, (S, io::Error)> {
+		let context = self.context.take();
+		let client = self.internal_accept(None)?;
+		Ok(WithContext {
+			client: client,
+			context: context,
+		})
+	}
+
+	pub(crate) fn send_rejection(self, rejection: Rejection) -> Result<S, (S, io::Error)> {
+		let Rejection { status, headers, body } = rejection;
+		self.reject_with_status(status, headers, Some(&body[..]))
+	}
+
 	fn internal_accept(mut self, headers: Option<HeaderMap>) -> Result<Client<S>, (S, io::Error)> {
 		let status = self.prepare_headers(headers);
 
-		if let Err(e) = self.send(status) {
+		if let Err(e) = self.send(status, None) {
 			return Err((self.stream, e));
 		}
 
-		Ok(Client::unchecked(
-			BufReader::new(self.stream),
+		// the client may have sent frames right behind its request
+		let buffered = match self.buffer {
+			Some(ref extra) => &extra.buf[extra.pos..extra.cap],
+			None => &[][..],
+		};
+
+		Ok(Client::unchecked_with_buffer(
+			self.stream,
+			buffered,
 			self.headers,
 			false,
 			true,
@@ -78,20 +108,72 @@ where
 
 	/// Reject the client's request to make a websocket connection.
 	pub fn reject(self) -> Result<S, (S, io::Error)> {
-		self.internal_reject(None)
+		self.internal_reject(None, StatusCode::BAD_REQUEST, None)
 	}
 
 	/// Reject the client's request to make a websocket connection
 	/// and send extra headers.
 	pub fn reject_with(self, headers: HeaderMap) -> Result<S, (S, io::Error)> {
-		self.internal_reject(Some(headers))
+		self.internal_reject(Some(headers), StatusCode::BAD_REQUEST, None)
 	}
 
-	fn internal_reject(mut self, headers: Option<HeaderMap>) -> Result<S, (S, io::Error)> {
+	/// Reject the client's request with any status, extra headers and an
+	/// optional body. The `Content-Length` header is set to fit the body.
+	///
+	/// ```rust,no_run
+	/// # extern crate websocket;
+	/// # extern crate http;
+	/// # fn main() {
+	/// use http::StatusCode;
+	/// use http::header::{HeaderMap, HeaderValue, SEC_WEBSOCKET_VERSION};
+	/// # use websocket::sync::Server;
+	/// # let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// # let upgrade = server.accept().ok().unwrap();
+	///
+	/// let mut headers = HeaderMap::new();
+	/// headers.insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+	/// upgrade.reject_with_status(StatusCode::UPGRADE_REQUIRED, headers, None).unwrap();
+	/// # }
+	/// ```
+	pub fn reject_with_status(
+		self,
+		status: StatusCode,
+		headers: HeaderMap,
+		body: Option<&[u8]>,
+	) -> Result<S, (S, io::Error)> {
+		self.internal_reject(Some(headers), status, Some(body.unwrap_or(&[])))
+	}
+
+	/// Reject the client's request with a `403 Forbidden` if its origin is not
+	/// allowed by `policy`, otherwise hand the upgrade back untouched.
+	///
+	/// On a rejection the stream is returned along with
+	/// `HyperIntoWsError::OriginNotAllowed`, or the IO error that prevented
+	/// the answer from being sent.
+	pub fn enforce_origin(self, policy: &OriginPolicy) -> Result<Self, (S, HyperIntoWsError)> {
+		if self.origin_allowed(policy) {
+			return Ok(self);
+		}
+		match self.internal_reject(Some(closing_headers()), StatusCode::FORBIDDEN, None) {
+			Ok(stream) => Err((stream, HyperIntoWsError::OriginNotAllowed)),
+			Err((stream, e)) => Err((stream, e.into())),
+		}
+	}
+
+	pub(crate) fn internal_reject(
+		mut self,
+		headers: Option<HeaderMap>,
+		status: StatusCode,
+		body: Option<&[u8]>,
+	) -> Result<S, (S, io::Error)> {
 		if let Some(custom) = headers {
 			self.headers.extend(custom.into_iter());
 		}
-		match self.send(StatusCode::BAD_REQUEST) {
+		// without a length the peer would wait for the connection to close
+		if body.is_none() && !self.headers.contains_key(CONTENT_LENGTH) {
+			self.headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+		}
+		match self.send(status, body) {
 			Ok(()) => Ok(self.stream),
 			Err(e) => Err((self.stream, e)),
 		}
@@ -164,73 +246,138 @@ where
 	type Error = (S, Option<RequestHead>, Option<Buffer>, HyperIntoWsError);
 
 	fn into_ws(self) -> Result<Upgrade<Self::Stream>, Self::Error> {
+		// a bare stream can't be given a read timeout
+		let config = HandshakeConfig {
+			timeout: None,
+			..HandshakeConfig::default()
+		};
+		read_request(self, &config, |_, _| Ok(()))
+	}
+}
 
-		let mut buf = String::new();
-		let mut reader = BufReader::new(self);
-		reader.read_line(&mut buf);
+/// Reads the handshake with the config's byte limits and timeout.
+///
+/// The timeout is enforced by setting a read timeout on the underlying TCP stream
+/// before every read, the stream's original read timeout is restored afterwards.
+impl<S> IntoWs for HandshakeStream<S>
+where
+	S: Stream + AsTcpStream + Send,
+{
+	type Stream = S;
+	type Error = (S, Option<RequestHead>, Option<Buffer>, HyperIntoWsError);
 
-		// cleanup
-		let buf2 = buf.clone();
+	fn into_ws(self) -> Result<Upgrade<Self::Stream>, Self::Error> {
+		let HandshakeStream(stream, config) = self;
+		let original = match stream.as_tcp().read_timeout() {
+			Ok(t) => t,
+			Err(e) => return Err((stream, None, None, e.into())),
+		};
 
-		let mut parse = httparse::Request::new(&mut []);
+		let result = read_request(stream, &config, |s, timeout| s.as_tcp().set_read_timeout(timeout));
 
-		let stream = reader.into_inner();
+		// put the stream back the way we found it
+		let restored = match result {
+			Ok(ref upgrade) => upgrade.stream.as_tcp().set_read_timeout(original),
+			Err((ref stream, ..)) => stream.as_tcp().set_read_timeout(original),
+		};
+		match (result, restored) {
+			(Ok(upgrade), Err(e)) => Err((upgrade.stream, Some(upgrade.request), upgrade.buffer, e.into())),
+			(result, _) => result,
+		}
+	}
+}
 
-		match parse.parse(buf2.as_bytes()) {
-			Ok(o) => {
-				match o {
-					httparse::Status::Complete(_) => {}
-					_ => return Err((stream, None, None, ::httparse::Error::HeaderValue.into())),
-				}
-			}
-			Err(e) => return Err((stream, None, None, e.into())),
+const READ_CHUNK: usize = 1024;
+
+fn read_request<S, F>(
+	mut stream: S,
+	config: &HandshakeConfig,
+	mut set_timeout: F,
+) -> Result<Upgrade<S>, (S, Option<RequestHead>, Option<Buffer>, HyperIntoWsError)>
+where
+	S: Stream + Send,
+	F: FnMut(&S, Option<Duration>) -> io::Result<()>,
+{
+	let mut codec = config.codec();
+	let deadline = config.timeout.map(|t| Instant::now() + t);
+	let mut buf = BytesMut::with_capacity(READ_CHUNK);
+	let mut chunk = [0u8; READ_CHUNK];
+
+	let request = loop {
+		match codec.decode(&mut buf) {
+			Ok(Some(request)) => break request,
+			Ok(None) => (),
+			Err(e) => return Err((stream, None, into_buffer(buf), e.into())),
 		}
 
-		let cap = buf.len();
-		let pos = buf.len();
-		let buffer = Some(Buffer {
-			buf: buf.into_bytes(),
-			cap: cap,
-			pos: pos,
-		});
-
-		let request = RequestHead {
-			version: match parse.version {
-				Some(0) => http::Version::HTTP_10,
-				Some(1) => http::Version::HTTP_11,
-				Some(_) | None => {
-					return Err((stream, None, buffer, httparse::Error::Version.into()))
-				}
-			},
-			subject: RequestLine(
-				match parse.method.unwrap().parse() {
-					Ok(method) => method,
-					Err(e) => {
-						return Err((stream, None, buffer, httparse::Error::HeaderValue.into()))
-					}
-				},
-				match parse.path.unwrap().parse() {
-					Ok(path) => path,
-					Err(e) => {
-						return Err((stream, None, buffer, httparse::Error::HeaderValue.into()))
-					}
-				},
-			),
-			headers: HeaderMap::new(),
-		};
+		if let Some(deadline) = deadline {
+			let now = Instant::now();
+			if now >= deadline {
+				return Err((stream, None, into_buffer(buf), HyperIntoWsError::Timeout));
+			}
+			if let Err(e) = set_timeout(&stream, Some(deadline - now)) {
+				return Err((stream, None, into_buffer(buf), e.into()));
+			}
+		}
 
-		match validate(&request.subject.0, &request.version, &request.headers) {
-			Ok(_) => {
-				Ok(WsUpgrade {
-					headers: HeaderMap::new(),
-					stream: stream,
-					request: request,
-					buffer: buffer,
-				})
+		match stream.read(&mut chunk) {
+			Ok(0) => {
+				let err = HyperIntoWsError::Io(io::Error::new(
+					io::ErrorKind::ConnectionReset,
+					"Connection dropped before handshake could be read",
+				));
+				return Err((stream, None, into_buffer(buf), err));
+			}
+			Ok(n) => buf.extend_from_slice(&chunk[..n]),
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+			Err(ref e) if deadline.is_some() &&
+				(e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut) => {
+				return Err((stream, None, into_buffer(buf), HyperIntoWsError::Timeout));
 			}
-			Err(e) => Err((stream, Some(request), buffer, e)),
+			Err(e) => return Err((stream, None, into_buffer(buf), e.into())),
 		}
+	};
+
+	let buffer = into_buffer(buf);
+	match validate(&request.subject.0, &request.version, &request.headers) {
+		Ok(_) => {
+			Ok(WsUpgrade {
+				headers: HeaderMap::new(),
+				stream: stream,
+				request: request,
+				buffer: buffer,
+				context: None,
+			})
+		}
+		Err(e) => Err((stream, Some(request), buffer, e)),
+	}
+}
+
+fn into_buffer(buf: BytesMut) -> Option<Buffer> {
+	if buf.is_empty() {
+		return None;
+	}
+	let cap = buf.len();
+	Some(Buffer {
+		buf: buf.to_vec(),
+		pos: 0,
+		cap: cap,
+	})
+}
+
+/// Answer a request that failed to be read with the status the error calls for
+/// (e.g. 408 on a timeout), see `HyperIntoWsError::response`.
+pub fn respond_to_error<S>(stream: &mut S, error: &HyperIntoWsError) -> io::Result<()>
+where
+	S: Write,
+{
+	if let Some(response) = error.response() {
+		let mut dst = BytesMut::new();
+		HttpServerCodec.encode(response, &mut dst)?;
+		stream.write_all(&dst)?;
+		stream.flush()?;
 	}
+	Ok(())
 }
 
 impl<S> IntoWs for RequestStreamPair<S>
@@ -248,6 +395,7 @@ where
 					stream: self.0,
 					request: self.1,
 					buffer: None,
+					context: None,
 				})
 			}
 			Err(e) => Err((self.0, self.1, e)),
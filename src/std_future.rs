@@ -0,0 +1,168 @@
+//! The async client and server on `std::future` instead of futures 0.1.
+//!
+//! Everything here wraps the types of the `async` feature, so the handshake
+//! still goes through `codec::http` and messages through
+//! `codec::ws::MessageCodec`, only the traits they are used through change:
+//! futures resolve to a `Result` and clients are a `futures::Stream` and a
+//! `futures::Sink` of `OwnedMessage`s from the 0.3 series of `futures`.
+//!
+//! The sockets still belong to tokio 0.1. Outside of a tokio 0.1 runtime they
+//! are driven by its background reactor, so any executor can run these.
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # extern crate futures03;
+//! # fn main() {
+//! use futures03::executor::block_on;
+//! use futures03::{future, FutureExt, StreamExt};
+//! use websocket::std_future;
+//!
+//! let server = std_future::bind("127.0.0.1:2794").unwrap();
+//! let echo = std_future::incoming(server.incoming())
+//!     .filter_map(|upgrade| future::ready(upgrade.ok()))
+//!     .for_each(|(upgrade, _)| std_future::accept(upgrade).then(|accepted| match accepted {
+//!         Ok((client, _)) => {
+//!             let (sink, stream) = client.split();
+//!             stream.forward(sink).map(|_| ()).left_future()
+//!         }
+//!         Err(_) => future::ready(()).right_future(),
+//!     }));
+//! block_on(echo);
+//! # }
+//! ```
+use std::io;
+use std::net::ToSocketAddrs;
+
+use futures03::compat::{Compat01As03, Compat01As03Sink, Future01CompatExt, Sink01CompatExt,
+                        Stream01CompatExt};
+use futures03::{Future, TryFutureExt};
+use http::HeaderMap;
+
+use client::async::{Client as Client01, ClientNew};
+use client::builder::ClientBuilder;
+use message::OwnedMessage;
+use result::WebSocketResult;
+use server::NoTlsAcceptor;
+use server::async::{Handle, Incoming as Incoming01, Server};
+use server::upgrade::async::Upgrade;
+use stream::async::{Stream as AsyncStream, TcpStream};
+
+#[cfg(feature = "async-ssl")]
+use native_tls::TlsConnector;
+
+/// A websocket connection, a `Stream` and `Sink` of `OwnedMessage`s.
+pub type Client<S> = Compat01As03Sink<Client01<S>, OwnedMessage>;
+
+/// The upgrade requests a server receives, see `incoming`.
+pub type Incoming<S> = Compat01As03<Incoming01<S>>;
+
+/// Turn a futures 0.1 client into one for `std::future`.
+pub fn client<S>(client: Client01<S>) -> Client<S>
+where
+	S: AsyncStream + Send,
+{
+	client.sink_compat()
+}
+
+/// Turn the future of any handshake, like the one of
+/// `WsUpgrade::accept_with`, into a `std::future::Future`.
+pub fn handshake<S>(future: ClientNew<S>) -> impl Future<Output = WebSocketResult<(Client<S>, HeaderMap)>>
+where
+	S: AsyncStream + Send,
+{
+	future.compat().map_ok(|(c, headers)| (client(c), headers))
+}
+
+/// Connect to the builder's url, over TLS if it is a `wss://` url.
+///
+/// See `ClientBuilder::async_connect`.
+#[cfg(feature = "async-ssl")]
+pub fn connect(
+	builder: ClientBuilder,
+	ssl_config: Option<TlsConnector>,
+) -> impl Future<Output = WebSocketResult<(Client<Box<AsyncStream + Send>>, HeaderMap)>> {
+	handshake(builder.async_connect(ssl_config, &Handle::default()))
+}
+
+/// Connect to the builder's url without TLS.
+///
+/// See `ClientBuilder::async_connect_insecure`.
+pub fn connect_insecure(
+	builder: ClientBuilder,
+) -> impl Future<Output = WebSocketResult<(Client<TcpStream>, HeaderMap)>> {
+	handshake(builder.async_connect_insecure(&Handle::default()))
+}
+
+/// Do the handshake over a stream that is already connected.
+///
+/// See `ClientBuilder::async_connect_on`.
+pub fn connect_on<S>(
+	builder: ClientBuilder,
+	stream: S,
+) -> impl Future<Output = WebSocketResult<(Client<S>, HeaderMap)>>
+where
+	S: AsyncStream + Send + 'static,
+{
+	handshake(builder.async_connect_on(stream))
+}
+
+/// Bind a server to an address on tokio's default reactor.
+///
+/// Pass `server.incoming()` to `incoming` to get its connections.
+///
+/// The handshake timeout needs tokio's timer, which `std::future` executors
+/// don't run, so it is turned off. Set `server.handshake.timeout` again when
+/// the server runs on a tokio runtime.
+pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Server<NoTlsAcceptor>> {
+	let mut server = Server::bind(addr, &Handle::default())?;
+	server.handshake.timeout = None;
+	Ok(server)
+}
+
+/// Turn the upgrade requests of a server into a `futures::Stream`.
+pub fn incoming<S>(incoming: Incoming01<S>) -> Incoming<S>
+where
+	S: AsyncStream + Send,
+{
+	incoming.compat()
+}
+
+/// Accept an upgrade request, resolving to the client once the response was sent.
+///
+/// See `WsUpgrade::accept`.
+pub fn accept<S>(upgrade: Upgrade<S>) -> impl Future<Output = WebSocketResult<(Client<S>, HeaderMap)>>
+where
+	S: AsyncStream + Send + 'static,
+{
+	handshake(upgrade.accept())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures03::{executor, future, stream, FutureExt, StreamExt};
+	use result::WebSocketError;
+
+	#[test]
+	fn exchanges_a_message() {
+		let server = bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+
+		let server_side = incoming(server.incoming())
+			.into_future()
+			.map(|(upgrade, _)| match upgrade {
+				Some(Ok((upgrade, _))) => upgrade,
+				_ => panic!("no upgrade request"),
+			})
+			.then(accept)
+			.and_then(|(client, _)| {
+				let message = OwnedMessage::Text("hi".to_string());
+				stream::iter(vec![Ok::<_, WebSocketError>(message)]).forward(client)
+			});
+		let client_side = connect_insecure(ClientBuilder::new(&url).unwrap())
+			.and_then(|(client, _)| client.into_future().map(Ok));
+
+		let (_, (received, _)) = executor::block_on(future::try_join(server_side, client_side)).unwrap();
+		assert_eq!(received.unwrap().unwrap(), OwnedMessage::Text("hi".to_string()));
+	}
+}
@@ -0,0 +1,766 @@
+//! STOMP 1.2 over websockets, the `v12.stomp` subprotocol.
+//!
+//! Every websocket message holds one STOMP frame, or a single end of line
+//! as a heart-beat. `Frame` parses and serializes frames, `StompClient` talks
+//! to a broker over a sync `Client` and `StompServer` is the broker's side of
+//! a connection, enough to answer a client in tests.
+//!
+//!```rust,no_run
+//!use websocket::stomp::{self, Ack, Frame, StompClient};
+//!
+//!let client = stomp::builder("ws://127.0.0.1:61614/stomp").unwrap()
+//!    .connect_insecure().unwrap();
+//!let connect = Frame::connect("broker.example.com")
+//!    .header("login", "guest")
+//!    .header("passcode", "guest")
+//!    .header("heart-beat", "10000,10000");
+//!let (mut stomp, _) = StompClient::connect(client, connect).unwrap();
+//!
+//!stomp.subscribe("/queue/telemetry", Ack::Client).unwrap();
+//!stomp.send("/queue/commands", "text/plain", b"start").unwrap();
+//!let message = stomp.recv().unwrap();
+//!println!("{}", String::from_utf8_lossy(&message.body));
+//!stomp.ack(&message).unwrap();
+//!stomp.disconnect().unwrap();
+//!```
+//!
+//! Heart-beats are not sent on their own, call `tick` often enough, for
+//! example after setting a read timeout on the connection.
+use std::error::Error;
+use std::fmt;
+use std::str::{self, FromStr};
+use std::time::{Duration, Instant};
+
+use client::builder::{ClientBuilder, ParseError};
+use client::sync::Client;
+use message::OwnedMessage;
+use result::WebSocketError;
+use stream::sync::Stream;
+
+/// The name of the subprotocol, to offer with `ClientBuilder::add_protocols`
+/// or to pick with `WsUpgrade::negotiate_protocol`.
+pub const PROTOCOL: &'static str = "v12.stomp";
+
+/// A `ClientBuilder` for `url` that offers the STOMP subprotocol.
+pub fn builder(url: &str) -> Result<ClientBuilder<'static>, ParseError> {
+	Ok(ClientBuilder::new(url)?.add_protocols(vec![PROTOCOL]))
+}
+
+/// The command of a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Command {
+	Connect,
+	Stomp,
+	Connected,
+	Send,
+	Subscribe,
+	Unsubscribe,
+	Ack,
+	Nack,
+	Begin,
+	Commit,
+	Abort,
+	Disconnect,
+	Message,
+	Receipt,
+	Error,
+}
+
+impl Command {
+	/// The command as it is written in a frame.
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			Command::Connect => "CONNECT",
+			Command::Stomp => "STOMP",
+			Command::Connected => "CONNECTED",
+			Command::Send => "SEND",
+			Command::Subscribe => "SUBSCRIBE",
+			Command::Unsubscribe => "UNSUBSCRIBE",
+			Command::Ack => "ACK",
+			Command::Nack => "NACK",
+			Command::Begin => "BEGIN",
+			Command::Commit => "COMMIT",
+			Command::Abort => "ABORT",
+			Command::Disconnect => "DISCONNECT",
+			Command::Message => "MESSAGE",
+			Command::Receipt => "RECEIPT",
+			Command::Error => "ERROR",
+		}
+	}
+
+	// the headers of the connect frames are not escaped, for 1.0 brokers
+	fn escapes_headers(&self) -> bool {
+		match *self {
+			Command::Connect | Command::Connected => false,
+			_ => true,
+		}
+	}
+}
+
+impl FromStr for Command {
+	type Err = StompError;
+
+	fn from_str(command: &str) -> Result<Command, StompError> {
+		Ok(match command {
+			"CONNECT" => Command::Connect,
+			"STOMP" => Command::Stomp,
+			"CONNECTED" => Command::Connected,
+			"SEND" => Command::Send,
+			"SUBSCRIBE" => Command::Subscribe,
+			"UNSUBSCRIBE" => Command::Unsubscribe,
+			"ACK" => Command::Ack,
+			"NACK" => Command::Nack,
+			"BEGIN" => Command::Begin,
+			"COMMIT" => Command::Commit,
+			"ABORT" => Command::Abort,
+			"DISCONNECT" => Command::Disconnect,
+			"MESSAGE" => Command::Message,
+			"RECEIPT" => Command::Receipt,
+			"ERROR" => Command::Error,
+			_ => return Err(StompError::Frame("unknown command")),
+		})
+	}
+}
+
+/// What can go wrong speaking STOMP.
+#[derive(Debug)]
+pub enum StompError {
+	/// A frame could not be parsed.
+	Frame(&'static str),
+	/// The peer did not follow the protocol.
+	Protocol(&'static str),
+	/// The broker sent an `ERROR` frame.
+	Server(Frame),
+	/// The connection was closed.
+	Closed,
+	/// The connection failed, see the `WebSocketError`.
+	WebSocket(WebSocketError),
+}
+
+impl fmt::Display for StompError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			StompError::Frame(e) => write!(fmt, "invalid STOMP frame: {}", e),
+			StompError::Protocol(e) => write!(fmt, "STOMP protocol error: {}", e),
+			StompError::Server(ref frame) => {
+				write!(fmt, "STOMP error: {}", frame.get("message").unwrap_or("no message"))
+			}
+			StompError::Closed => fmt.write_str("STOMP connection closed"),
+			StompError::WebSocket(ref e) => fmt::Display::fmt(e, fmt),
+		}
+	}
+}
+
+impl Error for StompError {
+	fn description(&self) -> &str {
+		match *self {
+			StompError::Frame(_) => "invalid STOMP frame",
+			StompError::Protocol(_) => "STOMP protocol error",
+			StompError::Server(_) => "STOMP error frame",
+			StompError::Closed => "STOMP connection closed",
+			StompError::WebSocket(ref e) => e.description(),
+		}
+	}
+
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			StompError::WebSocket(ref e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<WebSocketError> for StompError {
+	fn from(err: WebSocketError) -> StompError {
+		StompError::WebSocket(err)
+	}
+}
+
+/// A STOMP frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+	/// What the frame is for.
+	pub command: Command,
+	/// The headers in the order they are written, a header that is repeated
+	/// counts with its first value.
+	pub headers: Vec<(String, String)>,
+	/// The body, empty for most commands.
+	pub body: Vec<u8>,
+}
+
+impl Frame {
+	/// A frame without headers or body.
+	pub fn new(command: Command) -> Frame {
+		Frame {
+			command: command,
+			headers: Vec::new(),
+			body: Vec::new(),
+		}
+	}
+
+	/// A `CONNECT` frame for a STOMP 1.2 broker serving `host`.
+	pub fn connect(host: &str) -> Frame {
+		Frame::new(Command::Connect)
+			.header("accept-version", "1.2")
+			.header("host", host)
+	}
+
+	/// Add a header.
+	pub fn header<N, V>(mut self, name: N, value: V) -> Frame
+	where
+		N: Into<String>,
+		V: Into<String>,
+	{
+		self.headers.push((name.into(), value.into()));
+		self
+	}
+
+	/// Set the body.
+	pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Frame {
+		self.body = body.into();
+		self
+	}
+
+	/// The value of a header.
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.headers
+		    .iter()
+		    .find(|&&(ref n, _)| n == name)
+		    .map(|&(_, ref value)| value.as_str())
+	}
+
+	/// The `RECEIPT` for this frame, if the peer asked for one.
+	pub fn receipt(&self) -> Option<Frame> {
+		self.get("receipt")
+		    .map(|id| Frame::new(Command::Receipt).header("receipt-id", id))
+	}
+
+	/// Serialize the frame, a `content-length` header is added for a body
+	/// unless there already is one.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(64 + self.body.len());
+		bytes.extend(self.command.as_str().as_bytes());
+		bytes.push(b'\n');
+		for &(ref name, ref value) in &self.headers {
+			self.write_header(&mut bytes, name, value);
+		}
+		if !self.body.is_empty() && self.get("content-length").is_none() {
+			self.write_header(&mut bytes, "content-length", &self.body.len().to_string());
+		}
+		bytes.push(b'\n');
+		bytes.extend(&self.body);
+		bytes.push(0);
+		bytes
+	}
+
+	fn write_header(&self, bytes: &mut Vec<u8>, name: &str, value: &str) {
+		if self.command.escapes_headers() {
+			escape(bytes, name);
+			bytes.push(b':');
+			escape(bytes, value);
+		} else {
+			bytes.extend(name.as_bytes());
+			bytes.push(b':');
+			bytes.extend(value.as_bytes());
+		}
+		bytes.push(b'\n');
+	}
+
+	/// Parse a frame, `None` if there are only heart-beats.
+	pub fn parse(data: &[u8]) -> Result<Option<Frame>, StompError> {
+		let mut pos = 0;
+		while pos < data.len() && (data[pos] == b'\n' || data[pos] == b'\r') {
+			pos += 1;
+		}
+		if pos == data.len() {
+			return Ok(None);
+		}
+
+		let command: Command = utf8(line(data, &mut pos)?)?.parse()?;
+		let mut frame = Frame::new(command);
+		loop {
+			let header = line(data, &mut pos)?;
+			if header.is_empty() {
+				break;
+			}
+			let colon = match header.iter().position(|&b| b == b':') {
+				Some(colon) => colon,
+				None => return Err(StompError::Frame("header without a colon")),
+			};
+			let (name, value) = (&header[..colon], &header[colon + 1..]);
+			frame.headers.push(if command.escapes_headers() {
+				(unescape(name)?, unescape(value)?)
+			} else {
+				(utf8(name)?.to_string(), utf8(value)?.to_string())
+			});
+		}
+
+		let rest = &data[pos..];
+		let length = match frame.get("content-length") {
+			Some(length) => {
+				let length = length.parse().map_err(|_| StompError::Frame("invalid content-length"))?;
+				if rest.len() <= length || rest[length] != 0 {
+					return Err(StompError::Frame("body does not match its content-length"));
+				}
+				length
+			}
+			None => match rest.iter().position(|&b| b == 0) {
+				Some(length) => length,
+				None => return Err(StompError::Frame("frame does not end with a NUL")),
+			},
+		};
+		if rest[length + 1..].iter().any(|&b| b != b'\n' && b != b'\r') {
+			return Err(StompError::Frame("data after the frame"));
+		}
+		frame.body = rest[..length].to_vec();
+		Ok(Some(frame))
+	}
+
+	/// The frame as a websocket message, a text message unless the body is
+	/// not UTF-8.
+	pub fn to_message(&self) -> OwnedMessage {
+		match String::from_utf8(self.to_bytes()) {
+			Ok(text) => OwnedMessage::Text(text),
+			Err(e) => OwnedMessage::Binary(e.into_bytes()),
+		}
+	}
+
+	/// The frame in a websocket message, `None` for a heart-beat or a
+	/// message that is not a text or binary message.
+	pub fn from_message(message: &OwnedMessage) -> Result<Option<Frame>, StompError> {
+		match *message {
+			OwnedMessage::Text(ref text) => Frame::parse(text.as_bytes()),
+			OwnedMessage::Binary(ref data) => Frame::parse(data),
+			_ => Ok(None),
+		}
+	}
+}
+
+fn line<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a [u8], StompError> {
+	let rest = &data[*pos..];
+	let end = match rest.iter().position(|&b| b == b'\n') {
+		Some(end) => end,
+		None => return Err(StompError::Frame("incomplete frame")),
+	};
+	*pos += end + 1;
+	match rest[..end].last() {
+		Some(&b'\r') => Ok(&rest[..end - 1]),
+		_ => Ok(&rest[..end]),
+	}
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, StompError> {
+	str::from_utf8(bytes).map_err(|_| StompError::Frame("invalid UTF-8"))
+}
+
+fn escape(bytes: &mut Vec<u8>, text: &str) {
+	for &b in text.as_bytes() {
+		match b {
+			b'\\' => bytes.extend(b"\\\\"),
+			b'\r' => bytes.extend(b"\\r"),
+			b'\n' => bytes.extend(b"\\n"),
+			b':' => bytes.extend(b"\\c"),
+			b => bytes.push(b),
+		}
+	}
+}
+
+fn unescape(bytes: &[u8]) -> Result<String, StompError> {
+	let mut text = Vec::with_capacity(bytes.len());
+	let mut escaped = bytes.iter();
+	while let Some(&b) = escaped.next() {
+		if b != b'\\' {
+			text.push(b);
+			continue;
+		}
+		text.push(match escaped.next() {
+			Some(&b'\\') => b'\\',
+			Some(&b'r') => b'\r',
+			Some(&b'n') => b'\n',
+			Some(&b'c') => b':',
+			_ => return Err(StompError::Frame("invalid escape in a header")),
+		});
+	}
+	String::from_utf8(text).map_err(|_| StompError::Frame("invalid UTF-8"))
+}
+
+/// The `heart-beat` header: how often, in milliseconds, a side can send
+/// heart-beats and wants to receive them. Zero means never.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct HeartBeat {
+	/// The shortest interval this side can send heart-beats at.
+	pub send: u32,
+	/// The interval this side wants heart-beats at.
+	pub receive: u32,
+}
+
+impl HeartBeat {
+	/// The heart-beat a frame asks for, none if it has no valid header.
+	pub fn of(frame: &Frame) -> HeartBeat {
+		let mut values = frame.get("heart-beat").unwrap_or("").split(',').map(|v| v.trim().parse());
+		match (values.next(), values.next(), values.next()) {
+			(Some(Ok(send)), Some(Ok(receive)), None) => HeartBeat {
+				send: send,
+				receive: receive,
+			},
+			_ => HeartBeat::default(),
+		}
+	}
+
+	/// The value of the header.
+	pub fn to_header(&self) -> String {
+		format!("{},{}", self.send, self.receive)
+	}
+
+	/// How often this side has to send heart-beats and how often it gets
+	/// them, after the peer asked for `theirs`.
+	pub fn negotiate(&self, theirs: &HeartBeat) -> (Option<Duration>, Option<Duration>) {
+		fn interval(a: u32, b: u32) -> Option<Duration> {
+			if a == 0 || b == 0 {
+				None
+			} else {
+				Some(Duration::from_millis(u64::from(a.max(b))))
+			}
+		}
+		(interval(self.send, theirs.receive), interval(self.receive, theirs.send))
+	}
+}
+
+/// The frames of a websocket connection, with heart-beats.
+struct Connection<S: Stream> {
+	client: Client<S>,
+	outgoing: Option<Duration>,
+	incoming: Option<Duration>,
+	last_sent: Instant,
+	last_received: Instant,
+}
+
+impl<S: Stream> Connection<S> {
+	fn new(client: Client<S>) -> Self {
+		Connection {
+			client: client,
+			outgoing: None,
+			incoming: None,
+			last_sent: Instant::now(),
+			last_received: Instant::now(),
+		}
+	}
+
+	fn send(&mut self, frame: &Frame) -> Result<(), StompError> {
+		self.client.send_message(&frame.to_message())?;
+		self.last_sent = Instant::now();
+		Ok(())
+	}
+
+	fn recv(&mut self) -> Result<Frame, StompError> {
+		loop {
+			let message = self.client.recv_message()?;
+			self.last_received = Instant::now();
+			match message {
+				OwnedMessage::Ping(data) => self.client.send_message(&OwnedMessage::Pong(data))?,
+				OwnedMessage::Close(_) => {
+					let _ = self.client.send_message(&OwnedMessage::Close(None));
+					return Err(StompError::Closed);
+				}
+				message => {
+					if let Some(frame) = Frame::from_message(&message)? {
+						return Ok(frame);
+					}
+				}
+			}
+		}
+	}
+
+	fn tick(&mut self) -> Result<(), StompError> {
+		match self.outgoing {
+			Some(interval) if self.last_sent.elapsed() >= interval => {
+				self.client.send_message(&OwnedMessage::Text("\n".to_string()))?;
+				self.last_sent = Instant::now();
+				Ok(())
+			}
+			_ => Ok(()),
+		}
+	}
+
+	fn peer_alive(&self) -> bool {
+		// a heart-beat may be late, but not by a whole interval
+		self.incoming.map_or(true, |interval| self.last_received.elapsed() < interval * 2)
+	}
+}
+
+/// How the broker wants messages of a subscription acknowledged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ack {
+	/// Messages count as acknowledged once they are sent.
+	Auto,
+	/// Acknowledging a message acknowledges the ones before it as well.
+	Client,
+	/// Every message is acknowledged on its own.
+	ClientIndividual,
+}
+
+impl Ack {
+	fn as_str(&self) -> &'static str {
+		match *self {
+			Ack::Auto => "auto",
+			Ack::Client => "client",
+			Ack::ClientIndividual => "client-individual",
+		}
+	}
+}
+
+/// A client connected to a STOMP broker.
+pub struct StompClient<S: Stream> {
+	connection: Connection<S>,
+	next_subscription: u64,
+}
+
+impl<S: Stream> StompClient<S> {
+	/// Send `connect`, see `Frame::connect`, over a connection that speaks the
+	/// STOMP subprotocol and wait for the broker's `CONNECTED` frame, which
+	/// is returned along with the client.
+	pub fn connect(client: Client<S>, connect: Frame) -> Result<(Self, Frame), StompError> {
+		if client.protocol() != Some(PROTOCOL) {
+			return Err(StompError::Protocol("the server did not accept the v12.stomp subprotocol"));
+		}
+		let mut connection = Connection::new(client);
+		connection.send(&connect)?;
+		let connected = connection.recv()?;
+		match connected.command {
+			Command::Connected => (),
+			Command::Error => return Err(StompError::Server(connected)),
+			_ => return Err(StompError::Protocol("expected a CONNECTED frame")),
+		}
+
+		let (outgoing, incoming) = HeartBeat::of(&connect).negotiate(&HeartBeat::of(&connected));
+		connection.outgoing = outgoing;
+		connection.incoming = incoming;
+		let client = StompClient {
+			connection: connection,
+			next_subscription: 0,
+		};
+		Ok((client, connected))
+	}
+
+	/// Subscribe to a destination, returns the id of the subscription.
+	pub fn subscribe(&mut self, destination: &str, ack: Ack) -> Result<String, StompError> {
+		let id = format!("sub-{}", self.next_subscription);
+		self.next_subscription += 1;
+		self.send_frame(&Frame::new(Command::Subscribe)
+			.header("id", id.clone())
+			.header("destination", destination)
+			.header("ack", ack.as_str()))?;
+		Ok(id)
+	}
+
+	/// End a subscription.
+	pub fn unsubscribe(&mut self, id: &str) -> Result<(), StompError> {
+		self.send_frame(&Frame::new(Command::Unsubscribe).header("id", id))
+	}
+
+	/// Send a message to a destination.
+	pub fn send(&mut self, destination: &str, content_type: &str, body: &[u8]) -> Result<(), StompError> {
+		self.send_frame(&Frame::new(Command::Send)
+			.header("destination", destination)
+			.header("content-type", content_type)
+			.body(body))
+	}
+
+	/// Acknowledge a `MESSAGE` frame.
+	pub fn ack(&mut self, message: &Frame) -> Result<(), StompError> {
+		self.acknowledge(Command::Ack, message)
+	}
+
+	/// Tell the broker a `MESSAGE` frame was not consumed.
+	pub fn nack(&mut self, message: &Frame) -> Result<(), StompError> {
+		self.acknowledge(Command::Nack, message)
+	}
+
+	fn acknowledge(&mut self, command: Command, message: &Frame) -> Result<(), StompError> {
+		let id = match message.get("ack") {
+			Some(id) => id.to_string(),
+			None => return Err(StompError::Protocol("the message has no ack header")),
+		};
+		self.send_frame(&Frame::new(command).header("id", id))
+	}
+
+	/// Send any frame.
+	pub fn send_frame(&mut self, frame: &Frame) -> Result<(), StompError> {
+		self.connection.send(frame)
+	}
+
+	/// Read the next frame from the broker, skipping heart-beats. An `ERROR`
+	/// frame is returned as `StompError::Server`.
+	pub fn recv(&mut self) -> Result<Frame, StompError> {
+		let frame = self.connection.recv()?;
+		match frame.command {
+			Command::Error => Err(StompError::Server(frame)),
+			_ => Ok(frame),
+		}
+	}
+
+	/// Send a heart-beat if one is due.
+	pub fn tick(&mut self) -> Result<(), StompError> {
+		self.connection.tick()
+	}
+
+	/// Whether the broker's heart-beats, or other frames, still arrive in time.
+	pub fn is_server_alive(&self) -> bool {
+		self.connection.peer_alive()
+	}
+
+	/// The websocket connection, to set timeouts on for example.
+	pub fn get_mut(&mut self) -> &mut Client<S> {
+		&mut self.connection.client
+	}
+
+	/// Disconnect gracefully: wait until the broker received every frame that
+	/// was sent and return the websocket connection.
+	pub fn disconnect(mut self) -> Result<Client<S>, StompError> {
+		self.send_frame(&Frame::new(Command::Disconnect).header("receipt", "disconnect"))?;
+		loop {
+			let frame = self.recv()?;
+			if frame.command == Command::Receipt && frame.get("receipt-id") == Some("disconnect") {
+				return Ok(self.connection.client);
+			}
+		}
+	}
+}
+
+/// The broker's side of a STOMP connection.
+///
+/// It does no more than the protocol needs to get going, every other frame
+/// is up to the user.
+pub struct StompServer<S: Stream> {
+	connection: Connection<S>,
+}
+
+impl<S: Stream> StompServer<S> {
+	/// Wait for the client's `CONNECT` or `STOMP` frame and answer it with
+	/// `CONNECTED`. Returns the client's frame along with the connection, to
+	/// check its login for example.
+	pub fn accept(client: Client<S>, heart_beat: HeartBeat) -> Result<(Self, Frame), StompError> {
+		let mut connection = Connection::new(client);
+		let connect = connection.recv()?;
+		match connect.command {
+			Command::Connect | Command::Stomp => (),
+			_ => return Err(StompError::Protocol("expected a CONNECT frame")),
+		}
+		let supported = connect.get("accept-version")
+		                       .map_or(false, |versions| versions.split(',').any(|v| v.trim() == "1.2"));
+		if !supported {
+			connection.send(&Frame::new(Command::Error)
+				.header("version", "1.2")
+				.header("message", "only STOMP 1.2 is supported"))?;
+			return Err(StompError::Protocol("the client does not speak STOMP 1.2"));
+		}
+
+		connection.send(&Frame::new(Command::Connected)
+			.header("version", "1.2")
+			.header("heart-beat", heart_beat.to_header()))?;
+		let (outgoing, incoming) = heart_beat.negotiate(&HeartBeat::of(&connect));
+		connection.outgoing = outgoing;
+		connection.incoming = incoming;
+		Ok((StompServer { connection: connection }, connect))
+	}
+
+	/// Send a frame to the client.
+	pub fn send(&mut self, frame: &Frame) -> Result<(), StompError> {
+		self.connection.send(frame)
+	}
+
+	/// Read the next frame from the client, skipping heart-beats.
+	pub fn recv(&mut self) -> Result<Frame, StompError> {
+		self.connection.recv()
+	}
+
+	/// Send a heart-beat if one is due.
+	pub fn tick(&mut self) -> Result<(), StompError> {
+		self.connection.tick()
+	}
+
+	/// Whether the client's heart-beats, or other frames, still arrive in time.
+	pub fn is_client_alive(&self) -> bool {
+		self.connection.peer_alive()
+	}
+
+	/// The websocket connection.
+	pub fn into_inner(self) -> Client<S> {
+		self.connection.client
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use server::sync::Server;
+
+	#[test]
+	fn frames_round_trip() {
+		let frame = Frame::new(Command::Send)
+			.header("destination", "/queue/a:b")
+			.header("note", "line\nbreak")
+			.body(&b"with\0nul"[..]);
+		let bytes = frame.to_bytes();
+		assert!(bytes.starts_with(b"SEND\ndestination:/queue/a\\cb\nnote:line\\nbreak\ncontent-length:8\n\n"));
+
+		let parsed = Frame::parse(&bytes).unwrap().unwrap();
+		assert_eq!(parsed.get("destination"), Some("/queue/a:b"));
+		assert_eq!(parsed.get("note"), Some("line\nbreak"));
+		assert_eq!(parsed.body, b"with\0nul".to_vec());
+
+		assert_eq!(Frame::parse(b"\r\n").unwrap(), None);
+		let connected = Frame::parse(b"\nCONNECTED\r\nversion:1.2\r\nheart-beat:0,500\r\n\r\n\0\n").unwrap().unwrap();
+		assert_eq!(HeartBeat::of(&connected), HeartBeat { send: 0, receive: 500 });
+		assert!(Frame::parse(b"SEND\ndestination:x\n\nno nul").is_err());
+	}
+
+	#[test]
+	fn heart_beats_use_the_slower_side() {
+		let client = HeartBeat { send: 1000, receive: 0 };
+		let server = HeartBeat { send: 2000, receive: 3000 };
+		assert_eq!(client.negotiate(&server), (Some(Duration::from_millis(3000)), None));
+		assert_eq!(server.negotiate(&client), (None, Some(Duration::from_millis(3000))));
+	}
+
+	#[test]
+	fn client_talks_to_server() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+
+		let broker = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap().negotiate_protocol(&[PROTOCOL]).ok().unwrap();
+			let (mut broker, connect) = StompServer::accept(upgrade.accept().unwrap(), HeartBeat::default()).unwrap();
+			assert_eq!(connect.get("host"), Some("localhost"));
+
+			let subscribe = broker.recv().unwrap();
+			assert_eq!(subscribe.command, Command::Subscribe);
+			broker.send(&Frame::new(Command::Message)
+				.header("subscription", subscribe.get("id").unwrap())
+				.header("message-id", "1")
+				.header("destination", subscribe.get("destination").unwrap())
+				.header("ack", "a1")
+				.body("hello")).unwrap();
+
+			let ack = broker.recv().unwrap();
+			assert_eq!((ack.command, ack.get("id")), (Command::Ack, Some("a1")));
+			let disconnect = broker.recv().unwrap();
+			assert_eq!(disconnect.command, Command::Disconnect);
+			broker.send(&disconnect.receipt().unwrap()).unwrap();
+		});
+
+		let client = builder(&url).unwrap().connect_insecure().unwrap();
+		let (mut stomp, connected) = StompClient::connect(client, Frame::connect("localhost")).unwrap();
+		assert_eq!(connected.get("version"), Some("1.2"));
+
+		stomp.subscribe("/topic/telemetry", Ack::Client).unwrap();
+		let message = stomp.recv().unwrap();
+		assert_eq!(message.command, Command::Message);
+		assert_eq!(message.body, b"hello".to_vec());
+		stomp.ack(&message).unwrap();
+		stomp.disconnect().unwrap();
+		broker.join().unwrap();
+	}
+}
@@ -112,6 +112,8 @@ pub mod sync {
 	pub use super::ReadWritePair;
 	use std::io::{self, Read, Write};
 	use std::ops::Deref;
+	use std::sync::{Arc, Mutex, MutexGuard};
+	use std::time::Duration;
 	pub use std::net::TcpStream;
 	pub use std::net::Shutdown;
 	#[cfg(feature = "sync-ssl")]
@@ -134,7 +136,8 @@ pub mod sync {
 	/// `TcpStream` is an example. This trait marks this ability so one can split
 	/// up the client into two parts.
 	///
-	/// Notice however that this is not possible to do with SSL.
+	/// Streams that can't be read and written independently, like TLS streams,
+	/// are split into two `SharedStream`s.
 	pub trait Splittable {
 		/// The reading component of this type
 		type Reader: Read;
@@ -167,6 +170,131 @@ pub mod sync {
 		}
 	}
 
+	#[cfg(feature = "sync-ssl")]
+	impl Splittable for TlsStream<TcpStream> {
+		type Reader = SharedStream<TlsStream<TcpStream>>;
+		type Writer = SharedStream<TlsStream<TcpStream>>;
+
+		fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+			SharedStream::split(self)
+		}
+	}
+
+	/// One half of a stream that is shared between a reading and a writing
+	/// thread, such as a TLS session that has to be used as a whole.
+	///
+	/// Both halves lock the stream to use it. A read waits for the peer's data
+	/// on the socket underneath before taking the lock, unless the stream
+	/// already holds some, so a writer is not stuck behind a reader waiting for
+	/// the peer. With the lock held it only reads what already arrived, if that
+	/// is not enough for any data, e.g. half a record, it lets go and waits
+	/// again. The socket is left in the mode it is in, read timeouts set on it
+	/// still apply to the reading half.
+	pub struct SharedStream<S> {
+		stream: Arc<Mutex<S>>,
+		tcp: TcpStream,
+	}
+
+	impl<S> SharedStream<S>
+	where
+		S: Read + Write + AsTcpStream + BufferedRead,
+	{
+		/// Split a stream into a reading and a writing half.
+		pub fn split(stream: S) -> io::Result<(Self, Self)> {
+			let reader_tcp = stream.as_tcp().try_clone()?;
+			let writer_tcp = stream.as_tcp().try_clone()?;
+			let stream = Arc::new(Mutex::new(stream));
+			Ok((
+				SharedStream {
+					stream: stream.clone(),
+					tcp: reader_tcp,
+				},
+				SharedStream {
+					stream: stream,
+					tcp: writer_tcp,
+				},
+			))
+		}
+
+		fn lock(&self) -> MutexGuard<S> {
+			self.stream.lock().unwrap_or_else(|e| e.into_inner())
+		}
+	}
+
+	impl<S> Read for SharedStream<S>
+	where
+		S: Read + Write + AsTcpStream + BufferedRead,
+	{
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			loop {
+				if self.lock().buffered_read_size()? == 0 {
+					// an end of file is left for the stream to report
+					self.tcp.peek(&mut [0])?;
+				}
+
+				// what arrived may not hold any data yet, like part of a record
+				// or a session ticket, so don't wait for more with the lock held
+				let read = {
+					let mut stream = self.lock();
+					let timeout = self.tcp.read_timeout()?;
+					self.tcp.set_read_timeout(Some(Duration::from_millis(1)))?;
+					let read = stream.read(buf);
+					self.tcp.set_read_timeout(timeout)?;
+					read
+				};
+				match read {
+					Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+					Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
+					read => return read,
+				}
+			}
+		}
+	}
+
+	impl<S> Write for SharedStream<S>
+	where
+		S: Read + Write + AsTcpStream + BufferedRead,
+	{
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.lock().write(buf)
+		}
+
+		fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+			// keep the lock for all of it so the other half can't get in between
+			self.lock().write_all(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			self.lock().flush()
+		}
+	}
+
+	impl<S> AsTcpStream for SharedStream<S> {
+		fn as_tcp(&self) -> &TcpStream {
+			&self.tcp
+		}
+	}
+
+	/// A stream that may hold data it has already taken off the socket, like a
+	/// TLS session with decrypted bytes waiting to be read.
+	pub trait BufferedRead {
+		/// How many bytes can be read without waiting for the socket.
+		fn buffered_read_size(&self) -> io::Result<usize>;
+	}
+
+	impl BufferedRead for TcpStream {
+		fn buffered_read_size(&self) -> io::Result<usize> {
+			Ok(0)
+		}
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	impl BufferedRead for TlsStream<TcpStream> {
+		fn buffered_read_size(&self) -> io::Result<usize> {
+			TlsStream::buffered_read_size(self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+		}
+	}
+
 	/// The ability access a borrow to an underlying TcpStream,
 	/// so one can set options on the stream such as `nonblocking`.
 	pub trait AsTcpStream {
@@ -196,3 +324,159 @@ pub mod sync {
 		}
 	}
 }
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+	use std::io::{Read, Write};
+	use std::net::{TcpListener, TcpStream};
+	use std::thread;
+	use super::sync::SharedStream;
+
+	#[test]
+	fn shared_stream_writes_while_reading() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (mut peer, _) = listener.accept().unwrap();
+		let (mut reader, mut writer) = SharedStream::split(stream).unwrap();
+
+		let reading = thread::spawn(move || {
+			let mut buf = [0; 4];
+			reader.read_exact(&mut buf).unwrap();
+			buf
+		});
+
+		// the reader is waiting for data, this must not wait for it
+		writer.write_all(b"ping").unwrap();
+		let mut buf = [0; 4];
+		peer.read_exact(&mut buf).unwrap();
+		assert_eq!(&buf, b"ping");
+		peer.write_all(b"pong").unwrap();
+
+		assert_eq!(&reading.join().unwrap(), b"pong");
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn tls_halves_write_while_reading() {
+		use std::sync::mpsc::channel;
+		use std::time::Duration;
+		use native_tls::{Certificate, Pkcs12, TlsAcceptor, TlsConnector};
+		use super::sync::Splittable;
+
+		let identity = Pkcs12::from_der(include_bytes!("../fixtures/localhost.p12"), "websocket").unwrap();
+		let acceptor = TlsAcceptor::builder(identity).unwrap().build().unwrap();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		let peer = thread::spawn(move || {
+			let mut peer = acceptor.accept(listener.accept().unwrap().0).unwrap();
+			let mut buf = [0; 4];
+			peer.read_exact(&mut buf).unwrap();
+			peer.write_all(b"pong").unwrap();
+			peer.flush().unwrap();
+			buf
+		});
+
+		let root = Certificate::from_der(include_bytes!("../fixtures/localhost.der")).unwrap();
+		let mut connector = TlsConnector::builder().unwrap();
+		connector.add_root_certificate(root).unwrap();
+		let stream = connector.build().unwrap().connect("localhost", TcpStream::connect(addr).unwrap()).unwrap();
+		let (mut reader, mut writer) = stream.split().unwrap();
+
+		let (read_tx, read) = channel();
+		thread::spawn(move || {
+			let mut buf = [0; 4];
+			reader.read_exact(&mut buf).unwrap();
+			read_tx.send(buf).unwrap();
+		});
+
+		// the reader is waiting for data, this must not wait for it
+		writer.write_all(b"ping").unwrap();
+		writer.flush().unwrap();
+		assert_eq!(&peer.join().unwrap(), b"ping");
+		assert_eq!(&read.recv_timeout(Duration::from_secs(10)).unwrap(), b"pong");
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn tls_reader_lets_go_of_a_partial_record() {
+		use std::io;
+		use std::sync::Arc;
+		use std::sync::atomic::{AtomicBool, Ordering};
+		use std::sync::mpsc::{channel, Receiver};
+		use std::time::Duration;
+		use native_tls::{Certificate, Pkcs12, TlsAcceptor, TlsConnector};
+		use super::sync::Splittable;
+
+		// once `stall` is set, sends the header of the next record and waits
+		// for `resume` before sending the rest
+		struct Stalling(TcpStream, Arc<AtomicBool>, Receiver<()>);
+
+		impl Read for Stalling {
+			fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+				self.0.read(buf)
+			}
+		}
+
+		impl Write for Stalling {
+			fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+				if self.1.swap(false, Ordering::SeqCst) {
+					self.0.write_all(&buf[..5])?;
+					self.0.flush()?;
+					self.2.recv().unwrap();
+					self.0.write_all(&buf[5..])?;
+					return Ok(buf.len());
+				}
+				self.0.write(buf)
+			}
+
+			fn flush(&mut self) -> io::Result<()> {
+				self.0.flush()
+			}
+		}
+
+		let identity = Pkcs12::from_der(include_bytes!("../fixtures/localhost.p12"), "websocket").unwrap();
+		let acceptor = TlsAcceptor::builder(identity).unwrap().build().unwrap();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let (resume_tx, resume) = channel();
+
+		thread::spawn(move || {
+			let stall = Arc::new(AtomicBool::new(false));
+			let stream = Stalling(listener.accept().unwrap().0, stall.clone(), resume);
+			let mut peer = acceptor.accept(stream).ok().unwrap();
+			stall.store(true, Ordering::SeqCst);
+			peer.write_all(b"pong").unwrap();
+			peer.flush().unwrap();
+			let mut buf = [0; 4];
+			peer.read_exact(&mut buf).unwrap();
+		});
+
+		let root = Certificate::from_der(include_bytes!("../fixtures/localhost.der")).unwrap();
+		let mut connector = TlsConnector::builder().unwrap();
+		connector.add_root_certificate(root).unwrap();
+		let stream = connector.build().unwrap().connect("localhost", TcpStream::connect(addr).unwrap()).unwrap();
+		let (mut reader, mut writer) = stream.split().unwrap();
+
+		let (read_tx, read) = channel();
+		thread::spawn(move || {
+			let mut buf = [0; 4];
+			reader.read_exact(&mut buf).unwrap();
+			read_tx.send(buf).unwrap();
+		});
+
+		// the reader has the start of a record and waits for the rest, that
+		// must not keep the writer out
+		thread::sleep(Duration::from_millis(200));
+		let (written_tx, written) = channel();
+		thread::spawn(move || {
+			writer.write_all(b"ping").unwrap();
+			writer.flush().unwrap();
+			written_tx.send(()).unwrap();
+		});
+		written.recv_timeout(Duration::from_secs(10)).unwrap();
+
+		resume_tx.send(()).unwrap();
+		assert_eq!(&read.recv_timeout(Duration::from_secs(10)).unwrap(), b"pong");
+	}
+}
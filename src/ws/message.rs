@@ -2,9 +2,11 @@
 //!
 //! See the `ws` module documentation for more information.
 
-use std::io::Write;
+use std::io::{Cursor, Write};
 use ws::dataframe::DataFrame as DataFrameable;
 use result::WebSocketResult;
+use dataframe::DataFrame;
+use message::OwnedMessage;
 
 /// A trait for WebSocket messages
 pub trait Message: Sized {
@@ -16,4 +18,19 @@ pub trait Message: Sized {
 
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D: DataFrameable>(frames: Vec<D>) -> WebSocketResult<Self>;
+
+	/// Copies this message into an `OwnedMessage`.
+	///
+	/// By default the message is serialized and parsed back, implementors that
+	/// can convert directly should override this.
+	fn to_owned_message(&self) -> WebSocketResult<OwnedMessage> {
+		let mut bytes = Vec::with_capacity(self.message_size(false));
+		self.serialize(&mut bytes, false)?;
+		let mut reader = Cursor::new(bytes);
+		let mut frames = Vec::new();
+		while (reader.position() as usize) < reader.get_ref().len() {
+			frames.push(DataFrame::read_dataframe(&mut reader, false)?);
+		}
+		OwnedMessage::from_dataframes(frames)
+	}
 }